@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::errors;
+use crate::mod_info::hidden_files::is_hidden;
+use crate::mod_info::instance::Instance;
+
+/// The name the overwrite directory is given in the conflict map
+pub const OVERWRITE_NAME: &str = "Overwrite";
+
+/// The directory inside a mod whose contents are deployed to the game directory instead of the data directory
+pub const ROOT_DIR: &str = "Root";
+
+/// Where in the game directory a file is deployed to
+///
+/// Files in different namespaces never conflict with each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Namespace {
+    /// The game's data directory
+    #[default]
+    Data,
+    /// The game's install directory, for files in a mod's `Root` directory
+    Root,
+}
+
+/// The key a file is compared by
+type FileKey = (Namespace, String);
+
+/* ========================================= */
+/* Conflict State                            */
+/* ========================================= */
+
+/// How a mod fares against the mods it shares files with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictState {
+    /// The mod doesn't share any files with other mods
+    NoConflicts,
+    /// The mod wins every file it shares with other mods
+    Winning,
+    /// The mod loses every file it shares with other mods
+    Losing,
+    /// The mod wins some of the files it shares with other mods and loses others
+    Mixed,
+    /// Every file the mod provides is overwritten by other mods
+    Redundant,
+}
+
+impl ConflictState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictState::NoConflicts => "no conflicts",
+            ConflictState::Winning => "winning",
+            ConflictState::Losing => "losing",
+            ConflictState::Mixed => "mixed",
+            ConflictState::Redundant => "redundant",
+        }
+    }
+}
+
+/// The file that is used for a path in the game directory
+pub struct WinningFile<'a> {
+    pub mod_name: &'a str,
+    pub namespace: Namespace,
+    /// The absolute path to the file
+    pub source: PathBuf,
+    /// The path of the file relative to its namespace's directory
+    pub path: PathBuf,
+}
+
+/// The files a mod shares with other mods, keyed by the name of the other mod
+#[derive(Default)]
+pub struct ModConflicts {
+    /// Files this mod overwrites in lower priority mods
+    pub overwrites: BTreeMap<String, Vec<String>>,
+    /// Files of this mod that are overwritten by higher priority mods
+    pub overwritten_by: BTreeMap<String, Vec<String>>,
+}
+
+/* ========================================= */
+/* Conflict Map                              */
+/* ========================================= */
+
+struct ModFiles {
+    name: String,
+    path: PathBuf,
+    /// Map of file key to the path relative to the mod directory
+    files: BTreeMap<FileKey, PathBuf>,
+}
+
+/// A map of every path provided by a set of mods to the mods that provide it
+pub struct ConflictMap {
+    /// The mods in the map, lowest priority first
+    mods: Vec<ModFiles>,
+    /// Map of file key to the indexes of the mods that provide it, lowest priority first
+    files: HashMap<FileKey, Vec<usize>>,
+}
+
+impl ConflictMap {
+    pub fn new() -> Self {
+        Self { mods: Vec::new(), files: HashMap::new() }
+    }
+
+    /// Build the conflict map for the enabled mods in the instance's active profile
+    ///
+    /// The overwrite directory is included as the highest priority mod.
+    pub fn for_instance(instance: &Instance) -> errors::Result<Self> {
+        let mut map = Self::new();
+
+        for entry in instance.mod_list().enabled_mods() {
+            map.add_mod(entry.name(), &instance.mod_path(entry.name()))?;
+        }
+        map.add_mod(OVERWRITE_NAME, &instance.overwrite_path())?;
+
+        Ok(map)
+    }
+
+    /// Add a mod to the map with a higher priority than every mod already in the map
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the mod
+    /// * `path`: The directory containing the mod's files, a missing directory is treated as empty. Hidden
+    ///   files are skipped
+    ///
+    /// returns: Result<(), Error>
+    pub fn add_mod(&mut self, name: &str, path: &Path) -> errors::Result<()> {
+        let mut files = Vec::new();
+
+        if path.exists() {
+            for entry in WalkDir::new(path).min_depth(1) {
+                let entry = entry.map_err(io::Error::from)?;
+                if entry.file_type().is_dir() || is_hidden(entry.path()) {
+                    continue;
+                }
+
+                files.push(entry.path().strip_prefix(path).unwrap().to_path_buf());
+            }
+        }
+
+        self.add_mod_files(name, path, files);
+        Ok(())
+    }
+
+    fn add_mod_files(&mut self, name: &str, path: &Path, files: Vec<PathBuf>) {
+        let index = self.mods.len();
+        let mut mod_files = ModFiles { name: name.to_string(), path: path.to_path_buf(), files: BTreeMap::new() };
+
+        for file in files {
+            let key = file_key(&file);
+            // Paths that only differ in case are the same file to the game, so the mod only provides it once
+            let indexes = self.files.entry(key.clone()).or_default();
+            if indexes.last() != Some(&index) {
+                indexes.push(index);
+            }
+            mod_files.files.insert(key, file);
+        }
+
+        self.mods.push(mod_files);
+    }
+
+    /// Get the names of the mods in the map, lowest priority first
+    pub fn mods(&self) -> impl Iterator<Item = &str> {
+        self.mods.iter().map(|mod_files| mod_files.name.as_str())
+    }
+
+    /// Get the names of the mods providing a path, lowest priority first
+    pub fn providers(&self, namespace: Namespace, path: &Path) -> Vec<&str> {
+        self.files.get(&(namespace, fold_path(path)))
+            .map(|indexes| indexes.iter().map(|index| self.mods[*index].name.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get the name of the mod whose copy of a path is used
+    pub fn winner(&self, namespace: Namespace, path: &Path) -> Option<&str> {
+        self.files.get(&(namespace, fold_path(path)))
+            .and_then(|indexes| indexes.last())
+            .map(|index| self.mods[*index].name.as_str())
+    }
+
+    /// Get the file that wins every path, sorted by namespace then path
+    pub fn winning_files(&self) -> Vec<WinningFile<'_>> {
+        let mut winners: Vec<(&FileKey, &ModFiles)> = self.files.iter()
+            .map(|(key, indexes)| (key, &self.mods[*indexes.last().unwrap()]))
+            .collect();
+        winners.sort_by(|a, b| a.0.cmp(b.0));
+
+        winners.into_iter()
+            .map(|(key, mod_files)| {
+                let relative = &mod_files.files[key];
+                let path = match key.0 {
+                    Namespace::Data => relative.clone(),
+                    Namespace::Root => relative.components().skip(1).collect(),
+                };
+
+                WinningFile { mod_name: &mod_files.name, namespace: key.0, source: mod_files.path.join(relative), path }
+            })
+            .collect()
+    }
+
+    /// Classify how a mod fares against the mods it shares files with
+    ///
+    /// returns: Option<ConflictState> The state of the mod, or None if the mod isn't in the map
+    pub fn state(&self, mod_name: &str) -> Option<ConflictState> {
+        let index = self.index_of(mod_name)?;
+        let mod_files = &self.mods[index];
+
+        let mut wins = 0;
+        let mut losses = 0;
+        for key in mod_files.files.keys() {
+            let providers = &self.files[key];
+            if providers.len() < 2 {
+                continue;
+            }
+
+            if *providers.last().unwrap() == index {
+                wins += 1;
+            } else {
+                losses += 1;
+            }
+        }
+
+        Some(match (wins, losses) {
+            (0, 0) => ConflictState::NoConflicts,
+            (_, 0) => ConflictState::Winning,
+            (0, losses) if losses == mod_files.files.len() => ConflictState::Redundant,
+            (0, _) => ConflictState::Losing,
+            _ => ConflictState::Mixed,
+        })
+    }
+
+    /// Get the files a mod shares with other mods
+    ///
+    /// returns: Option<ModConflicts> The conflicts of the mod, or None if the mod isn't in the map
+    pub fn conflicts(&self, mod_name: &str) -> Option<ModConflicts> {
+        let index = self.index_of(mod_name)?;
+        let mut conflicts = ModConflicts::default();
+
+        for (key, file) in &self.mods[index].files {
+            let file_name = file.to_string_lossy().to_string();
+            for other in &self.files[key] {
+                let other_name = &self.mods[*other].name;
+                if *other < index {
+                    conflicts.overwrites.entry(other_name.clone()).or_default().push(file_name.clone());
+                } else if *other > index {
+                    conflicts.overwritten_by.entry(other_name.clone()).or_default().push(file_name.clone());
+                }
+            }
+        }
+
+        Some(conflicts)
+    }
+
+    fn index_of(&self, mod_name: &str) -> Option<usize> {
+        self.mods.iter().position(|mod_files| mod_files.name == mod_name)
+    }
+}
+
+impl Default for ConflictMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the key of a file from its path relative to the mod directory
+fn file_key(relative: &Path) -> FileKey {
+    let mut components = relative.components();
+    let in_root = components.next()
+        .is_some_and(|first| first.as_os_str().eq_ignore_ascii_case(ROOT_DIR))
+        && components.next().is_some();
+
+    if in_root {
+        (Namespace::Root, fold_path(&relative.components().skip(1).collect::<PathBuf>()))
+    } else {
+        (Namespace::Data, fold_path(relative))
+    }
+}
+
+/// Fold a path into the form used to compare it, games treat paths case-insensitively
+pub fn fold_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::conflicts::{ConflictMap, ConflictState, Namespace};
+
+    fn add(map: &mut ConflictMap, name: &str, files: &[&str]) {
+        map.add_mod_files(name, Path::new(name), files.iter().map(PathBuf::from).collect());
+    }
+
+    #[test]
+    fn conflict_states() {
+        let mut map = ConflictMap::new();
+        add(&mut map, "Base", &["textures/a.dds", "textures/b.dds", "base.esp"]);
+        add(&mut map, "Redundant", &["Textures/A.dds"]);
+        add(&mut map, "Mixed", &["textures/a.dds", "textures/b.dds"]);
+        add(&mut map, "Winner", &["TEXTURES/b.dds", "meshes/c.nif"]);
+        add(&mut map, "Alone", &["sound/d.wav"]);
+        add(&mut map, "Proxy", &["root/d3d11.dll", "d3d11.dll"]);
+        add(&mut map, "Enb", &["Root/D3D11.dll"]);
+
+        assert_eq!(map.state("Base"), Some(ConflictState::Losing));
+        assert_eq!(map.state("Redundant"), Some(ConflictState::Redundant));
+        assert_eq!(map.state("Mixed"), Some(ConflictState::Mixed));
+        assert_eq!(map.state("Winner"), Some(ConflictState::Winning));
+        assert_eq!(map.state("Alone"), Some(ConflictState::NoConflicts));
+        assert_eq!(map.state("Missing"), None);
+        assert_eq!(map.state("Proxy"), Some(ConflictState::Losing));
+
+        assert_eq!(map.winner(Namespace::Data, Path::new("textures/A.DDS")), Some("Mixed"));
+        assert_eq!(map.providers(Namespace::Data, Path::new("textures/b.dds")), vec!["Base", "Mixed", "Winner"]);
+        assert_eq!(map.providers(Namespace::Root, Path::new("d3d11.dll")), vec!["Proxy", "Enb"]);
+        assert_eq!(map.providers(Namespace::Data, Path::new("d3d11.dll")), vec!["Proxy"]);
+
+        let conflicts = map.conflicts("Mixed").unwrap();
+        assert_eq!(conflicts.overwrites["Base"].len(), 2);
+        assert_eq!(conflicts.overwrites["Redundant"], vec!["textures/a.dds"]);
+        assert_eq!(conflicts.overwritten_by["Winner"], vec!["textures/b.dds"]);
+    }
+
+    #[test]
+    fn paths_differing_in_case() {
+        let mut map = ConflictMap::new();
+        add(&mut map, "Base", &["textures/a.dds"]);
+        add(&mut map, "Mixed", &["Textures/a.dds", "textures/A.dds", "b.esp", "B.ESP"]);
+
+        assert_eq!(map.providers(Namespace::Data, Path::new("textures/a.dds")), vec!["Base", "Mixed"]);
+        assert_eq!(map.providers(Namespace::Data, Path::new("b.esp")), vec!["Mixed"]);
+        assert_eq!(map.state("Mixed"), Some(ConflictState::Winning));
+
+        let conflicts = map.conflicts("Mixed").unwrap();
+        assert_eq!(conflicts.overwrites.keys().collect::<Vec<_>>(), vec!["Base"]);
+        assert!(conflicts.overwritten_by.is_empty());
+    }
+}
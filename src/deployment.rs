@@ -0,0 +1,694 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, symlink};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use error_chain::bail;
+
+use crate::conflicts::{ConflictMap, Namespace};
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::load_order::morrowind::{OPENMW_CFG, OpenMwConfig};
+use crate::mod_info::game::get_game;
+use crate::mod_info::instance::Instance;
+use crate::util::{hash_file, move_file, resolve_case_insensitive};
+
+const MANIFEST_FILE: &str = "deployment.toml";
+const BACKUPS_DIR: &str = "backups";
+
+/// How a game is given access to the files of the enabled mods
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeployMethod {
+    /// The winning files are linked into the game directory
+    Link,
+    /// The mod directories are added to the data paths in `openmw.cfg`, which OpenMW layers in its virtual file
+    /// system, so nothing is linked
+    DataPaths,
+}
+
+/* ========================================= */
+/* Manifest                                  */
+/* ========================================= */
+
+/// How a file was linked into the game directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkKind {
+    Hardlink,
+    Symlink,
+}
+
+/// A file linked into the game directory by deployment
+#[derive(Serialize, Deserialize)]
+pub struct DeployedFile {
+    /// The path of the link, relative to the game directory
+    pub target: PathBuf,
+    /// The file the link points to
+    pub source: PathBuf,
+    /// The mod the file was deployed from
+    pub mod_name: String,
+    #[serde(default)]
+    pub namespace: Namespace,
+    pub link: LinkKind,
+    /// The identity of a hard link, so it can still be recognised once its source is removed
+    #[serde(default)]
+    pub file_id: Option<FileId>,
+    /// The SHA-256 hash of the game file that was moved to the backup store to make way for the link
+    #[serde(default)]
+    pub backup_hash: Option<String>,
+    /// Where the symlink removed to make way for the link pointed, so purging can recreate it
+    #[serde(default)]
+    pub backup_link: Option<PathBuf>,
+}
+
+/// What identifies a hard linked file, inodes are reused so the size and modification time are kept too
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileId {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileId {
+    fn new(metadata: &fs::Metadata) -> Self {
+        FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// A record of everything deployment has changed in the game directory
+#[derive(Default, Serialize, Deserialize)]
+pub struct DeploymentManifest {
+    #[serde(default)]
+    files: Vec<DeployedFile>,
+    /// Directories created by deployment, relative to the game directory
+    #[serde(default)]
+    directories: Vec<PathBuf>,
+}
+
+impl DeploymentManifest {
+    /// Load the deployment manifest of an instance, an instance that hasn't been deployed has an empty manifest
+    pub fn load(instance: &Instance) -> errors::Result<Self> {
+        let content = match fs::read_to_string(manifest_path(instance)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into())
+        };
+
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub fn save(&self, instance: &Instance) -> errors::Result<()> {
+        fs::write(manifest_path(instance), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn files(&self) -> &[DeployedFile] {
+        &self.files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.directories.is_empty()
+    }
+}
+
+fn manifest_path(instance: &Instance) -> PathBuf {
+    instance.base_path().join(MANIFEST_FILE)
+}
+
+/// Get where the game file replaced by a deployed file is backed up to
+fn backup_path(instance: &Instance, target: &Path) -> PathBuf {
+    instance.base_path().join(BACKUPS_DIR).join(target)
+}
+
+/* ========================================= */
+/* Deploy                                    */
+/* ========================================= */
+
+/// Link the winning file of every path in the active profile into the game directory
+///
+/// Files in a mod's `Root` directory are linked into the game's install directory, everything else is
+/// linked into the game's data directory. Game files that are in the way are moved to the instance's
+/// backup store so purging can put them back. Anything previously deployed is purged first.
+///
+/// # Arguments
+///
+/// * `instance`: The instance to deploy
+///
+/// returns: Result<DeploymentManifest, Error> The manifest of the new deployment
+pub fn deploy(instance: &Instance) -> errors::Result<DeploymentManifest> {
+    purge(instance)?;
+
+    if get_game(instance.game())?.deploy_method() == DeployMethod::DataPaths {
+        set_data_paths(instance, true)?;
+        return Ok(DeploymentManifest::default())
+    }
+
+    let game_path = instance.game_path();
+    let data_path = instance.data_path()?;
+    let conflict_map = ConflictMap::for_instance(instance)?;
+    let mut manifest = DeploymentManifest::default();
+
+    for file in conflict_map.winning_files() {
+        let base_path = match file.namespace {
+            Namespace::Data => data_path.as_path(),
+            Namespace::Root => game_path,
+        };
+        let target = resolve_case_insensitive(base_path, &file.path);
+        create_parent_dirs(game_path, &target, &mut manifest.directories)?;
+
+        let relative_target = target.strip_prefix(game_path).unwrap().to_path_buf();
+        let (backup_hash, backup_link) = match fs::symlink_metadata(&target) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&target)?;
+                fs::remove_file(&target)?;
+                (None, Some(link))
+            }
+            Ok(_) => (Some(back_up_file(instance, game_path, &relative_target)?), None),
+            Err(_) => (None, None)
+        };
+        let backed_up = backup_hash.is_some() || backup_link.is_some();
+
+        let link = link_file(&file.source, &target)?;
+        manifest.files.push(DeployedFile {
+            target: relative_target,
+            source: file.source,
+            mod_name: file.mod_name.to_string(),
+            namespace: file.namespace,
+            link,
+            file_id: link_file_id(link, &target)?,
+            backup_hash,
+            backup_link,
+        });
+
+        // Save as we go so a failed deployment can still be purged, and backups are never lost track of
+        if backed_up || manifest.files.len() % 500 == 0 {
+            manifest.save(instance)?;
+        }
+    }
+
+    manifest.save(instance)?;
+    Ok(manifest)
+}
+
+/// Remove everything deployment has linked into the game directory
+///
+/// Files that have been replaced since they were deployed are left alone, links whose source has been
+/// removed from the mod are still removed as long as they're the links that were deployed. Backed up game
+/// files are restored and checked against the hash they were backed up with.
+///
+/// If something else has taken the place of a backed up game file, the backup is kept and stays in the
+/// manifest, and purging fails once everything else is purged, so the game file is never lost.
+pub fn purge(instance: &Instance) -> errors::Result<()> {
+    if get_game(instance.game())?.deploy_method() == DeployMethod::DataPaths {
+        return set_data_paths(instance, false)
+    }
+
+    let mut manifest = DeploymentManifest::load(instance)?;
+    if manifest.is_empty() {
+        return Ok(())
+    }
+
+    let game_path = instance.game_path();
+    let mut kept = Vec::new();
+    for file in manifest.files {
+        if matches!(check_file(game_path, &file), None | Some(IssueKind::SourceMissing | IssueKind::Outdated)) {
+            fs::remove_file(game_path.join(&file.target))?;
+        }
+        if !restore_backup(instance, game_path, &file)? {
+            kept.push(file);
+        }
+    }
+
+    // Remove the deepest directories first so their parents are empty by the time they're reached
+    let mut directories: Vec<&PathBuf> = manifest.directories.iter().collect();
+    directories.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in directories {
+        let dir = game_path.join(dir);
+        if fs::read_dir(&dir).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+            fs::remove_dir(&dir)?;
+        }
+    }
+
+    if !kept.is_empty() {
+        let targets: Vec<String> = kept.iter().map(|file| file.target.display().to_string()).collect();
+        manifest.files = kept;
+        manifest.save(instance)?;
+        bail!(ErrorKind::BackupConflict(targets.join(", ")))
+    }
+
+    fs::remove_file(manifest_path(instance))?;
+    Ok(())
+}
+
+/// Replace the data paths in `openmw.cfg` that point into the instance with the enabled mods
+///
+/// Data paths the user added themselves are kept with a lower priority than the mods. Files hidden in a mod and
+/// the mod's `Root` directory can't be left out of a data path.
+///
+/// # Arguments
+///
+/// * `instance`: The instance to deploy
+/// * `add_mods`: Whether to add the enabled mods and the overwrite directory, if false they're only removed
+///
+/// returns: Result<(), Error>
+fn set_data_paths(instance: &Instance, add_mods: bool) -> errors::Result<()> {
+    let Some(app_data_path) = instance.app_data_path() else {
+        bail!(ErrorKind::NoAppDataPath)
+    };
+    let config_path = app_data_path.join(OPENMW_CFG);
+    let mut config = OpenMwConfig::load(&config_path)?;
+
+    let mut paths = Vec::new();
+    if add_mods {
+        paths.extend(instance.mod_list().enabled_mods().map(|entry| instance.mod_path(entry.name())));
+        paths.push(instance.overwrite_path());
+    }
+
+    let mods_path = instance.mods_path();
+    let overwrite_path = instance.overwrite_path();
+    config.set_data_paths(|path| !path.starts_with(&mods_path) && !path.starts_with(&overwrite_path), &paths);
+    config.save(&config_path)
+}
+
+fn create_parent_dirs(game_path: &Path, target: &Path, created: &mut Vec<PathBuf>) -> errors::Result<()> {
+    let parent = target.parent().unwrap();
+    let mut missing = Vec::new();
+    for dir in parent.ancestors() {
+        if dir.exists() {
+            break;
+        }
+        missing.push(dir);
+    }
+
+    for dir in missing.into_iter().rev() {
+        fs::create_dir(dir)?;
+        // Repairing can recreate a directory the deployment already created
+        let dir = dir.strip_prefix(game_path).unwrap().to_path_buf();
+        if !created.contains(&dir) {
+            created.push(dir);
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a game file into the backup store
+///
+/// returns: Result<String, Error> The SHA-256 hash of the file
+fn back_up_file(instance: &Instance, game_path: &Path, target: &Path) -> errors::Result<String> {
+    let backup = backup_path(instance, target);
+    fs::create_dir_all(backup.parent().unwrap())?;
+
+    let hash = hash_file(&game_path.join(target))?;
+    move_file(&game_path.join(target), &backup)?;
+
+    Ok(hash)
+}
+
+/// Move a deployed file's backup back into the game directory, checking it matches the hash it was
+/// backed up with, or recreate the symlink it replaced
+///
+/// If the game has put the same file back since deployment, the backup is discarded instead. If anything
+/// else has taken the file's place, the backup is kept in the backup store.
+///
+/// returns: Result<bool, Error> False if the backup was kept because something else is in its place
+fn restore_backup(instance: &Instance, game_path: &Path, file: &DeployedFile) -> errors::Result<bool> {
+    let target = game_path.join(&file.target);
+    if let Some(link) = &file.backup_link {
+        if fs::symlink_metadata(&target).is_err() {
+            symlink(link, &target)?;
+        }
+        return Ok(true)
+    }
+
+    let Some(hash) = &file.backup_hash else {
+        return Ok(true)
+    };
+
+    let backup = backup_path(instance, &file.target);
+    if !backup.exists() {
+        // Already restored by an earlier purge that failed part way through
+        return Ok(true)
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(&target) {
+        if metadata.is_file() && hash_file(&target)? == *hash {
+            fs::remove_file(&backup)?;
+            return Ok(true)
+        }
+        return Ok(false)
+    }
+
+    // Check before and after moving, so a corrupt backup is left in the backup store for inspection and a
+    // bad copy between filesystems is still caught
+    if hash_file(&backup)? != *hash {
+        bail!(ErrorKind::BackupHashMismatch(file.target.display().to_string()))
+    }
+    move_file(&backup, &target)?;
+    if hash_file(&target)? != *hash {
+        bail!(ErrorKind::BackupHashMismatch(file.target.display().to_string()))
+    }
+
+    Ok(true)
+}
+
+/// Hardlink a file, falling back to a symlink when the source is on a different filesystem
+fn link_file(source: &Path, target: &Path) -> io::Result<LinkKind> {
+    match fs::hard_link(source, target) {
+        Ok(_) => Ok(LinkKind::Hardlink),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            symlink(source, target)?;
+            Ok(LinkKind::Symlink)
+        }
+        Err(err) => Err(err)
+    }
+}
+
+/// Get the identity of a deployed hard link
+fn link_file_id(link: LinkKind, target: &Path) -> io::Result<Option<FileId>> {
+    if link != LinkKind::Hardlink {
+        return Ok(None)
+    }
+
+    Ok(Some(FileId::new(&fs::symlink_metadata(target)?)))
+}
+
+/* ========================================= */
+/* Verify                                    */
+/* ========================================= */
+
+/// A way a deployed file no longer matches the deployment manifest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The deployed file has been removed
+    Missing,
+    /// The deployed file has been replaced by a different file
+    Replaced,
+    /// The deployed file overrode a game file, and the game has put its own file back
+    GameReplaced,
+    /// The deployed file has been replaced by a link to a file that isn't from the deployment, either a symlink
+    /// or a hard link shared with a file outside the game directory
+    Foreign,
+    /// The file the deployed file links to has been removed from the mod
+    SourceMissing,
+    /// The file the deployed file links to has been replaced in the mod, so the deployed file is out of date
+    Outdated,
+}
+
+impl IssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueKind::Missing => "missing",
+            IssueKind::Replaced => "replaced",
+            IssueKind::GameReplaced => "replaced by game",
+            IssueKind::Foreign => "foreign",
+            IssueKind::SourceMissing => "source missing",
+            IssueKind::Outdated => "outdated",
+        }
+    }
+}
+
+/// A deployed file that no longer matches the deployment manifest
+pub struct DeploymentIssue {
+    /// The index of the file in the manifest
+    pub index: usize,
+    pub kind: IssueKind,
+}
+
+/// Check every file in the deployment manifest against the game directory
+///
+/// # Arguments
+///
+/// * `instance`: The instance the manifest belongs to
+/// * `manifest`: The manifest to check
+///
+/// returns: Vec<DeploymentIssue> The files that no longer match the manifest
+pub fn verify(instance: &Instance, manifest: &DeploymentManifest) -> Vec<DeploymentIssue> {
+    manifest.files.iter()
+        .enumerate()
+        .filter_map(|(index, file)| {
+            check_file(instance.game_path(), file).map(|kind| DeploymentIssue { index, kind })
+        })
+        .collect()
+}
+
+/// Redeploy the files with issues
+///
+/// Files that have replaced deployed files are moved to the backup store, replacing any older backup, so
+/// files put back by a game update are restored on purge. Files whose source has been removed from the mod
+/// can't be redeployed, so they're removed from the game directory and the manifest instead, restoring
+/// their backup. Anything else that has taken the place of such a file is left alone.
+pub fn repair(instance: &Instance, manifest: &mut DeploymentManifest, issues: &[DeploymentIssue]) -> errors::Result<()> {
+    let game_path = instance.game_path();
+    let mut dropped = Vec::new();
+
+    for issue in issues {
+        let file = &mut manifest.files[issue.index];
+        let target = game_path.join(&file.target);
+
+        match issue.kind {
+            IssueKind::SourceMissing => {
+                fs::remove_file(&target)?;
+                if restore_backup(instance, game_path, file)? {
+                    dropped.push(issue.index);
+                }
+                continue;
+            }
+            _ if !file.source.exists() => {
+                // Files whose backup is kept stay in the manifest so the backup isn't lost track of
+                if restore_backup(instance, game_path, file)? {
+                    dropped.push(issue.index);
+                }
+                continue;
+            }
+            IssueKind::Replaced | IssueKind::GameReplaced => {
+                let backup = backup_path(instance, &file.target);
+                if backup.exists() {
+                    fs::remove_file(&backup)?;
+                }
+                file.backup_hash = Some(back_up_file(instance, game_path, &file.target)?);
+                file.backup_link = None;
+            }
+            IssueKind::Foreign | IssueKind::Outdated => fs::remove_file(&target)?,
+            IssueKind::Missing => {}
+        }
+
+        create_parent_dirs(game_path, &target, &mut manifest.directories)?;
+        file.link = link_file(&file.source, &target)?;
+        file.file_id = link_file_id(file.link, &target)?;
+    }
+
+    dropped.sort_unstable();
+    for index in dropped.into_iter().rev() {
+        manifest.files.remove(index);
+    }
+
+    manifest.save(instance)
+}
+
+fn check_file(game_path: &Path, file: &DeployedFile) -> Option<IssueKind> {
+    let target = game_path.join(&file.target);
+
+    let Ok(target_metadata) = fs::symlink_metadata(&target) else {
+        return Some(IssueKind::Missing)
+    };
+    let source_metadata = fs::metadata(&file.source).ok();
+    let target_id = FileId::new(&target_metadata);
+    let source_id = source_metadata.as_ref().map(FileId::new);
+
+    // Only a link the deployment made can be removed or replaced, checking the source alone would claim
+    // whatever has since been put in the place of a link to a removed file
+    let is_deployed = if target_metadata.file_type().is_symlink() {
+        fs::read_link(&target).is_ok_and(|link| link == file.source)
+    } else {
+        // The source is checked too, for manifests from before the identity was recorded and sources edited in place
+        file.file_id == Some(target_id) || source_id == Some(target_id)
+    };
+
+    if is_deployed {
+        match source_id {
+            None => Some(IssueKind::SourceMissing),
+            Some(source_id) if file.link == LinkKind::Hardlink && source_id != target_id => Some(IssueKind::Outdated),
+            Some(_) => None
+        }
+    } else if target_metadata.file_type().is_symlink() || target_metadata.nlink() > 1 {
+        // Something else, such as another mod manager, has linked its own file in
+        Some(IssueKind::Foreign)
+    } else if file.backup_hash.is_some() || file.backup_link.is_some() {
+        Some(IssueKind::GameReplaced)
+    } else {
+        Some(IssueKind::Replaced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use crate::deployment::{deploy, DeploymentManifest, IssueKind, purge, repair, verify};
+    use crate::mod_info::instance::Instance;
+
+    /// Create a Skyrim SE instance in a directory with its game installed alongside
+    fn test_instance(dir: &Path) -> Instance {
+        let base = dir.join("instance");
+        let game = dir.join("game");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &game, None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        fs::create_dir_all(game.join("Data")).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+        instance
+    }
+
+    fn add_file(dir: &Path, path: &str, content: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn deploy_verify_repair_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        let instance = test_instance(dir.path());
+        let mod_path = instance.mod_path("Mod");
+        add_file(&mod_path, "textures/a.dds", "a");
+        add_file(&mod_path, "meshes/deep/b.nif", "b");
+        let data = instance.game_path().join("Data");
+
+        let mut manifest = deploy(&instance).unwrap();
+        assert_eq!(fs::read_to_string(data.join("textures/a.dds")).unwrap(), "a");
+        assert!(verify(&instance, &manifest).is_empty());
+
+        // Remove a deployed directory, and swap a file for a hard link to a file from outside the deployment
+        fs::remove_dir_all(data.join("meshes")).unwrap();
+        fs::remove_file(data.join("textures/a.dds")).unwrap();
+        add_file(dir.path(), "other/a.dds", "other");
+        fs::hard_link(dir.path().join("other/a.dds"), data.join("textures/a.dds")).unwrap();
+
+        let issues = verify(&instance, &manifest);
+        let mut kinds: Vec<IssueKind> = issues.iter().map(|issue| issue.kind).collect();
+        kinds.sort_by_key(|kind| kind.name());
+        assert_eq!(kinds, vec![IssueKind::Foreign, IssueKind::Missing]);
+
+        repair(&instance, &mut manifest, &issues).unwrap();
+        assert!(verify(&instance, &DeploymentManifest::load(&instance).unwrap()).is_empty());
+        assert_eq!(fs::read_to_string(data.join("meshes/deep/b.nif")).unwrap(), "b");
+
+        purge(&instance).unwrap();
+        assert!(!data.join("meshes").exists());
+        assert!(!data.join("textures").exists());
+        assert!(DeploymentManifest::load(&instance).unwrap().is_empty());
+        assert_eq!(fs::read_to_string(dir.path().join("other/a.dds")).unwrap(), "other");
+    }
+
+    #[test]
+    fn back_up_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let instance = test_instance(dir.path());
+        add_file(&instance.mod_path("Mod"), "a.esp", "mod a");
+        add_file(&instance.mod_path("Mod"), "b.esp", "mod b");
+        let data = instance.game_path().join("Data");
+        add_file(&data, "a.esp", "game a");
+        add_file(dir.path(), "elsewhere/b.esp", "linked b");
+        symlink(dir.path().join("elsewhere/b.esp"), data.join("b.esp")).unwrap();
+
+        let manifest = deploy(&instance).unwrap();
+        assert_eq!(fs::read_to_string(data.join("a.esp")).unwrap(), "mod a");
+        assert_eq!(fs::read_to_string(data.join("b.esp")).unwrap(), "mod b");
+        assert!(manifest.files().iter().all(|file| file.backup_hash.is_some() || file.backup_link.is_some()));
+
+        purge(&instance).unwrap();
+        assert_eq!(fs::read_to_string(data.join("a.esp")).unwrap(), "game a");
+        assert_eq!(fs::read_link(data.join("b.esp")).unwrap(), dir.path().join("elsewhere/b.esp"));
+        assert!(!instance.base_path().join("backups/Data/a.esp").exists());
+    }
+
+    #[test]
+    fn keep_files_replacing_removed_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let instance = test_instance(dir.path());
+        let mod_path = instance.mod_path("Mod");
+        add_file(&mod_path, "a.esp", "mod a");
+        add_file(&mod_path, "b.esp", "mod b");
+        add_file(&mod_path, "c.esp", "mod c");
+        let data = instance.game_path().join("Data");
+
+        let mut manifest = deploy(&instance).unwrap();
+        fs::remove_file(mod_path.join("a.esp")).unwrap();
+        fs::remove_file(mod_path.join("b.esp")).unwrap();
+        fs::remove_file(data.join("b.esp")).unwrap();
+        add_file(&data, "b.esp", "user b");
+        fs::remove_file(mod_path.join("c.esp")).unwrap();
+        add_file(&mod_path, "c.esp", "new mod c");
+
+        let issues = verify(&instance, &manifest);
+        let mut kinds: Vec<(&Path, IssueKind)> = issues.iter()
+            .map(|issue| (manifest.files()[issue.index].target.as_path(), issue.kind))
+            .collect();
+        kinds.sort_by_key(|(target, _)| target.to_path_buf());
+        assert_eq!(kinds, vec![
+            (Path::new("Data/a.esp"), IssueKind::SourceMissing),
+            (Path::new("Data/b.esp"), IssueKind::Replaced),
+            (Path::new("Data/c.esp"), IssueKind::Outdated),
+        ]);
+
+        repair(&instance, &mut manifest, &issues).unwrap();
+        assert!(!data.join("a.esp").exists());
+        assert_eq!(fs::read_to_string(data.join("b.esp")).unwrap(), "user b");
+        assert_eq!(fs::read_to_string(data.join("c.esp")).unwrap(), "new mod c");
+        assert_eq!(manifest.files().len(), 1);
+
+        // A purge after the source is removed leaves the user's file alone too
+        let mut manifest = deploy(&instance).unwrap();
+        assert!(verify(&instance, &manifest).is_empty());
+        fs::remove_file(mod_path.join("c.esp")).unwrap();
+        fs::remove_file(data.join("c.esp")).unwrap();
+        add_file(&data, "c.esp", "user c");
+        purge(&instance).unwrap();
+        assert_eq!(fs::read_to_string(data.join("c.esp")).unwrap(), "user c");
+        manifest = DeploymentManifest::load(&instance).unwrap();
+        assert!(manifest.is_empty());
+    }
+
+    #[test]
+    fn keep_backups_of_replaced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let instance = test_instance(dir.path());
+        add_file(&instance.mod_path("Mod"), "a.esp", "mod a");
+        add_file(&instance.mod_path("Mod"), "b.esp", "mod b");
+        let data = instance.game_path().join("Data");
+        add_file(&data, "a.esp", "game a");
+        add_file(&data, "b.esp", "game b");
+        let backups = instance.base_path().join("backups/Data");
+
+        deploy(&instance).unwrap();
+        fs::remove_file(data.join("a.esp")).unwrap();
+        add_file(&data, "a.esp", "other a");
+        fs::remove_file(data.join("b.esp")).unwrap();
+        add_file(&data, "b.esp", "game b");
+
+        assert!(purge(&instance).is_err());
+        assert_eq!(fs::read_to_string(data.join("a.esp")).unwrap(), "other a");
+        assert_eq!(fs::read_to_string(backups.join("a.esp")).unwrap(), "game a");
+        assert_eq!(fs::read_to_string(data.join("b.esp")).unwrap(), "game b");
+        assert!(!backups.join("b.esp").exists());
+        assert_eq!(DeploymentManifest::load(&instance).unwrap().files().len(), 1);
+        assert!(deploy(&instance).is_err());
+
+        fs::remove_file(data.join("a.esp")).unwrap();
+        purge(&instance).unwrap();
+        assert_eq!(fs::read_to_string(data.join("a.esp")).unwrap(), "game a");
+        assert!(DeploymentManifest::load(&instance).unwrap().is_empty());
+    }
+}
@@ -1,27 +1,701 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use error_chain::bail;
 use rayon::{ThreadPool, ThreadPoolBuilder};
 use url::Url;
 
+use crate::download_manager::checksum::{FileHash, HashAlgorithm, verify_file};
+use crate::download_manager::limits::{DownloadLimits, HostQueue, TokenBucket};
+use crate::download_manager::metadata::{available_file_name, DownloadAttempt, DownloadMetadata, DownloadStatus};
+use crate::download_manager::retry::RetryPolicy;
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::plugin::downloader::{DownloadControl, DownloadedFile, Downloader, DownloadFailed, remove_partial};
+
+pub mod checksum;
+pub mod limits;
+pub mod metadata;
+pub mod retry;
+
+/// The number of downloads run at once if not configured
+pub const DEFAULT_CONCURRENT_DOWNLOADS: usize = 3;
+/// The minimum time between progress updates sent for a download
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type DownloadId = u64;
+
+/// How much of a download has been downloaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub downloaded: u64,
+    /// The size of the file, if the server reported it
+    pub total: Option<u64>,
+}
+
+impl DownloadProgress {
+    /// The fraction of the file that has been downloaded, if the size is known
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.filter(|total| *total > 0).map(|total| self.downloaded as f64 / total as f64)
+    }
+}
+
+/// The state of a download, sent to the download's sender every time it changes
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum DownloadState {
+    /// Waiting for a free download slot
+    Queued,
+    /// Connecting to the server
+    Connecting,
+    Downloading(DownloadProgress),
+    /// Waiting to try again after failing for a reason that may pass
+    Retrying {
+        error: String,
+        delay: Duration,
+    },
+    /// Stopped until resumed
+    Paused,
+    /// Finished, holding the path of the downloaded file
+    Completed(PathBuf),
+    Failed(String),
+    Cancelled,
+}
+
+impl DownloadState {
+    /// Whether the download has stopped without being paused, only failed downloads can be resumed
+    pub fn is_finished(&self) -> bool {
+        matches!(self, DownloadState::Completed(_) | DownloadState::Failed(_) | DownloadState::Cancelled)
+    }
+}
+
+/// What a running download has been asked to do
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+struct TaskState {
+    state: DownloadState,
+    control: Control,
+    /// Incremented every time the download is queued, so a stale queued job knows not to run
+    generation: u64,
+    /// The download's metadata, saved next to the file whenever the download's status changes
+    metadata: DownloadMetadata,
+}
 
+struct DownloadTask {
+    url: Url,
+    dest: PathBuf,
+    /// The downloaders to try in order, each falling back to the next if it fails
+    downloaders: Vec<Arc<dyn Downloader>>,
+    sender: Sender<DownloadState>,
+    state: Mutex<TaskState>,
+    limiter: Arc<Limiter>,
+    retry_policy: RetryPolicy,
 }
 
+impl DownloadTask {
+    /// Change the state and send it, a closed receiver is ignored as the download carries on regardless
+    fn set_state(&self, task_state: &mut TaskState, state: DownloadState) {
+        let status = task_state.metadata.status;
+        task_state.metadata.set_state(&state);
+        if task_state.metadata.status != status || task_state.metadata.status == DownloadStatus::Queued {
+            self.save_metadata(task_state);
+        }
+
+        task_state.state = state.clone();
+        let _ = self.sender.send(state);
+    }
+
+    /// Change the name of the file being downloaded, moving the metadata to match
+    fn set_file_name(&self, task_state: &mut TaskState, file_name: String) {
+        if task_state.metadata.file_name == file_name {
+            return
+        }
+
+        // Another download may have taken the old name since, so only remove the metadata if it's still this one's
+        let old_path = task_state.metadata.path(&self.dest);
+        if DownloadMetadata::load(&old_path).is_ok_and(|metadata| metadata.url == task_state.metadata.url) {
+            if let Err(err) = fs::remove_file(&old_path) {
+                eprintln!("Failed to remove the old metadata of {}: {err}", task_state.metadata.file_name);
+            }
+        }
+        task_state.metadata.file_name = file_name;
+        self.save_metadata(task_state);
+    }
+
+    fn save_metadata(&self, task_state: &TaskState) {
+        if let Err(err) = task_state.metadata.save(&self.dest) {
+            eprintln!("Failed to save the metadata of {}: {err}", task_state.metadata.file_name);
+        }
+    }
+}
+
+/// The limits every download of a manager shares
+struct Limiter {
+    limits: Mutex<DownloadLimits>,
+    /// The bandwidth of every download together
+    bandwidth: TokenBucket,
+    /// The downloads running against each host, and the queued jobs waiting for their host to have room
+    hosts: Mutex<HostQueue<(Arc<DownloadTask>, u64)>>,
+}
+
+impl Limiter {
+    fn per_download_speed(&self) -> Option<u64> {
+        self.limits.lock().unwrap().per_download_speed
+    }
+}
+
+/// A queue of downloads run on a thread pool, with at most a configured number running at once
 pub struct DownloadManager {
-    pool: ThreadPool
+    pool: ThreadPool,
+    tasks: Mutex<HashMap<DownloadId, Arc<DownloadTask>>>,
+    next_id: AtomicU64,
+    limiter: Arc<Limiter>,
+    retry_policy: RetryPolicy,
 }
 
 impl DownloadManager {
-    pub fn download(url: Url, dest: PathBuf, sender: Sender<DownloadState>) {
-
+    /// Create a download manager
+    ///
+    /// # Arguments
+    ///
+    /// * `concurrent_downloads`: The maximum number of downloads to run at once, at least 1
+    ///
+    /// returns: DownloadManager
+    pub fn new(concurrent_downloads: usize) -> Self {
+        Self::with_limits(concurrent_downloads, DownloadLimits::default())
     }
 
-    pub fn new() -> Self {
+    /// Create a download manager with limits on download speeds and the downloads per host
+    ///
+    /// # Arguments
+    ///
+    /// * `concurrent_downloads`: The maximum number of downloads to run at once, at least 1
+    /// * `limits`: The limits to start with, they can be changed later with [DownloadManager::set_limits]
+    ///
+    /// returns: DownloadManager
+    pub fn with_limits(concurrent_downloads: usize, limits: DownloadLimits) -> Self {
         Self {
             pool: ThreadPoolBuilder::new()
-                .num_threads(5)
+                .num_threads(concurrent_downloads.max(1))
+                .thread_name(|index| format!("download-{index}"))
                 .build()
-                .unwrap()
+                .unwrap(),
+            tasks: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            limiter: Arc::new(Limiter {
+                limits: Mutex::new(limits),
+                bandwidth: TokenBucket::new(limits.speed),
+                hosts: Mutex::new(HostQueue::new(limits.per_host)),
+            }),
+            retry_policy: RetryPolicy::default(),
         }
     }
-}
\ No newline at end of file
+
+    /// Change how downloads added from now on retry failures that may pass
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn limits(&self) -> DownloadLimits {
+        *self.limiter.limits.lock().unwrap()
+    }
+
+    /// Change the speed and per host limits, running downloads follow the new limits from their next update
+    pub fn set_limits(&self, limits: DownloadLimits) {
+        *self.limiter.limits.lock().unwrap() = limits;
+        self.limiter.bandwidth.set_rate(limits.speed);
+
+        // Start the downloads a raised per host limit has made room for
+        let startable: Vec<(Arc<DownloadTask>, u64)> = {
+            let mut hosts = self.limiter.hosts.lock().unwrap();
+            hosts.set_limit(limits.per_host);
+            std::iter::from_fn(|| hosts.next_waiting()).collect()
+        };
+        for (task, generation) in startable {
+            self.pool.spawn(move || run(task, generation));
+        }
+    }
+
+    /// Queue a download
+    ///
+    /// # Arguments
+    ///
+    /// * `downloaders`: The downloaders to download the file with, in order of preference
+    /// * `url`: The URL to download
+    /// * `dest`: The directory to download the file into, the download's metadata is saved there too
+    /// * `sender`: Sent every state the download goes through, starting with [DownloadState::Queued]
+    ///
+    /// returns: DownloadId The ID used to pause, resume and cancel the download
+    pub fn download(&self, downloaders: Vec<Arc<dyn Downloader>>, url: Url, dest: PathBuf, sender: Sender<DownloadState>) -> DownloadId {
+        let mut metadata = DownloadMetadata::new(&url);
+        metadata.file_name = available_file_name(&dest, &metadata.file_name, &url);
+        self.add(downloaders, url, metadata, dest, sender)
+    }
+
+    /// Add a download from its saved metadata, such as one left unfinished when the manager last closed
+    ///
+    /// Downloads that were queued or downloading are queued again, paused and failed downloads are added in
+    /// the same state to be resumed later.
+    ///
+    /// # Arguments
+    ///
+    /// * `downloaders`: The downloaders to download the file with, in order of preference
+    /// * `metadata`: The saved metadata of the download
+    /// * `dest`: The directory the download's metadata is in
+    /// * `sender`: Sent every state the download goes through, starting with its current state
+    ///
+    /// returns: Result<DownloadId, Error> The ID used to pause, resume and cancel the download
+    pub fn restore(&self, downloaders: Vec<Arc<dyn Downloader>>, metadata: DownloadMetadata, dest: PathBuf, sender: Sender<DownloadState>) -> errors::Result<DownloadId> {
+        let url = metadata.url()?;
+        Ok(self.add(downloaders, url, metadata, dest, sender))
+    }
+
+    fn add(&self, downloaders: Vec<Arc<dyn Downloader>>, url: Url, metadata: DownloadMetadata, dest: PathBuf, sender: Sender<DownloadState>) -> DownloadId {
+        let state = match metadata.status {
+            DownloadStatus::Paused => Some(DownloadState::Paused),
+            DownloadStatus::Failed => Some(DownloadState::Failed(metadata.error.clone().unwrap_or_default())),
+            DownloadStatus::Completed => Some(DownloadState::Completed(metadata.file_path(&dest))),
+            DownloadStatus::Cancelled => Some(DownloadState::Cancelled),
+            DownloadStatus::Queued | DownloadStatus::Downloading => None
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let task = Arc::new(DownloadTask {
+            url,
+            dest,
+            downloaders,
+            sender,
+            state: Mutex::new(TaskState {
+                state: state.clone().unwrap_or(DownloadState::Queued),
+                control: if state.is_some() { Control::Pause } else { Control::Run },
+                generation: 0,
+                metadata,
+            }),
+            limiter: self.limiter.clone(),
+            retry_policy: self.retry_policy,
+        });
+
+        self.tasks.lock().unwrap().insert(id, task.clone());
+        match state {
+            Some(state) => {
+                let _ = task.sender.send(state);
+            }
+            None => self.queue(task)
+        }
+        id
+    }
+
+    /// Get the current state of a download
+    pub fn state(&self, id: DownloadId) -> Option<DownloadState> {
+        self.task(id).ok().map(|task| task.state.lock().unwrap().state.clone())
+    }
+
+    /// Get the IDs and current states of every download, in the order they were added
+    pub fn downloads(&self) -> Vec<(DownloadId, DownloadState)> {
+        let mut downloads: Vec<(DownloadId, DownloadState)> = self.tasks.lock().unwrap().iter()
+            .map(|(id, task)| (*id, task.state.lock().unwrap().state.clone()))
+            .collect();
+        downloads.sort_by_key(|(id, _)| *id);
+        downloads
+    }
+
+    /// Pause a queued or running download
+    ///
+    /// A running download stops at the next progress update and reports [DownloadState::Paused].
+    pub fn pause(&self, id: DownloadId) -> errors::Result<()> {
+        let task = self.task(id)?;
+        let mut state = task.state.lock().unwrap();
+        match state.state {
+            DownloadState::Queued => {
+                state.control = Control::Pause;
+                task.set_state(&mut state, DownloadState::Paused);
+            }
+            DownloadState::Connecting | DownloadState::Downloading(_) | DownloadState::Retrying { .. } => state.control = Control::Pause,
+            _ => bail!(ErrorKind::InvalidDownloadState(id, "paused".to_string()))
+        }
+
+        Ok(())
+    }
+
+    /// Queue a paused or failed download again, downloaders that support it carry on from where they stopped
+    pub fn resume(&self, id: DownloadId) -> errors::Result<()> {
+        let task = self.task(id)?;
+        {
+            let mut state = task.state.lock().unwrap();
+            match state.state {
+                DownloadState::Paused | DownloadState::Failed(_) => state.control = Control::Run,
+                // Still stopping after being paused, so carry on instead of stopping
+                DownloadState::Connecting | DownloadState::Downloading(_) | DownloadState::Retrying { .. } if state.control == Control::Pause => {
+                    state.control = Control::Run;
+                    return Ok(())
+                }
+                _ => bail!(ErrorKind::InvalidDownloadState(id, "resumed".to_string()))
+            }
+        }
+
+        self.queue(task);
+        Ok(())
+    }
+
+    /// Cancel a download that hasn't finished, removing the partly downloaded file
+    pub fn cancel(&self, id: DownloadId) -> errors::Result<()> {
+        let task = self.task(id)?;
+        let mut state = task.state.lock().unwrap();
+        match state.state {
+            DownloadState::Queued | DownloadState::Paused | DownloadState::Failed(_) => {
+                state.control = Control::Cancel;
+                remove_partial_file(&task, &state);
+                task.set_state(&mut state, DownloadState::Cancelled);
+            }
+            DownloadState::Connecting | DownloadState::Downloading(_) | DownloadState::Retrying { .. } => state.control = Control::Cancel,
+            _ => bail!(ErrorKind::InvalidDownloadState(id, "cancelled".to_string()))
+        }
+
+        Ok(())
+    }
+
+    /// Forget the downloads that have finished
+    pub fn clear_finished(&self) {
+        self.tasks.lock().unwrap().retain(|_, task| !task.state.lock().unwrap().state.is_finished());
+    }
+
+    fn task(&self, id: DownloadId) -> errors::Result<Arc<DownloadTask>> {
+        match self.tasks.lock().unwrap().get(&id) {
+            Some(task) => Ok(task.clone()),
+            None => bail!(ErrorKind::UnknownDownload(id))
+        }
+    }
+
+    fn queue(&self, task: Arc<DownloadTask>) {
+        let generation = {
+            let mut state = task.state.lock().unwrap();
+            state.generation += 1;
+            task.set_state(&mut state, DownloadState::Queued);
+            state.generation
+        };
+
+        self.pool.spawn(move || run(task, generation));
+    }
+}
+
+/// Run a queued download on a pool thread, then any downloads that were waiting for their host to have room
+fn run(task: Arc<DownloadTask>, generation: u64) {
+    let limiter = task.limiter.clone();
+    let mut next = Some((task, generation));
+    while let Some((task, generation)) = next {
+        run_task(&task, generation);
+        next = limiter.hosts.lock().unwrap().next_waiting();
+    }
+}
+
+fn run_task(task: &Arc<DownloadTask>, generation: u64) {
+    // Only downloads from a host are limited, copying local files isn't
+    let host = task.url.host_str().filter(|host| !host.is_empty()).map(|host| host.to_string());
+    {
+        let mut state = task.state.lock().unwrap();
+        // Paused or cancelled while queued, or queued again after being paused
+        if state.generation != generation || state.control != Control::Run {
+            return
+        }
+        // Left queued until a download from the same host finishes if the host already has too many
+        if let Some(host) = &host {
+            if task.limiter.hosts.lock().unwrap().start(host, (task.clone(), generation)).is_none() {
+                return
+            }
+        }
+        task.set_state(&mut state, DownloadState::Connecting);
+    }
+
+    let result = download_with_fallback(task, host.is_some());
+
+    let mut state = task.state.lock().unwrap();
+    let final_state = match (state.control, result) {
+        (Control::Cancel, _) => {
+            remove_partial_file(task, &state);
+            DownloadState::Cancelled
+        }
+        (Control::Pause, _) => DownloadState::Paused,
+        (Control::Run, Ok(file)) => {
+            if let Some(file_name) = file.path.file_name() {
+                task.set_file_name(&mut state, file_name.to_string_lossy().to_string());
+            }
+            let metadata = &mut state.metadata;
+            metadata.game = file.game.or(metadata.game.take());
+            metadata.mod_id = file.mod_id.or(metadata.mod_id);
+            metadata.file_id = file.file_id.or(metadata.file_id);
+            metadata.version = file.version.or(metadata.version.take());
+            metadata.expected_size = file.expected_size.or(metadata.expected_size);
+            metadata.expected_hash = file.expected_hash.or(metadata.expected_hash.take());
+            verify_download(metadata, &file.path)
+        }
+        (Control::Run, Err(err)) => DownloadState::Failed(err.msg),
+    };
+    task.set_state(&mut state, final_state);
+    drop(state);
+
+    if let Some(host) = &host {
+        task.limiter.hosts.lock().unwrap().finish(host);
+    }
+}
+
+/// Try each of a download's downloaders in turn until one downloads the file
+///
+/// A downloader that fails for a reason that may pass is retried after a delay that doubles each time, until
+/// it's out of retries and the next downloader is tried. Every attempt is recorded in the download's metadata.
+///
+/// # Arguments
+///
+/// * `task`: The download to run
+/// * `limited`: Whether the download's speed is limited
+///
+/// returns: Result<DownloadedFile, DownloadFailed> The downloaded file, or why the last attempt failed. Also
+/// fails if the download was paused or cancelled, the task's control says which
+fn download_with_fallback(task: &DownloadTask, limited: bool) -> Result<DownloadedFile, DownloadFailed> {
+    let mut last_error = DownloadFailed::new(format!("No downloader supports {} URLs", task.url.scheme()));
+    let mut first = true;
+
+    for downloader in &task.downloaders {
+        let mut retry = 0;
+        loop {
+            // The first attempt is already connecting, the rest start again from after the last one failed
+            if !first {
+                let mut state = task.state.lock().unwrap();
+                if state.control != Control::Run {
+                    return Err(last_error)
+                }
+                task.set_state(&mut state, DownloadState::Connecting);
+            }
+            first = false;
+
+            let started = Utc::now();
+            let result = attempt_download(task, downloader.as_ref(), limited);
+
+            let mut state = task.state.lock().unwrap();
+            // Stopping to pause or cancel isn't a failed attempt
+            if state.control != Control::Run {
+                return result
+            }
+            state.metadata.attempts.push(DownloadAttempt {
+                downloader: downloader.name().to_string(),
+                started,
+                finished: Utc::now(),
+                error: result.as_ref().err().map(|err| err.msg.clone()),
+            });
+            task.save_metadata(&state);
+
+            let err = match result {
+                Ok(file) => return Ok(file),
+                Err(err) => err
+            };
+            if !err.transient || retry >= task.retry_policy.retries {
+                last_error = err;
+                break
+            }
+
+            let delay = task.retry_policy.delay(retry);
+            retry += 1;
+            task.set_state(&mut state, DownloadState::Retrying { error: err.msg.clone(), delay });
+            drop(state);
+            last_error = err;
+            if throttle(task, delay) == DownloadControl::Stop {
+                return Err(last_error)
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Download a file once with a downloader, reporting its progress and keeping it within the speed limits
+fn attempt_download(task: &DownloadTask, downloader: &dyn Downloader, limited: bool) -> Result<DownloadedFile, DownloadFailed> {
+    let mut last_update: Option<Instant> = None;
+    let mut last_downloaded: Option<u64> = None;
+    let bandwidth = TokenBucket::new(task.limiter.per_download_speed());
+    downloader.download(&task.url, task.dest.clone(), &mut |total, downloaded, file_name| {
+        {
+            let mut state = task.state.lock().unwrap();
+            if state.control != Control::Run {
+                return DownloadControl::Stop
+            }
+
+            task.set_file_name(&mut state, file_name);
+            if last_update.is_none_or(|last_update| last_update.elapsed() >= PROGRESS_INTERVAL) {
+                last_update = Some(Instant::now());
+                let total = (total > 0).then_some(total);
+                task.set_state(&mut state, DownloadState::Downloading(DownloadProgress { downloaded, total }));
+            }
+        }
+
+        // The first update can include a resumed part that was downloaded earlier, so only count from there
+        let bytes = last_downloaded.map_or(0, |last_downloaded| downloaded.saturating_sub(last_downloaded));
+        last_downloaded = Some(downloaded);
+        if !limited {
+            return DownloadControl::Continue
+        }
+        bandwidth.set_rate(task.limiter.per_download_speed());
+        throttle(task, task.limiter.bandwidth.take(bytes).max(bandwidth.take(bytes)))
+    })
+}
+
+/// Wait out a download's share of the bandwidth or a retry delay, in short steps so it stops promptly if paused or cancelled
+fn throttle(task: &DownloadTask, wait: Duration) -> DownloadControl {
+    let until = Instant::now() + wait;
+    loop {
+        let now = Instant::now();
+        if now >= until {
+            return DownloadControl::Continue
+        }
+
+        thread::sleep((until - now).min(PROGRESS_INTERVAL));
+        if task.state.lock().unwrap().control != Control::Run {
+            return DownloadControl::Stop
+        }
+    }
+}
+
+/// Check a downloaded file against the size and hash its downloader expected, storing its size and hash
+fn verify_download(metadata: &mut DownloadMetadata, path: &Path) -> DownloadState {
+    let expected_hashes: Vec<&FileHash> = metadata.expected_hash.iter().collect();
+    match verify_file(path, metadata.expected_size, &expected_hashes, &[HashAlgorithm::Sha256]) {
+        Ok(hashes) => {
+            metadata.size = fs::metadata(path).map(|file| file.len()).ok();
+            metadata.sha256 = hashes.last().map(|hash| hash.value.clone());
+            DownloadState::Completed(path.to_path_buf())
+        }
+        Err(err) => DownloadState::Failed(format!("Verifying the download failed: {err}"))
+    }
+}
+
+fn remove_partial_file(task: &DownloadTask, state: &TaskState) {
+    remove_partial(&state.metadata.file_path(&task.dest));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use url::Url;
+
+    use crate::download_manager::{DownloadManager, DownloadState};
+    use crate::download_manager::metadata::{DownloadStatus, find};
+    use crate::download_manager::retry::RetryPolicy;
+    use crate::plugin::downloader::{DownloadControl, DownloadedFile, Downloader, DownloadFailed};
+
+    /// Reports progress until told to stop, waiting on a barrier after the first update
+    struct BlockingDownloader {
+        barrier: Arc<Barrier>,
+    }
+
+    impl Downloader for BlockingDownloader {
+        fn name(&self) -> &'static str {
+            "Blocking"
+        }
+
+        fn supported_protocols(&self) -> Vec<&'static str> {
+            vec!["test"]
+        }
+
+        fn download(&self, _: &Url, _: PathBuf, callback: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed> {
+            callback(10, 0, "file".to_string());
+            self.barrier.wait();
+            while callback(10, 5, "file".to_string()) == DownloadControl::Continue {
+                std::thread::yield_now();
+            }
+            Err(DownloadFailed::new("Stopped"))
+        }
+    }
+
+    /// Fails a number of times, transiently or not, before writing the file
+    struct FlakyDownloader {
+        name: &'static str,
+        failures: AtomicU32,
+        transient: bool,
+    }
+
+    impl Downloader for FlakyDownloader {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn supported_protocols(&self) -> Vec<&'static str> {
+            vec!["test"]
+        }
+
+        fn download(&self, _: &Url, dest: PathBuf, _: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(if self.transient { DownloadFailed::transient("Timed out") } else { DownloadFailed::new("Not found") })
+            }
+
+            std::fs::create_dir_all(&dest).unwrap();
+            let path = dest.join("flaky");
+            std::fs::write(&path, "mod").unwrap();
+            Ok(path.into())
+        }
+    }
+
+    #[test]
+    fn retry_and_fall_back() {
+        let manager = DownloadManager::new(1).with_retry_policy(RetryPolicy { retries: 1, initial_delay: Duration::from_millis(1) });
+        let flaky = |name, failures, transient| -> Arc<dyn Downloader> {
+            Arc::new(FlakyDownloader { name, failures: AtomicU32::new(failures), transient })
+        };
+        let (sender, receiver) = channel();
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().to_path_buf();
+        let downloaders = vec![flaky("Broken", 1, false), flaky("Flaky", 2, true), flaky("Working", 0, true)];
+        manager.download(downloaders, Url::parse("test://flaky").unwrap(), dest.clone(), sender);
+
+        assert!(receiver.iter().any(|state| matches!(state, DownloadState::Retrying { .. })));
+        assert!(receiver.iter().any(|state| matches!(state, DownloadState::Completed(_))));
+
+        let metadata = find(&dest, "flaky").unwrap();
+        let attempts: Vec<(&str, Option<&str>)> = metadata.attempts.iter()
+            .map(|attempt| (attempt.downloader.as_str(), attempt.error.as_deref()))
+            .collect();
+        assert_eq!(attempts, vec![("Broken", Some("Not found")), ("Flaky", Some("Timed out")), ("Flaky", Some("Timed out")), ("Working", None)]);
+    }
+
+    #[test]
+    fn pause_resume_cancel() {
+        let barrier = Arc::new(Barrier::new(2));
+        let manager = DownloadManager::new(1);
+        let (sender, receiver) = channel();
+        let url = Url::parse("test://file").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().to_path_buf();
+        let id = manager.download(vec![Arc::new(BlockingDownloader { barrier: barrier.clone() })], url, dest.clone(), sender);
+
+        barrier.wait();
+        manager.pause(id).unwrap();
+        assert!(receiver.iter().any(|state| state == DownloadState::Paused));
+        assert!(manager.pause(id).is_err());
+
+        manager.resume(id).unwrap();
+        barrier.wait();
+        manager.cancel(id).unwrap();
+        assert!(receiver.iter().any(|state| state == DownloadState::Cancelled));
+        assert_eq!(manager.state(id), Some(DownloadState::Cancelled));
+        assert!(manager.resume(id).is_err());
+
+        let metadata = find(&dest, "file").unwrap();
+        assert_eq!(metadata.status, DownloadStatus::Cancelled);
+        assert_eq!(metadata.size, Some(10));
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use error_chain::bail;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+
+/// A hash algorithm a download can be checked with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha256,
+    /// 64 bit xxHash with a seed of 0
+    XxHash64,
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgorithm::Md5 => write!(f, "MD5"),
+            HashAlgorithm::Sha256 => write!(f, "SHA-256"),
+            HashAlgorithm::XxHash64 => write!(f, "xxHash64"),
+        }
+    }
+}
+
+/// A hash of a file, as a lowercase hex string
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHash {
+    pub algorithm: HashAlgorithm,
+    pub value: String,
+}
+
+impl FileHash {
+    /// Create a hash, the value is lowercased so it compares equal to the computed hash
+    pub fn new(algorithm: HashAlgorithm, value: &str) -> Self {
+        Self { algorithm, value: value.trim().to_lowercase() }
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+    XxHash64(Box<Xxh64>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::XxHash64 => Hasher::XxHash64(Box::new(Xxh64::new(0))),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::XxHash64(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Md5(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::XxHash64(hasher) => format!("{:016x}", hasher.digest()),
+        }
+    }
+}
+
+/// Hash a file with several algorithms while reading it once
+///
+/// # Arguments
+///
+/// * `path`: The file to hash
+/// * `algorithms`: The algorithms to hash the file with
+///
+/// returns: Result<Vec<FileHash>, Error> The hashes, in the order of the algorithms
+pub fn hash_file(path: &Path, algorithms: &[HashAlgorithm]) -> io::Result<Vec<FileHash>> {
+    let mut hashers: Vec<Hasher> = algorithms.iter().map(|algorithm| Hasher::new(*algorithm)).collect();
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hashers.iter_mut().for_each(|hasher| hasher.update(&buffer[..read]));
+    }
+
+    Ok(algorithms.iter().zip(hashers)
+        .map(|(algorithm, hasher)| FileHash { algorithm: *algorithm, value: hasher.finish() })
+        .collect())
+}
+
+/// Check a file against its expected size and hash
+///
+/// # Arguments
+///
+/// * `path`: The file to check
+/// * `expected_size`: The size the file should be, if known
+/// * `expected_hashes`: The hashes the file should have, empty to only check the size
+/// * `other_algorithms`: Other algorithms to hash the file with in the same pass, such as to store the hash
+///
+/// returns: Result<Vec<FileHash>, Error> The file's hashes for each expected hash followed by the other
+/// algorithms, or an error describing the first mismatch
+pub fn verify_file(path: &Path, expected_size: Option<u64>, expected_hashes: &[&FileHash], other_algorithms: &[HashAlgorithm]) -> errors::Result<Vec<FileHash>> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let size = path.metadata()?.len();
+    if let Some(expected_size) = expected_size.filter(|expected_size| *expected_size != size) {
+        bail!(ErrorKind::SizeMismatch(file_name, expected_size, size))
+    }
+
+    let algorithms: Vec<HashAlgorithm> = expected_hashes.iter().map(|hash| hash.algorithm)
+        .chain(other_algorithms.iter().copied())
+        .collect();
+    let hashes = hash_file(path, &algorithms)?;
+    for (expected, actual) in expected_hashes.iter().zip(&hashes) {
+        if expected.value != actual.value {
+            bail!(ErrorKind::ChecksumMismatch(file_name, expected.algorithm.to_string(), expected.value.clone(), actual.value.clone()))
+        }
+    }
+
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::download_manager::checksum::{FileHash, hash_file, HashAlgorithm, verify_file};
+
+    #[test]
+    fn file_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let hashes = hash_file(&path, &[HashAlgorithm::Md5, HashAlgorithm::Sha256, HashAlgorithm::XxHash64]).unwrap();
+        assert_eq!(hashes[0].value, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes[1].value, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hashes[2].value, "44bc2cf5ad770999");
+
+        let md5 = FileHash::new(HashAlgorithm::Md5, "900150983CD24FB0D6963F7D28E17F72");
+        let verified = verify_file(&path, Some(3), &[&md5], &[HashAlgorithm::Sha256]).unwrap();
+        assert_eq!(verified[1], hashes[1]);
+        assert!(verify_file(&path, Some(4), &[&md5], &[]).is_err());
+        assert!(verify_file(&path, None, &[&FileHash::new(HashAlgorithm::XxHash64, "0")], &[]).is_err());
+    }
+}
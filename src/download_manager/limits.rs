@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits on how fast downloads run and how many run against each host, None is unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DownloadLimits {
+    /// The most bytes per second every download together can download
+    pub speed: Option<u64>,
+    /// The most bytes per second each download can download
+    pub per_download_speed: Option<u64>,
+    /// The most downloads that can run against the same host at once
+    pub per_host: Option<usize>,
+}
+
+/// A token bucket filling with a byte per byte of the rate each second, up to a second's worth
+///
+/// Takers can overdraw the bucket, they're told how long to wait for it to refill instead, so downloads sharing a
+/// bucket share its rate between them.
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    rate: Option<u64>,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Create a bucket, with no rate to never wait
+    pub fn new(rate: Option<u64>) -> Self {
+        Self { state: Mutex::new(BucketState { rate: rate.filter(|rate| *rate > 0), tokens: 0.0, updated: Instant::now() }) }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Change the rate, takers waiting on the old rate aren't woken early
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let rate = rate.filter(|rate| *rate > 0);
+        let mut state = self.state.lock().unwrap();
+        if state.rate != rate {
+            state.rate = rate;
+            state.tokens = 0.0;
+            state.updated = Instant::now();
+        }
+    }
+
+    /// Take bytes out of the bucket
+    ///
+    /// returns: Duration How long to wait before the bytes are within the rate
+    pub fn take(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let Some(rate) = state.rate else {
+            return Duration::ZERO
+        };
+
+        let now = Instant::now();
+        let rate = rate as f64;
+        state.tokens = (state.tokens + now.duration_since(state.updated).as_secs_f64() * rate).min(rate);
+        state.updated = now;
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+/// Counts the downloads running against each host, keeping downloads waiting for a host with too many
+pub struct HostQueue<T> {
+    limit: Option<usize>,
+    running: HashMap<String, usize>,
+    waiting: VecDeque<(String, T)>,
+}
+
+impl<T> HostQueue<T> {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit: limit.filter(|limit| *limit > 0), running: HashMap::new(), waiting: VecDeque::new() }
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit.filter(|limit| *limit > 0);
+    }
+
+    fn has_room(&self, host: &str) -> bool {
+        self.limit.is_none_or(|limit| self.running.get(host).copied().unwrap_or(0) < limit)
+    }
+
+    /// Count a download as running against a host if it has room, otherwise keep it waiting
+    ///
+    /// returns: Option<T> The download back if it can run now
+    pub fn start(&mut self, host: &str, download: T) -> Option<T> {
+        if self.has_room(host) {
+            *self.running.entry(host.to_string()).or_default() += 1;
+            Some(download)
+        } else {
+            self.waiting.push_back((host.to_string(), download));
+            None
+        }
+    }
+
+    /// Stop counting a download as running against a host
+    pub fn finish(&mut self, host: &str) {
+        if let Some(running) = self.running.get_mut(host) {
+            *running = running.saturating_sub(1);
+            if *running == 0 {
+                self.running.remove(host);
+            }
+        }
+    }
+
+    /// Take the first waiting download whose host has room, it still needs [HostQueue::start]ing
+    pub fn next_waiting(&mut self) -> Option<T> {
+        let index = self.waiting.iter().position(|(host, _)| self.has_room(host))?;
+        self.waiting.remove(index).map(|(_, download)| download)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::download_manager::limits::{HostQueue, TokenBucket};
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket::new(Some(1000));
+        let wait = bucket.take(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+
+        bucket.set_rate(None);
+        assert_eq!(bucket.take(1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn host_queue() {
+        let mut queue = HostQueue::new(Some(1));
+        assert_eq!(queue.start("a", 1), Some(1));
+        assert_eq!(queue.start("a", 2), None);
+        assert_eq!(queue.start("b", 3), Some(3));
+        assert_eq!(queue.next_waiting(), None);
+
+        queue.finish("a");
+        assert_eq!(queue.next_waiting(), Some(2));
+        assert_eq!(queue.start("a", 2), Some(2));
+
+        queue.start("a", 4);
+        queue.set_limit(None);
+        assert_eq!(queue.next_waiting(), Some(4));
+    }
+}
@@ -0,0 +1,354 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::download_manager::checksum::{FileHash, HashAlgorithm, verify_file};
+use crate::download_manager::DownloadState;
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::plugin::downloader::{partial_path, remove_partial, url_file_name};
+
+/// The suffix added to a downloaded file's name for its metadata file
+pub const METADATA_SUFFIX: &str = ".meta.toml";
+
+/// The last state a download was saved in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    Queued,
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadStatus {
+    /// Whether the download still needs finishing, so belongs in the queue
+    pub fn is_unfinished(&self) -> bool {
+        matches!(self, DownloadStatus::Queued | DownloadStatus::Downloading | DownloadStatus::Paused | DownloadStatus::Failed)
+    }
+}
+
+impl From<&DownloadState> for DownloadStatus {
+    fn from(state: &DownloadState) -> Self {
+        match state {
+            DownloadState::Queued => DownloadStatus::Queued,
+            DownloadState::Connecting | DownloadState::Downloading(_) | DownloadState::Retrying { .. } => DownloadStatus::Downloading,
+            DownloadState::Paused => DownloadStatus::Paused,
+            DownloadState::Completed(_) => DownloadStatus::Completed,
+            DownloadState::Failed(_) => DownloadStatus::Failed,
+            DownloadState::Cancelled => DownloadStatus::Cancelled,
+        }
+    }
+}
+
+/// Whether a downloaded file has been installed as a mod
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStatus {
+    #[default]
+    NotInstalled,
+    Installed,
+}
+
+/// One try at downloading a file with a downloader
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownloadAttempt {
+    /// The name of the downloader that made the attempt
+    pub downloader: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    /// Why the attempt failed, None if it downloaded the file
+    pub error: Option<String>,
+}
+
+/// Where a download came from and what happened to it, stored next to the downloaded file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownloadMetadata {
+    pub url: String,
+    /// The name of the downloaded file in the downloads directory
+    pub file_name: String,
+    /// The game the file is a mod for, as the site it was downloaded from names it
+    pub game: Option<String>,
+    /// The ID of the mod on the site it was downloaded from
+    pub mod_id: Option<u64>,
+    /// The ID of the file within the mod on the site it was downloaded from
+    pub file_id: Option<u64>,
+    /// The version of the mod the file is
+    pub version: Option<String>,
+    /// The size of the file, once known
+    pub size: Option<u64>,
+    /// The SHA-256 hash of the completed file
+    pub sha256: Option<String>,
+    pub added: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub completed: Option<DateTime<Utc>>,
+    pub status: DownloadStatus,
+    /// Why the download last failed
+    pub error: Option<String>,
+    #[serde(default)]
+    pub install_status: InstallStatus,
+    /// The size the source says the file should be
+    pub expected_size: Option<u64>,
+    /// The hash the source says the file should have
+    pub expected_hash: Option<FileHash>,
+    /// Every attempt at downloading the file, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<DownloadAttempt>,
+}
+
+impl DownloadMetadata {
+    /// Create the metadata for a new download, named after the end of the URL until the downloader names it
+    pub fn new(url: &Url) -> Self {
+        let now = Utc::now();
+        Self {
+            url: url.to_string(),
+            file_name: url_file_name(url),
+            game: None,
+            mod_id: None,
+            file_id: None,
+            version: None,
+            size: None,
+            sha256: None,
+            added: now,
+            updated: now,
+            completed: None,
+            status: DownloadStatus::Queued,
+            error: None,
+            install_status: InstallStatus::NotInstalled,
+            expected_size: None,
+            expected_hash: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Parse the URL the file is downloaded from
+    pub fn url(&self) -> errors::Result<Url> {
+        Ok(Url::parse(&self.url)?)
+    }
+
+    /// Get the path of the downloaded file
+    pub fn file_path(&self, downloads_path: &Path) -> PathBuf {
+        downloads_path.join(&self.file_name)
+    }
+
+    /// Get the path of the metadata file
+    pub fn path(&self, downloads_path: &Path) -> PathBuf {
+        metadata_path(downloads_path, &self.file_name)
+    }
+
+    /// Update the status from a download's state
+    pub fn set_state(&mut self, state: &DownloadState) {
+        self.status = DownloadStatus::from(state);
+        self.updated = Utc::now();
+
+        match state {
+            DownloadState::Downloading(progress) => self.size = progress.total.or(self.size),
+            DownloadState::Completed(_) => {
+                self.completed = Some(self.updated);
+                self.error = None;
+            }
+            DownloadState::Failed(err) | DownloadState::Retrying { error: err, .. } => self.error = Some(err.clone()),
+            _ => {}
+        }
+    }
+
+    /// Check the downloaded file still matches the size and hashes it was downloaded with
+    pub fn verify(&self, downloads_path: &Path) -> errors::Result<()> {
+        let sha256 = self.sha256.as_ref().map(|sha256| FileHash::new(HashAlgorithm::Sha256, sha256));
+        let expected_hashes: Vec<&FileHash> = self.expected_hash.iter().chain(sha256.iter()).collect();
+        verify_file(&self.file_path(downloads_path), self.size.or(self.expected_size), &expected_hashes, &[])?;
+        Ok(())
+    }
+
+    pub fn save(&self, downloads_path: &Path) -> errors::Result<()> {
+        fs::create_dir_all(downloads_path)?;
+        fs::write(self.path(downloads_path), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> errors::Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Get the path of the metadata file for a downloaded file
+pub fn metadata_path(downloads_path: &Path, file_name: &str) -> PathBuf {
+    downloads_path.join(format!("{file_name}{METADATA_SUFFIX}"))
+}
+
+/// Find a name for a download's file that no other download is using
+///
+/// The name is kept if no file or download has it, or if it belongs to an unfinished download of the same URL
+/// that is being carried on. Otherwise a number is added to the name, as in `mod (1).zip`.
+///
+/// # Arguments
+///
+/// * `downloads_path`: The downloads directory
+/// * `file_name`: The name the file would have, such as the end of the URL
+/// * `url`: The URL of the download
+///
+/// returns: String The name to save the file as
+pub fn available_file_name(downloads_path: &Path, file_name: &str, url: &Url) -> String {
+    let name = Path::new(file_name);
+    let stem = name.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = name.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+
+    let mut candidate = file_name.to_string();
+    let mut number = 1;
+    while !is_available(downloads_path, &candidate, url) {
+        candidate = format!("{stem} ({number}){extension}");
+        number += 1;
+    }
+    candidate
+}
+
+/// Whether a download of a URL can use a file name without taking another download's file or metadata
+fn is_available(downloads_path: &Path, file_name: &str, url: &Url) -> bool {
+    let metadata_path = metadata_path(downloads_path, file_name);
+    if metadata_path.exists() {
+        return DownloadMetadata::load(&metadata_path)
+            .is_ok_and(|metadata| metadata.url == url.as_str() && metadata.status.is_unfinished())
+    }
+
+    let file_path = downloads_path.join(file_name);
+    !file_path.exists() && !partial_path(&file_path).exists()
+}
+
+/// Load the metadata of every download in a downloads directory, oldest first
+///
+/// Metadata files that can't be read are skipped with a warning, so one bad file doesn't hide the rest.
+pub fn load_all(downloads_path: &Path) -> errors::Result<Vec<DownloadMetadata>> {
+    let entries = match fs::read_dir(downloads_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into())
+    };
+
+    let mut downloads = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if !path.to_string_lossy().ends_with(METADATA_SUFFIX) {
+            continue;
+        }
+
+        match DownloadMetadata::load(&path) {
+            Ok(metadata) => downloads.push(metadata),
+            Err(err) => eprintln!("Skipping unreadable download metadata {}: {err}", path.display())
+        }
+    }
+
+    downloads.sort_by(|a, b| a.added.cmp(&b.added).then_with(|| a.file_name.cmp(&b.file_name)));
+    Ok(downloads)
+}
+
+/// Load the metadata of a downloaded file
+pub fn find(downloads_path: &Path, file_name: &str) -> errors::Result<DownloadMetadata> {
+    match DownloadMetadata::load(&metadata_path(downloads_path, file_name)) {
+        Err(err) if matches!(err.kind(), ErrorKind::Io(err) if err.kind() == io::ErrorKind::NotFound) =>
+            bail!(ErrorKind::UnknownDownloadFile(file_name.to_string())),
+        result => result
+    }
+}
+
+/// Remove a download's metadata, its partial file and optionally the downloaded file
+///
+/// # Arguments
+///
+/// * `downloads_path`: The downloads directory
+/// * `metadata`: The metadata of the download to remove
+/// * `keep_file`: Whether to keep the completed file, only forgetting where it came from
+///
+/// returns: Result<(), Error>
+pub fn remove(downloads_path: &Path, metadata: &DownloadMetadata, keep_file: bool) -> errors::Result<()> {
+    let file_path = metadata.file_path(downloads_path);
+    remove_partial(&file_path);
+    if !keep_file {
+        match fs::remove_file(&file_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    fs::remove_file(metadata.path(downloads_path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use url::Url;
+
+    use crate::download_manager::metadata::{available_file_name, DownloadMetadata, DownloadStatus, find, load_all, remove};
+    use crate::errors::ErrorKind;
+    use crate::plugin::downloader::partial_path;
+
+    fn save(dir: &std::path::Path, url: &str, status: DownloadStatus) -> DownloadMetadata {
+        let url = Url::parse(url).unwrap();
+        let mut metadata = DownloadMetadata::new(&url);
+        metadata.file_name = available_file_name(dir, &metadata.file_name, &url);
+        metadata.status = status;
+        metadata.save(dir).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn same_name_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let first = save(dir, "https://a.example.com/mod.zip", DownloadStatus::Completed);
+        fs::write(first.file_path(dir), "first").unwrap();
+        let second = save(dir, "https://b.example.com/mod.zip", DownloadStatus::Downloading);
+        let manual = save(dir, "https://c.example.com/archive", DownloadStatus::Queued);
+        fs::write(dir.join("other.7z"), "").unwrap();
+
+        assert_eq!(first.file_name, "mod.zip");
+        assert_eq!(second.file_name, "mod (1).zip");
+        assert_eq!(manual.file_name, "archive");
+        assert_eq!(find(dir, "mod.zip").unwrap().url, "https://a.example.com/mod.zip");
+
+        // An unfinished download carries on with its own name, but a new download of a finished one doesn't
+        let url = Url::parse("https://b.example.com/mod.zip").unwrap();
+        assert_eq!(available_file_name(dir, "mod.zip", &url), "mod (1).zip");
+        let url = Url::parse("https://a.example.com/mod.zip").unwrap();
+        assert_eq!(available_file_name(dir, "mod.zip", &url), "mod (2).zip");
+        assert_eq!(available_file_name(dir, "other.7z", &url), "other (1).7z");
+        fs::write(partial_path(&dir.join("new.zip")), "").unwrap();
+        assert_eq!(available_file_name(dir, "new.zip", &url), "new (1).zip");
+
+        let mut names: Vec<String> = load_all(dir).unwrap().into_iter().map(|metadata| metadata.file_name).collect();
+        names.sort();
+        assert_eq!(names, ["archive", "mod (1).zip", "mod.zip"]);
+    }
+
+    #[test]
+    fn find_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        assert!(load_all(&dir.join("missing")).unwrap().is_empty());
+        assert!(matches!(find(dir, "mod.zip").unwrap_err().kind(), ErrorKind::UnknownDownloadFile(name) if name == "mod.zip"));
+
+        let kept = save(dir, "https://example.com/kept.zip", DownloadStatus::Completed);
+        fs::write(kept.file_path(dir), "kept").unwrap();
+        let removed = save(dir, "https://example.com/removed.zip", DownloadStatus::Failed);
+        fs::write(partial_path(&removed.file_path(dir)), "part").unwrap();
+        fs::write(dir.join("broken.zip.meta.toml"), "not metadata").unwrap();
+        assert_eq!(load_all(dir).unwrap().len(), 2);
+
+        remove(dir, &kept, true).unwrap();
+        assert!(kept.file_path(dir).exists());
+        assert!(find(dir, "kept.zip").is_err());
+
+        remove(dir, &removed, false).unwrap();
+        assert!(!partial_path(&removed.file_path(dir)).exists());
+        assert!(load_all(dir).unwrap().is_empty());
+    }
+}
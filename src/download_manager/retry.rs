@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// The number of times a download is retried if not configured
+pub const DEFAULT_RETRIES: u32 = 3;
+/// The delay before the first retry if not configured
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The longest delay between retries, however many there have been
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How many times to retry a downloader that failed for a reason that may pass, and how long to wait between
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The most times each downloader is retried before falling back to the next one
+    pub retries: u32,
+    /// The delay before the first retry, it doubles for each retry after
+    pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { retries: DEFAULT_RETRIES, initial_delay: DEFAULT_RETRY_DELAY }
+    }
+}
+
+impl RetryPolicy {
+    /// Get how long to wait before a retry, counting from 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay.saturating_mul(2u32.saturating_pow(retry)).min(MAX_RETRY_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::download_manager::retry::RetryPolicy;
+
+    #[test]
+    fn exponential_delay() {
+        let policy = RetryPolicy { retries: 5, initial_delay: Duration::from_millis(500) };
+        assert_eq!(policy.delay(0), Duration::from_millis(500));
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(40), Duration::from_secs(60));
+    }
+}
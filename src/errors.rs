@@ -10,9 +10,79 @@ error_chain!{
         Serialise(ser::Error) #[doc = "Error during serialisation"];
         Deserialise(de::Error) #[doc = "Error during deserialisation"];
         Library(libloading::Error) #[doc = "Error with library loading"];
+        Pattern(glob::PatternError) #[doc = "Error parsing a glob pattern"];
+        Yaml(serde_yaml::Error) #[doc = "Error parsing YAML"];
+        Regex(regex::Error) #[doc = "Error parsing a regular expression"];
+        Url(url::ParseError) #[doc = "Error parsing a URL"];
+        DBus(zbus::Error) #[doc = "Error communicating over D-Bus"];
     }
     errors {
         InstanceExists
         NoMatchingGame
+        NoGamePath
+        NoAppDataPath {
+            description("The instance has no app data path")
+            display("The instance has no app data path, which this game needs for its settings")
+        }
+        UnsupportedFeature(feature: String) {
+            description("Feature isn't supported for the game")
+            display("{} isn't supported for this game", feature)
+        }
+        InvalidPlugin(reason: String) {
+            description("Invalid game plugin")
+            display("Invalid game plugin: {}", reason)
+        }
+        UnknownPlugin(name: String) {
+            description("Unknown plugin")
+            display("Unknown plugin: {}", name)
+        }
+        ImplicitPlugin(name: String) {
+            description("Plugin is always loaded by the game")
+            display("{} is always loaded by the game, so it can't be deactivated or moved", name)
+        }
+        LoadOrderCycle(cycle: String) {
+            description("Plugins must load before each other")
+            display("Can't sort the load order because plugins must load before each other: {}", cycle)
+        }
+        UnknownSnapshot(name: String) {
+            description("Unknown load order snapshot")
+            display("Unknown load order snapshot: {}", name)
+        }
+        InvalidSnapshotName(name: String) {
+            description("Invalid load order snapshot name")
+            display("Invalid load order snapshot name: {}, names can't be empty, start with . or contain path separators", name)
+        }
+        UnknownDownload(id: u64) {
+            description("Unknown download")
+            display("Unknown download: {}", id)
+        }
+        UnknownDownloadFile(name: String) {
+            description("Unknown downloaded file")
+            display("No download metadata for {}", name)
+        }
+        SizeMismatch(file: String, expected: u64, actual: u64) {
+            description("Downloaded file is the wrong size")
+            display("{} is {} bytes, expected {} bytes", file, actual, expected)
+        }
+        ChecksumMismatch(file: String, algorithm: String, expected: String, actual: String) {
+            description("Downloaded file doesn't match its checksum")
+            display("{} has the {} hash {}, expected {}", file, algorithm, actual, expected)
+        }
+        InvalidDownloadState(id: u64, action: String) {
+            description("The download can't be changed in its current state")
+            display("Download {} can't be {} in its current state", id, action)
+        }
+        NoHomeDirectory {
+            description("The home directory couldn't be found")
+            display("The home directory couldn't be found to find the XDG directories in")
+        }
+        BackupHashMismatch(path: String) {
+            description("Restored backup doesn't match its hash")
+            display("The restored backup of {} doesn't match the hash it was backed up with", path)
+        }
+        BackupConflict(paths: String) {
+            description("Backed up game files can't be restored")
+            display("The backups of {} were kept because other files have taken their place, move those files and purge again", paths)
+        }
     }
 }
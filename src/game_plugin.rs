@@ -0,0 +1,131 @@
+use std::ops::RangeInclusive;
+
+pub mod header;
+pub mod light;
+pub mod record;
+
+/// The variant of the TES4 plugin format used by a game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PluginFormat {
+    /// Morrowind and OpenMW, which use the older TES3 format without FormIDs or groups
+    Morrowind,
+    /// Oblivion, which uses 20 byte record headers
+    Oblivion,
+    /// Fallout 3 and Fallout: New Vegas
+    Fallout3,
+    /// Skyrim
+    Skyrim,
+    /// Skyrim Special Edition, which adds light plugins
+    SkyrimSE,
+    Fallout4,
+    /// Starfield, which moves the light flag and adds medium plugins
+    Starfield,
+}
+
+impl PluginFormat {
+    /// The size of a record header in bytes
+    pub fn record_header_size(&self) -> usize {
+        match self {
+            PluginFormat::Morrowind => 16,
+            PluginFormat::Oblivion => 20,
+            _ => 24
+        }
+    }
+
+    /// Whether records are identified by FormIDs, which record-level features rely on
+    pub fn uses_form_ids(&self) -> bool {
+        *self != PluginFormat::Morrowind
+    }
+
+    /// The header flag marking a plugin as light, if the game supports light plugins
+    pub fn light_flag(&self) -> Option<u32> {
+        match self {
+            PluginFormat::SkyrimSE | PluginFormat::Fallout4 => Some(0x200),
+            PluginFormat::Starfield => Some(0x100),
+            _ => None
+        }
+    }
+
+    /// The header flag marking a plugin as medium, if the game supports medium plugins
+    pub fn medium_flag(&self) -> Option<u32> {
+        match self {
+            PluginFormat::Starfield => Some(0x400),
+            _ => None
+        }
+    }
+
+    /// The object indexes new records in a light plugin can use, if the game supports light plugins
+    ///
+    /// Skyrim Special Edition from header version 1.71 and Fallout 4 from header version 1.0 extend the range
+    /// down to 0x001, older plugins must start at 0x800.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_version`: The version from the plugin's `HEDR` subrecord
+    ///
+    /// returns: Option<RangeInclusive<u32>>
+    pub fn light_object_range(&self, header_version: f32) -> Option<RangeInclusive<u32>> {
+        match self {
+            PluginFormat::SkyrimSE if header_version >= 1.71 => Some(0x001..=0xFFF),
+            PluginFormat::Fallout4 if header_version >= 1.0 => Some(0x001..=0xFFF),
+            PluginFormat::SkyrimSE | PluginFormat::Fallout4 => Some(0x800..=0xFFF),
+            PluginFormat::Starfield => Some(0x001..=0xFFF),
+            _ => None
+        }
+    }
+
+    /// Whether the game treats plugins with an `.esm` or `.esl` extension as masters regardless of their flags
+    pub fn extension_implies_master(&self) -> bool {
+        matches!(self, PluginFormat::SkyrimSE | PluginFormat::Fallout4 | PluginFormat::Starfield)
+    }
+}
+
+/// Check if a file name has the extension of a game plugin, including OpenMW's content files
+pub fn is_plugin_file(file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase();
+    [".esp", ".esm", ".esl", ".omwaddon", ".omwgame"].iter().any(|extension| file_name.ends_with(extension))
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::game_plugin::PluginFormat;
+
+    /// Build a subrecord
+    pub fn subrecord(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = kind.to_vec();
+        bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Build a record with the given data
+    pub fn record(format: PluginFormat, kind: &[u8; 4], flags: u32, form_id: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = kind.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&form_id.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        if format != PluginFormat::Oblivion {
+            bytes.extend_from_slice(&44u16.to_le_bytes());
+            bytes.extend_from_slice(&0u16.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Build the TES4 header record of a plugin
+    pub fn header(format: PluginFormat, flags: u32, masters: &[&str], record_count: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut hedr = 1.7f32.to_le_bytes().to_vec();
+        hedr.extend_from_slice(&record_count.to_le_bytes());
+        hedr.extend_from_slice(&0x800u32.to_le_bytes());
+        data.extend(subrecord(b"HEDR", &hedr));
+        data.extend(subrecord(b"CNAM", b"Author\0"));
+        for master in masters {
+            data.extend(subrecord(b"MAST", format!("{master}\0").as_bytes()));
+            data.extend(subrecord(b"DATA", &0u64.to_le_bytes()));
+        }
+
+        record(format, b"TES4", flags, 0, &data)
+    }
+}
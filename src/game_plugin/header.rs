@@ -0,0 +1,345 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use error_chain::bail;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::PluginFormat;
+use crate::game_plugin::record::{read_data, read_u32, read_zstring, record_data, RecordHeader, Subrecords};
+
+/// The header flag marking a plugin as a master
+pub const MASTER_FLAG: u32 = 0x1;
+/// The header flag marking a plugin's strings as stored in separate string files
+pub const LOCALIZED_FLAG: u32 = 0x80;
+
+/// The information in the `TES4` header record at the start of a plugin
+pub struct PluginHeader {
+    format: PluginFormat,
+    flags: u32,
+    form_version: u16,
+    version: f32,
+    record_count: u32,
+    next_object_id: u32,
+    author: Option<String>,
+    description: Option<String>,
+    masters: Vec<String>,
+}
+
+impl PluginHeader {
+    /// Read the header of a plugin file
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the plugin
+    /// * `format`: The plugin format of the game the plugin is for
+    ///
+    /// returns: Result<PluginHeader, Error>
+    pub fn from_path(path: &Path, format: PluginFormat) -> errors::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?), format)
+    }
+
+    /// Read the header of a plugin, the `TES3` record for Morrowind and the `TES4` record for later games
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader, positioned at the start of the plugin
+    /// * `format`: The plugin format of the game the plugin is for
+    ///
+    /// returns: Result<PluginHeader, Error>
+    pub fn read<R: Read>(reader: &mut R, format: PluginFormat) -> errors::Result<Self> {
+        let record_header = RecordHeader::read(reader, format)?;
+        let expected = if format == PluginFormat::Morrowind { b"TES3" } else { b"TES4" };
+        if &record_header.kind != expected {
+            bail!(ErrorKind::InvalidPlugin(format!("File doesn't start with a {} record", String::from_utf8_lossy(expected))))
+        }
+
+        let data = read_data(reader, record_header.data_size)?;
+        if format == PluginFormat::Morrowind {
+            return Self::parse_tes3(&data)
+        }
+        let data = record_data(&record_header, &data)?;
+
+        let mut header = Self {
+            format,
+            flags: record_header.flags,
+            form_version: record_header.form_version,
+            version: 0.0,
+            record_count: 0,
+            next_object_id: 0,
+            author: None,
+            description: None,
+            masters: Vec::new(),
+        };
+
+        for subrecord in Subrecords::new(&data) {
+            let (kind, data) = subrecord?;
+            match &kind {
+                b"HEDR" => {
+                    if data.len() < 12 {
+                        bail!(ErrorKind::InvalidPlugin("HEDR subrecord is too small".to_string()))
+                    }
+                    header.version = f32::from_le_bytes(data[0..4].try_into().unwrap());
+                    header.record_count = read_u32(data, 4);
+                    header.next_object_id = read_u32(data, 8);
+                }
+                b"CNAM" => header.author = Some(read_zstring(data)),
+                b"SNAM" => header.description = Some(read_zstring(data)),
+                b"MAST" => header.masters.push(read_zstring(data)),
+                _ => {}
+            }
+        }
+
+        Ok(header)
+    }
+
+    /// Parse the data of a Morrowind `TES3` record, whose subrecords have 32 bit sizes and whose `HEDR` holds the
+    /// file type, author and description
+    fn parse_tes3(data: &[u8]) -> errors::Result<Self> {
+        let mut header = Self {
+            format: PluginFormat::Morrowind,
+            flags: 0,
+            form_version: 0,
+            version: 0.0,
+            record_count: 0,
+            next_object_id: 0,
+            author: None,
+            description: None,
+            masters: Vec::new(),
+        };
+
+        let mut position = 0;
+        while position < data.len() {
+            if position + 8 > data.len() {
+                bail!(ErrorKind::InvalidPlugin("Truncated subrecord header".to_string()))
+            }
+            let kind = &data[position..position + 4];
+            let start = position + 8;
+            let end = start + read_u32(data, position + 4) as usize;
+            if end > data.len() {
+                bail!(ErrorKind::InvalidPlugin(format!("Subrecord {} overruns its record", String::from_utf8_lossy(kind))))
+            }
+            let subrecord = &data[start..end];
+            position = end;
+
+            match kind {
+                b"HEDR" => {
+                    if subrecord.len() < 300 {
+                        bail!(ErrorKind::InvalidPlugin("HEDR subrecord is too small".to_string()))
+                    }
+                    header.version = f32::from_le_bytes(subrecord[0..4].try_into().unwrap());
+                    // The file type is 1 for masters, which lines up with the master flag of later games
+                    header.flags = read_u32(subrecord, 4);
+                    header.author = Some(read_zstring(&subrecord[8..40]));
+                    header.description = Some(read_zstring(&subrecord[40..296]));
+                    header.record_count = read_u32(subrecord, 296);
+                }
+                b"MAST" => header.masters.push(read_zstring(subrecord)),
+                _ => {}
+            }
+        }
+
+        Ok(header)
+    }
+
+    pub fn format(&self) -> PluginFormat {
+        self.format
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Whether the plugin is flagged as a master
+    pub fn is_master(&self) -> bool {
+        self.flags & MASTER_FLAG != 0
+    }
+
+    /// Whether the plugin is flagged as light, always false for games without light plugins
+    pub fn is_light(&self) -> bool {
+        self.format.light_flag().is_some_and(|flag| self.flags & flag != 0)
+    }
+
+    /// Whether the plugin is flagged as medium, always false for games without medium plugins
+    pub fn is_medium(&self) -> bool {
+        self.format.medium_flag().is_some_and(|flag| self.flags & flag != 0)
+    }
+
+    /// Whether the plugin's strings are stored in separate string files
+    pub fn is_localized(&self) -> bool {
+        self.flags & LOCALIZED_FLAG != 0
+    }
+
+    /// The form version of the header record, always 0 for Oblivion
+    pub fn form_version(&self) -> u16 {
+        self.form_version
+    }
+
+    /// The version of the plugin format, from the `HEDR` subrecord
+    pub fn version(&self) -> f32 {
+        self.version
+    }
+
+    /// The number of records and groups in the plugin
+    pub fn record_count(&self) -> u32 {
+        self.record_count
+    }
+
+    pub fn next_object_id(&self) -> u32 {
+        self.next_object_id
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// The file names of the plugin's masters, in the order they're referenced by FormIDs
+    pub fn masters(&self) -> &[String] {
+        &self.masters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    use crate::game_plugin::header::PluginHeader;
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::record::COMPRESSED_FLAG;
+    use crate::game_plugin::test_util::{header, record, subrecord};
+
+    #[test]
+    fn read_header() {
+        let plugin = header(PluginFormat::SkyrimSE, 0x201, &["Skyrim.esm", "Update.esm"], 12);
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).unwrap();
+
+        assert!(header.is_master());
+        assert!(header.is_light());
+        assert!(!header.is_localized());
+        assert_eq!(header.form_version(), 44);
+        assert_eq!(header.version(), 1.7);
+        assert_eq!(header.record_count(), 12);
+        assert_eq!(header.next_object_id(), 0x800);
+        assert_eq!(header.author(), Some("Author"));
+        assert_eq!(header.description(), None);
+        assert_eq!(header.masters(), ["Skyrim.esm", "Update.esm"]);
+    }
+
+    #[test]
+    fn windows_1252_strings() {
+        let mut data = subrecord(b"HEDR", &[0; 12]);
+        data.extend(subrecord(b"CNAM", b"Caf\xe9 \x80 Author\x92s\0"));
+        let plugin = record(PluginFormat::Oblivion, b"TES4", 0, 0, &data);
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::Oblivion).unwrap();
+
+        assert_eq!(header.author(), Some("Café € Author’s"));
+    }
+
+    #[test]
+    fn per_game_flags() {
+        let plugin = header(PluginFormat::Starfield, 0x300, &[], 0);
+        let starfield = PluginHeader::read(&mut Cursor::new(&plugin), PluginFormat::Starfield).unwrap();
+        assert!(starfield.is_light());
+        assert!(!starfield.is_medium());
+
+        let plugin = header(PluginFormat::Skyrim, 0x280, &[], 0);
+        let skyrim = PluginHeader::read(&mut Cursor::new(&plugin), PluginFormat::Skyrim).unwrap();
+        assert!(!skyrim.is_light());
+        assert!(skyrim.is_localized());
+    }
+
+    #[test]
+    fn oblivion_header() {
+        let plugin = header(PluginFormat::Oblivion, 0, &["Oblivion.esm"], 3);
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::Oblivion).unwrap();
+
+        assert_eq!(header.form_version(), 0);
+        assert_eq!(header.record_count(), 3);
+        assert_eq!(header.masters(), ["Oblivion.esm"]);
+    }
+
+    #[test]
+    fn compressed_header() {
+        let mut data = subrecord(b"HEDR", &[0; 12]);
+        data.extend(subrecord(b"SNAM", b"Compressed\0"));
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let mut compressed = (data.len() as u32).to_le_bytes().to_vec();
+        compressed.extend(encoder.finish().unwrap());
+
+        let plugin = record(PluginFormat::Fallout4, b"TES4", COMPRESSED_FLAG, 0, &compressed);
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::Fallout4).unwrap();
+        assert_eq!(header.description(), Some("Compressed"));
+    }
+
+    #[test]
+    fn large_subrecord() {
+        let mut data = subrecord(b"HEDR", &[0; 12]);
+        data.extend(subrecord(b"XXXX", &11u32.to_le_bytes()));
+        data.extend(b"SNAM\0\0Large text\0");
+
+        let plugin = record(PluginFormat::SkyrimSE, b"TES4", 0, 0, &data);
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).unwrap();
+        assert_eq!(header.description(), Some("Large text"));
+    }
+
+    #[test]
+    fn morrowind_header() {
+        let mut hedr = 1.3f32.to_le_bytes().to_vec();
+        hedr.extend_from_slice(&1u32.to_le_bytes());
+        hedr.extend_from_slice(&[b'A'; 32]);
+        hedr.extend_from_slice(b"Description\0");
+        hedr.resize(296, 0);
+        hedr.extend_from_slice(&7u32.to_le_bytes());
+
+        let mut data = Vec::new();
+        for (kind, subrecord) in [(b"HEDR", hedr.as_slice()), (b"MAST", b"Morrowind.esm\0"), (b"DATA", &[0; 8])] {
+            data.extend_from_slice(kind);
+            data.extend_from_slice(&(subrecord.len() as u32).to_le_bytes());
+            data.extend_from_slice(subrecord);
+        }
+
+        let mut plugin = b"TES3".to_vec();
+        plugin.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        plugin.extend_from_slice(&[0; 8]);
+        plugin.extend(data);
+
+        let header = PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::Morrowind).unwrap();
+        assert!(header.is_master());
+        assert_eq!(header.record_count(), 7);
+        assert_eq!(header.author(), Some("A".repeat(32).as_str()));
+        assert_eq!(header.description(), Some("Description"));
+        assert_eq!(header.masters(), ["Morrowind.esm"]);
+    }
+
+    #[test]
+    fn invalid_plugin() {
+        let plugin = record(PluginFormat::SkyrimSE, b"GRUP", 0, 0, &[]);
+        assert!(PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).is_err());
+
+        let mut plugin = header(PluginFormat::SkyrimSE, 0, &["Skyrim.esm"], 0);
+        plugin.truncate(plugin.len() - 4);
+        assert!(PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).is_err());
+
+        // Sizes far larger than the file fail at the end of the file rather than being allocated
+        let mut plugin = header(PluginFormat::SkyrimSE, 0, &["Skyrim.esm"], 0);
+        plugin[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).is_err());
+
+        let mut data = u32::MAX.to_le_bytes().to_vec();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&subrecord(b"HEDR", &[0; 12])).unwrap();
+        data.extend(encoder.finish().unwrap());
+        let plugin = record(PluginFormat::SkyrimSE, b"TES4", COMPRESSED_FLAG, 44, &data);
+        assert!(PluginHeader::read(&mut Cursor::new(plugin), PluginFormat::SkyrimSE).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use error_chain::bail;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::header::PluginHeader;
+use crate::game_plugin::PluginFormat;
+use crate::game_plugin::record::Records;
+
+/// The offset of the flags in a record header
+const FLAGS_OFFSET: u64 = 8;
+
+/// Whether a plugin can be flagged as light without changing any FormIDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightEligibility {
+    /// Every record the plugin adds fits the light plugin range
+    Eligible { new_records: usize },
+    /// The plugin is already flagged as light
+    AlreadyLight,
+    /// The game doesn't support light plugins
+    Unsupported,
+    /// A record the plugin adds has a FormID outside the light plugin range
+    OutOfRange { form_id: u32 },
+}
+
+impl LightEligibility {
+    pub fn is_eligible(&self) -> bool {
+        matches!(self, LightEligibility::Eligible { .. })
+    }
+}
+
+/// Check whether a plugin file can be flagged as light
+///
+/// # Arguments
+///
+/// * `path`: The path to the plugin
+/// * `format`: The plugin format of the game the plugin is for
+///
+/// returns: Result<LightEligibility, Error>
+pub fn light_eligibility(path: &Path, format: PluginFormat) -> errors::Result<LightEligibility> {
+    check_light_eligibility(BufReader::new(File::open(path)?), format)
+}
+
+/// Check whether a plugin can be flagged as light by reading every record it contains
+///
+/// A record is new if the master index in its FormID is past the plugin's masters, its object index must
+/// then fit the game's light plugin range for the plugin's header version.
+///
+/// # Arguments
+///
+/// * `reader`: The reader, positioned at the start of the plugin
+/// * `format`: The plugin format of the game the plugin is for
+///
+/// returns: Result<LightEligibility, Error>
+pub fn check_light_eligibility<R: Read>(mut reader: R, format: PluginFormat) -> errors::Result<LightEligibility> {
+    let header = PluginHeader::read(&mut reader, format)?;
+    let Some(range) = format.light_object_range(header.version()) else {
+        return Ok(LightEligibility::Unsupported)
+    };
+    if header.is_light() {
+        return Ok(LightEligibility::AlreadyLight)
+    }
+
+    let master_count = header.masters().len() as u32;
+    let mut new_records = 0;
+    for record in Records::new(reader, format, false) {
+        let (record_header, _) = record?;
+        if record_header.form_id >> 24 < master_count {
+            continue;
+        }
+
+        if !range.contains(&(record_header.form_id & 0x00FF_FFFF)) {
+            return Ok(LightEligibility::OutOfRange { form_id: record_header.form_id })
+        }
+        new_records += 1;
+    }
+
+    Ok(LightEligibility::Eligible { new_records })
+}
+
+/// Copy a plugin and set the light flag on the copy
+///
+/// The plugin isn't checked for eligibility, check it with [light_eligibility] first.
+///
+/// # Arguments
+///
+/// * `source`: The plugin to copy
+/// * `dest`: The path to write the light copy to, parent directories are created. It can be `source` to flag the
+///   plugin in place
+/// * `format`: The plugin format of the game the plugin is for
+///
+/// returns: Result<(), Error>
+pub fn copy_as_light(source: &Path, dest: &Path, format: PluginFormat) -> errors::Result<()> {
+    let Some(light_flag) = format.light_flag() else {
+        bail!(ErrorKind::InvalidPlugin("The game doesn't support light plugins".to_string()))
+    };
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write through a temporary file so the plugin can be flagged in place without truncating it first
+    let mut temp_name = dest.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp = dest.with_file_name(temp_name);
+    let result = fs::copy(source, &temp).map_err(errors::Error::from)
+        .and_then(|_| set_flag(&temp, format, light_flag))
+        .and_then(|_| Ok(fs::rename(&temp, dest)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Set a flag in a plugin's header record
+fn set_flag(path: &Path, format: PluginFormat, flag: u32) -> errors::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    PluginHeader::read(&mut BufReader::new(&file), format)?;
+
+    let mut flags = [0u8; 4];
+    file.seek(SeekFrom::Start(FLAGS_OFFSET))?;
+    file.read_exact(&mut flags)?;
+    let flags = u32::from_le_bytes(flags) | flag;
+    file.seek(SeekFrom::Start(FLAGS_OFFSET))?;
+    file.write_all(&flags.to_le_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::game_plugin::header::PluginHeader;
+    use crate::game_plugin::light::{check_light_eligibility, copy_as_light, LightEligibility};
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::test_util::{header, record};
+
+    fn plugin(format: PluginFormat, form_ids: &[u32]) -> Vec<u8> {
+        let mut plugin = header(format, 0, &["Skyrim.esm"], form_ids.len() as u32);
+
+        let mut records = Vec::new();
+        for form_id in form_ids {
+            records.extend(record(format, b"MISC", 0, *form_id, b"EDID\x02\0a\0"));
+        }
+        let mut group = b"GRUP".to_vec();
+        group.extend_from_slice(&(records.len() as u32 + 24).to_le_bytes());
+        group.extend_from_slice(b"MISC");
+        group.extend_from_slice(&[0; 12]);
+        group.extend(records);
+
+        plugin.extend(group);
+        plugin
+    }
+
+    #[test]
+    fn light_eligibility() {
+        let eligible = plugin(PluginFormat::SkyrimSE, &[0x0000_0012, 0x0100_0800, 0x0100_0FFF]);
+        assert_eq!(check_light_eligibility(Cursor::new(eligible), PluginFormat::SkyrimSE).unwrap(),
+            LightEligibility::Eligible { new_records: 2 });
+
+        let out_of_range = plugin(PluginFormat::SkyrimSE, &[0x0100_0800, 0x0100_1000]);
+        assert_eq!(check_light_eligibility(Cursor::new(out_of_range), PluginFormat::SkyrimSE).unwrap(),
+            LightEligibility::OutOfRange { form_id: 0x0100_1000 });
+
+        // The test header uses version 1.7, before the extended range
+        let low = plugin(PluginFormat::SkyrimSE, &[0x0100_0001]);
+        assert_eq!(check_light_eligibility(Cursor::new(&low), PluginFormat::SkyrimSE).unwrap(),
+            LightEligibility::OutOfRange { form_id: 0x0100_0001 });
+        assert!(check_light_eligibility(Cursor::new(&low), PluginFormat::Starfield).unwrap().is_eligible());
+        assert_eq!(check_light_eligibility(Cursor::new(&low), PluginFormat::Skyrim).unwrap(),
+            LightEligibility::Unsupported);
+
+        let mut truncated = plugin(PluginFormat::SkyrimSE, &[0x0100_0800]);
+        truncated.pop();
+        assert!(check_light_eligibility(Cursor::new(truncated), PluginFormat::SkyrimSE).is_err());
+    }
+
+    #[test]
+    fn flag_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("overwrite/Plugin.esp");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let original = plugin(PluginFormat::SkyrimSE, &[0x0100_0800]);
+        fs::write(&path, &original).unwrap();
+
+        copy_as_light(&path, &path, PluginFormat::SkyrimSE).unwrap();
+        let flagged = fs::read(&path).unwrap();
+        assert_eq!(flagged.len(), original.len());
+        assert!(PluginHeader::from_path(&path, PluginFormat::SkyrimSE).unwrap().is_light());
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let copy = dir.path().join("mods/Light/Plugin.esp");
+        copy_as_light(&path, &copy, PluginFormat::SkyrimSE).unwrap();
+        assert_eq!(fs::read(&copy).unwrap(), flagged);
+    }
+}
@@ -0,0 +1,255 @@
+use std::io;
+use std::io::Read;
+
+use encoding_rs::WINDOWS_1252;
+use error_chain::bail;
+use flate2::read::ZlibDecoder;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::PluginFormat;
+
+/// The record flag marking a record's data as zlib compressed
+pub const COMPRESSED_FLAG: u32 = 0x0004_0000;
+
+/// The header at the start of every record
+pub struct RecordHeader {
+    pub kind: [u8; 4],
+    /// The size of the record's data as stored in the file
+    pub data_size: u32,
+    pub flags: u32,
+    pub form_id: u32,
+    /// The form version of the record, always 0 for Oblivion
+    pub form_version: u16,
+}
+
+impl RecordHeader {
+    /// Read a record header
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader, positioned at the start of the record
+    /// * `format`: The plugin format of the game the plugin is for
+    ///
+    /// returns: Result<RecordHeader, Error>
+    pub fn read<R: Read>(reader: &mut R, format: PluginFormat) -> errors::Result<Self> {
+        let mut buffer = [0u8; 24];
+        let buffer = &mut buffer[..format.record_header_size()];
+        reader.read_exact(buffer)?;
+
+        Ok(Self::parse(buffer))
+    }
+
+    /// Parse a record header from a buffer holding exactly a full record header
+    ///
+    /// The 16 byte Morrowind header has no FormID or form version, they're left as 0.
+    pub fn parse(buffer: &[u8]) -> Self {
+        if buffer.len() == 16 {
+            return Self {
+                kind: buffer[0..4].try_into().unwrap(),
+                data_size: read_u32(buffer, 4),
+                flags: read_u32(buffer, 12),
+                form_id: 0,
+                form_version: 0,
+            }
+        }
+
+        Self {
+            kind: buffer[0..4].try_into().unwrap(),
+            data_size: read_u32(buffer, 4),
+            flags: read_u32(buffer, 8),
+            form_id: read_u32(buffer, 12),
+            form_version: if buffer.len() >= 24 { read_u16(buffer, 20) } else { 0 },
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & COMPRESSED_FLAG != 0
+    }
+}
+
+/// Get a record's data, decompressing it if it's compressed
+///
+/// # Arguments
+///
+/// * `header`: The header of the record
+/// * `data`: The record's data as stored in the file
+///
+/// returns: Result<Vec<u8>, Error> The uncompressed data
+pub fn record_data(header: &RecordHeader, data: &[u8]) -> errors::Result<Vec<u8>> {
+    if !header.is_compressed() {
+        return Ok(data.to_vec())
+    }
+
+    if data.len() < 4 {
+        bail!(ErrorKind::InvalidPlugin("Compressed record is too small".to_string()))
+    }
+
+    // Stop one byte past the stated size, enough to tell the record is corrupt without decompressing all of it
+    let size = read_u32(data, 0) as usize;
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(&data[4..]).take(size as u64 + 1).read_to_end(&mut decompressed)?;
+
+    if decompressed.len() != size {
+        bail!(ErrorKind::InvalidPlugin(format!("Compressed record decompressed to {} bytes, expected {size}", decompressed.len())))
+    }
+
+    Ok(decompressed)
+}
+
+/// An iterator over the subrecords in a record's uncompressed data
+///
+/// Handles `XXXX` subrecords, which hold the size of subrecords too large for a 16 bit size.
+pub struct Subrecords<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Subrecords<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+}
+
+impl<'a> Iterator for Subrecords<'a> {
+    type Item = errors::Result<([u8; 4], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut large_size = None;
+
+        loop {
+            if self.position >= self.data.len() {
+                return None
+            }
+
+            if self.position + 6 > self.data.len() {
+                self.position = self.data.len();
+                return Some(Err(ErrorKind::InvalidPlugin("Truncated subrecord header".to_string()).into()))
+            }
+
+            let kind: [u8; 4] = self.data[self.position..self.position + 4].try_into().unwrap();
+            let size = large_size.take()
+                .unwrap_or(read_u16(self.data, self.position + 4) as usize);
+            let start = self.position + 6;
+            let end = start + size;
+
+            if end > self.data.len() {
+                self.position = self.data.len();
+                return Some(Err(ErrorKind::InvalidPlugin(format!("Subrecord {} overruns its record", String::from_utf8_lossy(&kind))).into()))
+            }
+            self.position = end;
+
+            if &kind == b"XXXX" && size == 4 {
+                large_size = Some(read_u32(self.data, start) as usize);
+                continue;
+            }
+
+            return Some(Ok((kind, &self.data[start..end])))
+        }
+    }
+}
+
+/// An iterator over every record in a plugin, including the `TES4` header record
+///
+/// Groups are stepped into rather than returned, so records come out in file order regardless of nesting. A
+/// record's data is only kept if requested. Iteration stops after the first error.
+pub struct Records<R> {
+    reader: R,
+    format: PluginFormat,
+    read_data: bool,
+    done: bool,
+}
+
+impl<R: Read> Records<R> {
+    /// # Arguments
+    ///
+    /// * `reader`: The reader, positioned at the start of the plugin
+    /// * `format`: The plugin format of the game the plugin is for
+    /// * `read_data`: Whether to read each record's data as stored in the file, if false the data is empty
+    pub fn new(reader: R, format: PluginFormat, read_data: bool) -> Self {
+        Self { reader, format, read_data, done: false }
+    }
+
+    fn read_record(&mut self) -> errors::Result<Option<(RecordHeader, Vec<u8>)>> {
+        loop {
+            let mut buffer = [0u8; 24];
+            let buffer = &mut buffer[..self.format.record_header_size()];
+
+            // A clean end of file can only happen between records
+            let read = self.reader.read(&mut buffer[..1])?;
+            if read == 0 {
+                return Ok(None)
+            }
+            self.reader.read_exact(&mut buffer[1..])?;
+
+            let header = RecordHeader::parse(buffer);
+            if &header.kind == b"GRUP" {
+                continue;
+            }
+
+            let data = if self.read_data {
+                read_data(&mut self.reader, header.data_size)?
+            } else {
+                let skipped = io::copy(&mut (&mut self.reader).take(header.data_size as u64), &mut io::sink())?;
+                if skipped != header.data_size as u64 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+                }
+                Vec::new()
+            };
+
+            return Ok(Some((header, data)))
+        }
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = errors::Result<(RecordHeader, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+
+        let record = self.read_record().transpose();
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+/// Read a record's data as stored in the file
+///
+/// The size comes from the record header, so the buffer grows as data is read rather than being allocated up
+/// front, and a corrupt size fails at the end of the file instead of allocating up to 4 GiB.
+pub fn read_data<R: Read>(reader: &mut R, size: u32) -> errors::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(size as u64).read_to_end(&mut data)?;
+    if data.len() != size as usize {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+    }
+
+    Ok(data)
+}
+
+/// Decode a null terminated string from a subrecord
+///
+/// Strings are UTF-8 in newer games and Windows-1252 in older ones, anything that isn't valid UTF-8 is
+/// decoded as Windows-1252.
+pub fn read_zstring(data: &[u8]) -> String {
+    let data = match data.iter().position(|byte| *byte == 0) {
+        Some(end) => &data[..end],
+        None => data
+    };
+
+    match std::str::from_utf8(data) {
+        Ok(string) => string.to_string(),
+        Err(_) => WINDOWS_1252.decode_without_bom_handling(data).0.into_owned()
+    }
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
@@ -1,11 +1,22 @@
+use std::path::PathBuf;
 use std::process::{ExitCode, Termination};
+use std::rc::Rc;
+use std::sync::Arc;
 use gtk::{Application, ApplicationWindow, Button, CustomFilter, CustomSorter, DragSource, DropTarget, FilterChange, FilterListModel, gio, Label, ListItem, ListView, Orientation, PolicyType, ScrolledWindow, SignalListItemFactory, SingleSelection, SorterChange, SortListModel, Widget};
 use gtk::gdk::{ContentProvider, DragAction};
 use gtk::glib;
 use gtk::glib::{clone};
 use gtk::prelude::*;
+use crate::download_manager::DownloadManager;
+use crate::gui_application::download_list::build_download_list;
 use crate::gui_application::integer_object::StringObject;
+use crate::ipc;
+use crate::ipc::DownloadServer;
+use crate::manager_config::ManagerConfig;
+use crate::mod_info::instance::Instance;
+use crate::plugin::plugin_manager::PluginManager;
 
+mod download_list;
 mod integer_object;
 
 const APP_ID: &str = "com.datdeveloper.DatModManager";
@@ -14,13 +25,30 @@ pub fn gui_application() -> ExitCode {
     // Create a new gui_application
     let app = Application::builder().application_id(APP_ID).build();
 
-    app.connect_activate(build_ui);
+    let config = ManagerConfig::load_or_create();
+    let download_manager = Arc::new(DownloadManager::with_limits(config.max_concurrent_downloads, config.download_limits())
+        .with_retry_policy(config.retry_policy()));
+    let mut plugin_manager = PluginManager::default();
+    plugin_manager.register_plugins(&config);
+    let plugin_manager = Rc::new(plugin_manager);
+    let downloads_path = Instance::from_name(&config.default_instance).ok()
+        .map(|instance| instance.downloads_path());
+    // Take the downloads other processes forward, such as links opened from a browser while the window is open
+    let download_server = match ipc::serve_downloads() {
+        Ok(server) => server.map(Rc::new),
+        Err(err) => {
+            eprintln!("Failed to take downloads from other processes, error: {err}");
+            None
+        }
+    };
+
+    app.connect_activate(move |app| build_ui(app, download_manager.clone(), plugin_manager.clone(), downloads_path.clone(), download_server.clone()));
 
     // Run the gui_application
     app.run().report()
 }
 
-pub fn build_ui(app: &Application) {
+pub fn build_ui(app: &Application, download_manager: Arc<DownloadManager>, plugin_manager: Rc<PluginManager>, downloads_path: Option<PathBuf>, download_server: Option<Rc<DownloadServer>>) {
     // Create a `Vec<IntegerObject>` with numbers from 0 to 100_000
     let vector: Vec<StringObject> =
         (0..=100).into_iter().map(|val| {
@@ -135,13 +163,18 @@ pub fn build_ui(app: &Application) {
         .child(&list_view)
         .build();
 
+    scrolled_window.set_vexpand(true);
+    let content = gtk::Box::new(Orientation::Vertical, 12);
+    content.append(&scrolled_window);
+    content.append(&build_download_list(download_manager, plugin_manager, downloads_path, download_server));
+
     // Create a window
     let window = ApplicationWindow::builder()
         .application(app)
         .title("My GTK App")
         .default_width(600)
         .default_height(300)
-        .child(&scrolled_window)
+        .child(&content)
         .build();
 
     // Present the window
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use gtk::{Button, Entry, Label, ListBox, Orientation, ProgressBar, SelectionMode};
+use gtk::glib;
+use gtk::glib::clone;
+use gtk::prelude::*;
+use indicatif::HumanBytes;
+use url::Url;
+use crate::download_manager::{DownloadId, DownloadManager, DownloadState};
+use crate::download_manager::metadata;
+use crate::ipc::DownloadServer;
+use crate::mod_info::instance::Instance;
+use crate::plugin::downloader::parse_source;
+use crate::plugin::plugin_manager::PluginManager;
+
+/// How often the rows check their download for new states
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Build the downloads panel, with an entry to queue a URL and a row for each download
+///
+/// # Arguments
+///
+/// * `download_manager`: The download manager to queue downloads on
+/// * `plugin_manager`: The plugin manager to find a downloader for each URL in
+/// * `downloads_path`: The directory to download into, downloads are disabled if there isn't one
+/// * `download_server`: The server receiving the downloads other processes forward, if this process owns it
+///
+/// returns: gtk::Box
+pub fn build_download_list(download_manager: Arc<DownloadManager>, plugin_manager: Rc<PluginManager>, downloads_path: Option<PathBuf>,
+                           download_server: Option<Rc<DownloadServer>>) -> gtk::Box {
+    let container = gtk::Box::new(Orientation::Vertical, 6);
+
+    let input = gtk::Box::new(Orientation::Horizontal, 6);
+    let entry = Entry::builder()
+        .placeholder_text("URL to download or archive to import")
+        .hexpand(true)
+        .build();
+    let button = Button::with_label("Download");
+    input.append(&entry);
+    input.append(&button);
+
+    let status = Label::new(None);
+    status.set_xalign(0.0);
+
+    let list = ListBox::new();
+    list.set_selection_mode(SelectionMode::None);
+
+    container.append(&input);
+    container.append(&status);
+    container.append(&list);
+
+    if let Some(download_server) = download_server {
+        let default_downloads_path = downloads_path.clone();
+        glib::timeout_add_local(POLL_INTERVAL, clone!(@weak list, @weak status, @strong download_manager, @strong plugin_manager
+            => @default-return glib::Continue(false), move || {
+            if let Some(limits) = download_server.limit_changes().last() {
+                download_manager.set_limits(limits);
+            }
+            for request in download_server.requests() {
+                let downloads_path = match &request.instance {
+                    Some(name) => match Instance::from_name(name) {
+                        Ok(instance) => instance.downloads_path(),
+                        Err(err) => {
+                            status.set_label(&format!("Failed to get instance {name}: {err}"));
+                            continue
+                        }
+                    },
+                    None => match &default_downloads_path {
+                        Some(downloads_path) => downloads_path.clone(),
+                        None => {
+                            status.set_label("No default instance configured to download to");
+                            continue
+                        }
+                    }
+                };
+                queue_download(&list, &status, &download_manager, &plugin_manager, request.url, downloads_path);
+            }
+
+            glib::Continue(true)
+        }));
+    }
+
+    let Some(downloads_path) = downloads_path else {
+        entry.set_sensitive(false);
+        button.set_sensitive(false);
+        status.set_label("No default instance configured to download to");
+        return container
+    };
+
+    // Carry on with the downloads that were unfinished when the manager last closed
+    match metadata::load_all(&downloads_path) {
+        Ok(downloads) => for download in downloads.into_iter().filter(|download| download.status.is_unfinished()) {
+            let Ok(url) = download.url() else {
+                continue
+            };
+            let downloaders = plugin_manager.downloaders_for_url(&url);
+            if downloaders.is_empty() {
+                continue
+            }
+
+            let (sender, receiver) = channel();
+            if let Ok(id) = download_manager.restore(downloaders, download, downloads_path.clone(), sender) {
+                add_download_row(&list, &download_manager, id, &url, receiver);
+            }
+        }
+        Err(err) => status.set_label(&format!("Failed to load unfinished downloads: {err}"))
+    }
+
+    let start_download = clone!(@weak entry, @weak status, @weak list => move || {
+        let url = match parse_source(entry.text().trim()) {
+            Ok(url) => url,
+            Err(err) => {
+                status.set_label(&err);
+                return
+            }
+        };
+
+        if queue_download(&list, &status, &download_manager, &plugin_manager, url, downloads_path.clone()) {
+            entry.set_text("");
+            status.set_label("");
+        }
+    });
+    let start_download = Rc::new(start_download);
+
+    button.connect_clicked(clone!(@strong start_download => move |_| start_download()));
+    entry.connect_activate(move |_| start_download());
+
+    container
+}
+
+/// Queue a download with the downloaders that support its URL and add a row for it
+///
+/// returns: bool Whether the download was queued, the status shows why if it wasn't
+fn queue_download(list: &ListBox, status: &Label, download_manager: &Arc<DownloadManager>, plugin_manager: &PluginManager, url: Url, downloads_path: PathBuf) -> bool {
+    let downloaders = plugin_manager.downloaders_for_url(&url);
+    if downloaders.is_empty() {
+        status.set_label(&format!("No downloader supports {} URLs", url.scheme()));
+        return false
+    }
+
+    let (sender, receiver) = channel();
+    let id = download_manager.download(downloaders, url.clone(), downloads_path, sender);
+    add_download_row(list, download_manager, id, &url, receiver);
+    true
+}
+
+/// Add a row showing a download's progress, with buttons to pause, resume and cancel it
+fn add_download_row(list: &ListBox, download_manager: &Arc<DownloadManager>, id: DownloadId, url: &Url, receiver: Receiver<DownloadState>) {
+    let row = gtk::Box::new(Orientation::Horizontal, 6);
+
+    let name = Label::new(Some(url.as_str()));
+    name.set_xalign(0.0);
+    name.set_hexpand(true);
+    let progress = ProgressBar::new();
+    let state = Label::new(Some("Queued"));
+    let pause = Button::with_label("Pause");
+    let resume = Button::with_label("Resume");
+    let cancel = Button::with_label("Cancel");
+    resume.set_sensitive(false);
+
+    row.append(&name);
+    row.append(&progress);
+    row.append(&state);
+    row.append(&pause);
+    row.append(&resume);
+    row.append(&cancel);
+    list.append(&row);
+
+    pause.connect_clicked(clone!(@strong download_manager => move |_| {
+        let _ = download_manager.pause(id);
+    }));
+    resume.connect_clicked(clone!(@strong download_manager => move |_| {
+        let _ = download_manager.resume(id);
+    }));
+    cancel.connect_clicked(clone!(@strong download_manager => move |_| {
+        let _ = download_manager.cancel(id);
+    }));
+
+    glib::timeout_add_local(POLL_INTERVAL, clone!(@weak progress, @weak state, @weak pause, @weak resume, @weak cancel
+        => @default-return glib::Continue(false), move || {
+        let mut finished = false;
+        while let Ok(download_state) = receiver.try_recv() {
+            let label = match &download_state {
+                DownloadState::Queued => "Queued".to_string(),
+                DownloadState::Connecting => "Connecting".to_string(),
+                DownloadState::Downloading(download_progress) => {
+                    match download_progress.fraction() {
+                        Some(fraction) => progress.set_fraction(fraction),
+                        None => progress.pulse()
+                    }
+                    format!("{}", HumanBytes(download_progress.downloaded))
+                }
+                DownloadState::Retrying { error, delay } => format!("Retrying in {}s: {error}", delay.as_secs_f64().ceil()),
+                DownloadState::Paused => "Paused".to_string(),
+                DownloadState::Completed(_) => {
+                    progress.set_fraction(1.0);
+                    "Completed".to_string()
+                }
+                DownloadState::Failed(err) => format!("Failed: {err}"),
+                DownloadState::Cancelled => "Cancelled".to_string(),
+            };
+            state.set_label(&label);
+
+            let running = matches!(download_state, DownloadState::Queued | DownloadState::Connecting | DownloadState::Downloading(_) | DownloadState::Retrying { .. });
+            pause.set_sensitive(running);
+            resume.set_sensitive(matches!(download_state, DownloadState::Paused | DownloadState::Failed(_)));
+            finished = matches!(download_state, DownloadState::Completed(_) | DownloadState::Cancelled);
+            cancel.set_sensitive(!finished);
+        }
+
+        glib::Continue(!finished)
+    }));
+}
@@ -1 +1,197 @@
-//TODO: Swap out for ZBus
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender, TryIter};
+use url::Url;
+use zbus::{dbus_interface, dbus_proxy, fdo};
+use zbus::blocking::{Connection, ConnectionBuilder};
+use zbus::blocking::fdo::DBusProxy;
+use zbus::names::BusName;
+use crate::download_manager::limits::DownloadLimits;
+use crate::errors;
+
+/// The well known name the first running manager owns on the session bus
+///
+/// This is separate from the GUI's application ID, which GTK owns for itself.
+pub const BUS_NAME: &str = "com.datdeveloper.DatModManager.Downloads";
+/// The path the download service is served at
+pub const OBJECT_PATH: &str = "/com/datdeveloper/DatModManager/Downloads";
+
+/// A download another process asked the running manager to queue
+#[derive(Debug)]
+pub struct DownloadRequest {
+    pub url: Url,
+    /// The instance to download to, the running manager's default if not given
+    pub instance: Option<String>,
+}
+
+/// The D-Bus object other processes queue downloads through
+struct DownloadService {
+    /// Taken when the server stops, so a download is only acknowledged if it will be read
+    sender: Arc<Mutex<Option<Sender<DownloadRequest>>>>,
+    limits_sender: Mutex<Sender<DownloadLimits>>,
+}
+
+#[dbus_interface(name = "com.datdeveloper.DatModManager.Downloads1")]
+impl DownloadService {
+    /// Queue a download, an empty instance downloads to the default instance
+    fn download(&self, url: &str, instance: &str) -> fdo::Result<()> {
+        let url = Url::parse(url).map_err(|err| fdo::Error::InvalidArgs(format!("Invalid URL {url}: {err}")))?;
+        let instance = (!instance.is_empty()).then(|| instance.to_string());
+
+        self.sender.lock().unwrap().as_ref()
+            .and_then(|sender| sender.send(DownloadRequest { url, instance }).ok())
+            .ok_or_else(|| fdo::Error::Failed("The manager is no longer taking downloads".to_string()))
+    }
+
+    /// Change the limits of the running manager's downloads, 0 is unlimited
+    fn set_limits(&self, speed: u64, per_download_speed: u64, per_host: u32) -> fdo::Result<()> {
+        let limits = DownloadLimits {
+            speed: (speed > 0).then_some(speed),
+            per_download_speed: (per_download_speed > 0).then_some(per_download_speed),
+            per_host: (per_host > 0).then_some(per_host as usize),
+        };
+
+        self.limits_sender.lock().unwrap().send(limits)
+            .map_err(|_| fdo::Error::Failed("The manager is no longer taking downloads".to_string()))
+    }
+}
+
+#[dbus_proxy(
+    interface = "com.datdeveloper.DatModManager.Downloads1",
+    default_service = "com.datdeveloper.DatModManager.Downloads",
+    default_path = "/com/datdeveloper/DatModManager/Downloads"
+)]
+trait Downloads {
+    fn download(&self, url: &str, instance: &str) -> zbus::Result<()>;
+
+    fn set_limits(&self, speed: u64, per_download_speed: u64, per_host: u32) -> zbus::Result<()>;
+}
+
+/// Ownership of the bus name, receiving the downloads other processes forward until it's dropped
+pub struct DownloadServer {
+    connection: Connection,
+    sender: Arc<Mutex<Option<Sender<DownloadRequest>>>>,
+    requests: Receiver<DownloadRequest>,
+    limits: Receiver<DownloadLimits>,
+}
+
+impl DownloadServer {
+    /// Get the downloads that have been forwarded since this was last called, without waiting for more
+    pub fn requests(&self) -> TryIter<'_, DownloadRequest> {
+        self.requests.try_iter()
+    }
+
+    /// Stop taking downloads, giving up the bus name so the next process to download serves instead
+    ///
+    /// Call this before the last download finishes rather than relying on dropping the server, otherwise a download
+    /// forwarded in between is acknowledged but never read.
+    ///
+    /// returns: Vec<DownloadRequest> The downloads forwarded since [DownloadServer::requests] was last called,
+    /// there are no more after these
+    pub fn stop(&self) -> Vec<DownloadRequest> {
+        if self.sender.lock().unwrap().is_some() {
+            if let Err(err) = self.connection.release_name(BUS_NAME) {
+                eprintln!("Failed to release {BUS_NAME}, error: {err}");
+            }
+        }
+        // Downloads forwarded from here on are refused instead of acknowledged
+        self.sender.lock().unwrap().take();
+        self.requests.try_iter().collect()
+    }
+
+    /// Get the download limits other processes have set since this was last called, the last is the current one
+    pub fn limit_changes(&self) -> TryIter<'_, DownloadLimits> {
+        self.limits.try_iter()
+    }
+}
+
+/// Become the process other processes forward their downloads to
+///
+/// returns: Result<Option<DownloadServer>, Error> The server, or None if another process is already running one.
+/// Fails if there's no session bus to serve on
+pub fn serve_downloads() -> errors::Result<Option<DownloadServer>> {
+    let (sender, requests) = channel();
+    let (limits_sender, limits) = channel();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let service = DownloadService { sender: sender.clone(), limits_sender: Mutex::new(limits_sender) };
+    let connection = ConnectionBuilder::session()?
+        .serve_at(OBJECT_PATH, service)?
+        .name(BUS_NAME)?
+        .build();
+
+    match connection {
+        Ok(connection) => Ok(Some(DownloadServer { connection, sender, requests, limits })),
+        Err(zbus::Error::NameTaken) => Ok(None),
+        Err(err) => Err(err.into())
+    }
+}
+
+/// Ask the process running the download server to queue a download
+///
+/// # Arguments
+///
+/// * `url`: The URL to download
+/// * `instance`: The instance to download to, the running manager's default if not given
+///
+/// returns: Result<(), Error>
+pub fn forward_download(url: &Url, instance: Option<&str>) -> errors::Result<()> {
+    let connection = Connection::session()?;
+    DownloadsProxyBlocking::new(&connection)?.download(url.as_str(), instance.unwrap_or_default())?;
+    Ok(())
+}
+
+/// Change the download limits of the process running the download server, if there is one
+///
+/// returns: Result<bool, Error> Whether a process was running the download server
+pub fn forward_limits(limits: DownloadLimits) -> errors::Result<bool> {
+    let connection = Connection::session()?;
+    let bus_name = BusName::try_from(BUS_NAME).map_err(zbus::Error::from)?;
+    if !DBusProxy::new(&connection)?.name_has_owner(bus_name).map_err(zbus::Error::from)? {
+        return Ok(false)
+    }
+
+    let per_host = limits.per_host.map_or(0, |per_host| u32::try_from(per_host).unwrap_or(u32::MAX));
+    DownloadsProxyBlocking::new(&connection)?
+        .set_limits(limits.speed.unwrap_or(0), limits.per_download_speed.unwrap_or(0), per_host)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+
+    use zbus::fdo;
+
+    use crate::download_manager::limits::DownloadLimits;
+    use crate::ipc::DownloadService;
+
+    #[test]
+    fn download_service() {
+        let (sender, requests) = channel();
+        let (limits_sender, limits) = channel();
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let service = DownloadService { sender: sender.clone(), limits_sender: Mutex::new(limits_sender) };
+
+        service.download("https://example.com/mod.zip", "").unwrap();
+        service.download("nxm://skyrim/mods/1/files/2", "Skyrim").unwrap();
+        assert!(matches!(service.download("not a url", ""), Err(fdo::Error::InvalidArgs(_))));
+
+        let received: Vec<(String, Option<String>)> = requests.try_iter()
+            .map(|request| (request.url.to_string(), request.instance))
+            .collect();
+        assert_eq!(received, [
+            ("https://example.com/mod.zip".to_string(), None),
+            ("nxm://skyrim/mods/1/files/2".to_string(), Some("Skyrim".to_string())),
+        ]);
+
+        service.set_limits(100, 0, 2).unwrap();
+        assert_eq!(limits.try_recv().unwrap(), DownloadLimits { speed: Some(100), per_download_speed: None, per_host: Some(2) });
+
+        // Once stopped, a download is refused rather than acknowledged and lost
+        service.download("https://example.com/last.zip", "").unwrap();
+        sender.lock().unwrap().take();
+        assert!(matches!(service.download("https://example.com/late.zip", ""), Err(fdo::Error::Failed(_))));
+        let remaining: Vec<String> = requests.try_iter().map(|request| request.url.to_string()).collect();
+        assert_eq!(remaining, ["https://example.com/last.zip"]);
+    }
+}
@@ -1,25 +1,84 @@
-use std::process::Command;
-
-fn run_exe() {
-    let mut child = match Command::new("/bin/unshare")
-        .current_dir("/media/games/SteamLibrary/steamapps/common/Fallout 4/")
-        .env("STEAM_COMPAT_DATA_PATH","/media/games/SteamLibrary/steamapps/compatdata/377160")
-        .env("STEAM_COMPAT_CLIENT_INSTALL_PATH", "/home/jacob/.steam/steam")
-        .env("PROTON_DUMP_DEBUG_COMMANDS","1")
-        .arg("-rm")
-        .arg("/bin/sh")
-        .arg("-c")
-        .arg("/home/jacob/.local/share/Steam/ubuntu12_32/reaper SteamLaunch AppId=377160 -- /home/jacob/.local/share/Steam/compatibilitytools.d/GE-Proton7-49/proton waitforexitandrun \"/media/games/SteamLibrary/steamapps/common/Fallout 4/f4se_loader.exe\"")
-        .spawn() {
-        Ok(child) => child,
-        Err(error) => {
-            eprint!("an error occured: {error}");
-            return;
-        }
-    };
-
-    let ecode = child.wait()
-        .expect("failed to wait on child");
-
-    assert!(ecode.success());
-}
\ No newline at end of file
+use std::process::{Command, ExitStatus};
+
+use crate::errors;
+use crate::load_order::LoadOrder;
+use crate::load_order::validation::{validate, validate_dirty_plugins, ValidationIssue};
+use crate::manager_config::ManagerConfig;
+use crate::mod_info::instance::Instance;
+
+/// Check the load order of an instance's active profile before launching the game
+///
+/// The active plugins are also scanned for dirty records if `scan_dirty_plugins_before_launch` is set.
+///
+/// # Arguments
+///
+/// * `instance`: The instance being launched
+/// * `config`: The manager config, deciding which checks run
+///
+/// returns: Result<Vec<ValidationIssue>, Error> The issues found, in load order
+pub fn check_load_order(instance: &Instance, config: &ManagerConfig) -> errors::Result<Vec<ValidationIssue>> {
+    let load_order = LoadOrder::load(instance)?;
+    let mut issues = validate(instance, &load_order)?;
+    if config.scan_dirty_plugins_before_launch {
+        issues.extend(validate_dirty_plugins(instance, &load_order)?);
+    }
+
+    Ok(issues)
+}
+
+/// Whether the issues found before launching should stop the game from launching
+///
+/// Only fatal issues block launching, and only if `block_launch_on_invalid_load_order` is set.
+///
+/// # Arguments
+///
+/// * `config`: The manager config
+/// * `issues`: The issues found by [check_load_order]
+///
+/// returns: bool True if the game shouldn't be launched
+pub fn blocks_launch(config: &ManagerConfig, issues: &[ValidationIssue]) -> bool {
+    config.block_launch_on_invalid_load_order && issues.iter().any(ValidationIssue::is_fatal)
+}
+
+/// Run a program from the game directory and wait for it to exit
+///
+/// # Arguments
+///
+/// * `instance`: The instance whose game directory the program runs in
+/// * `program`: The program to run, such as the game's executable or a launcher
+/// * `args`: The arguments to pass to the program
+///
+/// returns: Result<ExitStatus, Error> The exit status of the program
+pub fn run_exe(instance: &Instance, program: &str, args: &[String]) -> errors::Result<ExitStatus> {
+    let mut child = Command::new(program)
+        .current_dir(instance.game_path())
+        .args(args)
+        .spawn()?;
+
+    Ok(child.wait()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::launch_file::blocks_launch;
+    use crate::load_order::validation::ValidationIssue;
+    use crate::manager_config::ManagerConfig;
+
+    fn dirty(deleted_navmeshes: usize) -> ValidationIssue {
+        ValidationIssue::DirtyPlugin { plugin: "Dirty.esp".to_string(), identical_to_master: 3, deleted_references: 2, deleted_navmeshes }
+    }
+
+    #[test]
+    fn only_fatal_issues_block() {
+        let mut config = ManagerConfig { block_launch_on_invalid_load_order: true, ..ManagerConfig::default() };
+        let missing = ValidationIssue::MissingMaster { plugin: "A.esp".to_string(), master: "B.esm".to_string() };
+
+        assert!(!blocks_launch(&config, &[]));
+        assert!(!blocks_launch(&config, &[dirty(0)]));
+        assert!(blocks_launch(&config, &[dirty(0), dirty(1)]));
+        assert!(blocks_launch(&config, &[missing]));
+
+        config.block_launch_on_invalid_load_order = false;
+        assert!(!blocks_launch(&config, &[dirty(1)]));
+    }
+}
@@ -1,12 +1,19 @@
+#![recursion_limit = "256"]
+
 pub mod constants;
 pub mod errors;
 pub mod util;
 
 pub mod ipc;
+pub mod url_handler;
 pub mod manager_config;
 pub mod download_manager;
 pub mod plugin;
 pub mod mod_info;
+pub mod conflicts;
+pub mod deployment;
+pub mod game_plugin;
+pub mod load_order;
 pub mod gui_application;
 
-mod launch_file;
+pub mod launch_file;
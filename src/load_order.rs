@@ -0,0 +1,605 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::conflicts::{ConflictMap, Namespace};
+use crate::deployment::DeploymentManifest;
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::{is_plugin_file, PluginFormat};
+use crate::game_plugin::header::PluginHeader;
+use crate::load_order::morrowind::{MORROWIND_INI, OPENMW_CFG, OpenMwConfig};
+use crate::mod_info::game::{Game, get_game};
+use crate::mod_info::instance::Instance;
+
+pub mod dirty_records;
+pub mod masterlist;
+pub mod morrowind;
+pub mod record_conflicts;
+pub mod snapshot;
+pub mod sorting;
+pub mod validation;
+
+const PLUGINS_FILE: &str = "plugins.txt";
+const LOAD_ORDER_FILE: &str = "loadorder.txt";
+/// The gap between the modification times of consecutive plugins for games using timestamps
+const TIMESTAMP_SPACING: Duration = Duration::from_secs(60);
+
+/// How a game stores its load order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadOrderMethod {
+    /// `plugins.txt` lists every plugin in load order, with active plugins prefixed with `*`
+    Asterisk,
+    /// `loadorder.txt` lists every plugin in load order, `plugins.txt` lists the active plugins
+    Textfile,
+    /// Plugins load in order of their file modification times, `plugins.txt` lists the active plugins
+    ///
+    /// The profile keeps a `loadorder.txt` as well, but the modification times are what the game uses.
+    Timestamp,
+    /// Morrowind lists the active plugins in the `[Game Files]` section of `Morrowind.ini`, but still loads them
+    /// in order of their modification times
+    MorrowindIni,
+    /// OpenMW lists the active plugins in load order as `content=` lines in `openmw.cfg`
+    OpenMw,
+}
+
+impl LoadOrderMethod {
+    /// Whether the game loads plugins in order of their file modification times
+    pub fn uses_timestamps(&self) -> bool {
+        matches!(self, LoadOrderMethod::Timestamp | LoadOrderMethod::MorrowindIni)
+    }
+}
+
+/* ========================================= */
+/* Visible Plugins                           */
+/* ========================================= */
+
+/// A plugin the game can see, either from an enabled mod or installed with the game
+pub struct VisiblePlugin {
+    pub name: String,
+    /// The path to the plugin file
+    pub path: PathBuf,
+    /// The mod providing the plugin, None if it's installed with the game
+    pub mod_name: Option<String>,
+}
+
+/// Get every plugin the game would see if the instance's active profile was deployed
+///
+/// Plugins in enabled mods come from the winning mod, plugins in the game's data directory that weren't
+/// put there by deployment are treated as installed with the game.
+pub fn visible_plugins(instance: &Instance) -> errors::Result<Vec<VisiblePlugin>> {
+    let mut plugins = Vec::new();
+    let mut seen = HashSet::new();
+
+    let conflict_map = ConflictMap::for_instance(instance)?;
+    for file in conflict_map.winning_files() {
+        let name = file.path.to_string_lossy().to_string();
+        if file.namespace != Namespace::Data || file.path.components().count() != 1 || !is_plugin_file(&name) {
+            continue;
+        }
+
+        seen.insert(name.to_lowercase());
+        plugins.push(VisiblePlugin { name, path: file.source, mod_name: Some(file.mod_name.to_string()) });
+    }
+
+    let data_path = instance.data_path()?;
+    let deployed: HashSet<PathBuf> = DeploymentManifest::load(instance)?.files().iter()
+        .map(|file| instance.game_path().join(&file.target))
+        .collect();
+    if data_path.exists() {
+        for entry in fs::read_dir(&data_path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !is_plugin_file(&name) || deployed.contains(&entry.path()) || !seen.insert(name.to_lowercase()) {
+                continue;
+            }
+
+            plugins.push(VisiblePlugin { name, path: entry.path(), mod_name: None });
+        }
+    }
+
+    Ok(plugins)
+}
+
+/// Get the plugins the game always loads, in the order it loads them
+///
+/// These are the game's official masters followed by the plugins listed in its Creation Club file.
+pub fn implicit_plugins(instance: &Instance, game: &Game) -> errors::Result<Vec<String>> {
+    let mut plugins = game.official_masters().to_vec();
+
+    if let Some(ccc_file) = game.ccc_file() {
+        match fs::read_to_string(instance.game_path().join(ccc_file)) {
+            Ok(content) => plugins.extend(content.lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into())
+        }
+    }
+
+    Ok(plugins)
+}
+
+/* ========================================= */
+/* Load Order                                */
+/* ========================================= */
+
+/// A plugin in the load order
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadOrderEntry {
+    name: String,
+    active: bool,
+}
+
+impl LoadOrderEntry {
+    pub fn new(name: &str, active: bool) -> Self {
+        Self { name: name.to_string(), active }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+}
+
+/// The order plugins are loaded in for a profile, and which of them are active
+pub struct LoadOrder {
+    method: LoadOrderMethod,
+    plugins: Vec<LoadOrderEntry>,
+    /// The number of plugins at the start of the load order the game always loads
+    implicit_count: usize,
+}
+
+impl LoadOrder {
+    /// Load the load order of the instance's active profile
+    ///
+    /// The load order is updated to match the plugins the game can see: plugins that have gone are removed,
+    /// new plugins are added inactive at the end, and the plugins the game always loads are moved to the
+    /// start in the game's order.
+    pub fn load(instance: &Instance) -> errors::Result<Self> {
+        let game = get_game(instance.game())?;
+        let profile_path = instance.profile_path();
+
+        let method = game.load_order_method();
+        let mut load_order = match Self::read_native(instance, method)? {
+            // A new profile starts with the game's own load order
+            Some(load_order) if !profile_path.join(LOAD_ORDER_FILE).exists() => load_order,
+            _ => Self::read(&profile_path, method)?
+        };
+
+        let visible = visible_plugins(instance)?;
+        let names: Vec<String> = visible.iter().map(|plugin| plugin.name.clone()).collect();
+        load_order.refresh(&names, &implicit_plugins(instance, game)?);
+
+        if method.uses_timestamps() {
+            load_order.sort_by_timestamps(&visible, game.plugin_format())?;
+        }
+
+        Ok(load_order)
+    }
+
+    /// Read a load order from the game's native files in a directory
+    ///
+    /// Missing files are treated as empty.
+    pub fn read(dir: &Path, method: LoadOrderMethod) -> errors::Result<Self> {
+        let plugins_lines = read_lines(&dir.join(PLUGINS_FILE))?;
+
+        let plugins = match method {
+            LoadOrderMethod::Asterisk => plugins_lines.iter()
+                .map(|line| match line.strip_prefix('*') {
+                    Some(name) => LoadOrderEntry::new(name, true),
+                    None => LoadOrderEntry::new(line, false)
+                })
+                .collect(),
+            _ => {
+                let active: HashSet<String> = plugins_lines.iter().map(|line| line.to_lowercase()).collect();
+                read_lines(&dir.join(LOAD_ORDER_FILE))?.iter()
+                    .map(|name| LoadOrderEntry::new(name, active.contains(&name.to_lowercase())))
+                    .collect()
+            }
+        };
+
+        Ok(Self { method, plugins, implicit_count: 0 })
+    }
+
+    /// Save the load order to the instance's active profile, and the game's app data directory if the instance
+    /// has one
+    ///
+    /// For games using timestamps the modification times of the plugin files are rewritten as well. Morrowind's
+    /// active plugins are written to `Morrowind.ini` in the game directory, and OpenMW's to `openmw.cfg` in the
+    /// app data directory instead of `plugins.txt`.
+    pub fn save(&self, instance: &Instance) -> errors::Result<()> {
+        self.write(&instance.profile_path())?;
+
+        let active: Vec<&str> = self.active_plugins().map(|entry| entry.name()).collect();
+        match self.method {
+            LoadOrderMethod::MorrowindIni => {
+                morrowind::write_game_files(&instance.game_path().join(MORROWIND_INI), &active)?;
+            }
+            LoadOrderMethod::OpenMw => {
+                let Some(app_data_path) = instance.app_data_path() else {
+                    bail!(ErrorKind::NoAppDataPath)
+                };
+                let config_path = app_data_path.join(OPENMW_CFG);
+                let mut config = OpenMwConfig::load(&config_path)?;
+                config.set_content(&active);
+                config.save(&config_path)?;
+            }
+            _ => if let Some(app_data_path) = instance.app_data_path() {
+                fs::create_dir_all(app_data_path)?;
+                self.write(app_data_path)?;
+            }
+        }
+
+        if self.method.uses_timestamps() {
+            self.write_timestamps(&visible_plugins(instance)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the active plugins from the game's own settings for games that don't use `plugins.txt`
+    ///
+    /// returns: Result<Option<LoadOrder>, Error> The load order, or None if the game uses `plugins.txt` or its
+    /// settings can't be found
+    fn read_native(instance: &Instance, method: LoadOrderMethod) -> errors::Result<Option<Self>> {
+        let active = match (method, instance.app_data_path()) {
+            (LoadOrderMethod::MorrowindIni, _) => morrowind::read_game_files(&instance.game_path().join(MORROWIND_INI))?,
+            (LoadOrderMethod::OpenMw, Some(app_data_path)) => OpenMwConfig::load(&app_data_path.join(OPENMW_CFG))?.content(),
+            _ => return Ok(None)
+        };
+
+        let plugins = active.iter().map(|name| LoadOrderEntry::new(name, true)).collect();
+        Ok(Some(Self { method, plugins, implicit_count: 0 }))
+    }
+
+    /// Order the plugins after the ones the game always loads by their file modification times
+    ///
+    /// Masters are put before the other plugins whatever their times, as the game loads them first. Plugins with
+    /// the same time keep their current order, and entries without a file keep their positions.
+    ///
+    /// # Arguments
+    ///
+    /// * `plugins`: The plugin files
+    /// * `format`: The plugin format of the game, used to read which plugins are masters
+    ///
+    /// returns: Result<(), Error>
+    pub fn sort_by_timestamps(&mut self, plugins: &[VisiblePlugin], format: PluginFormat) -> errors::Result<()> {
+        let mut keys = HashMap::new();
+        for plugin in plugins {
+            // Plugins that can't be read are sorted with the non-masters, validation reports them
+            let is_master = PluginHeader::from_path(&plugin.path, format).is_ok_and(|header| header.is_master());
+            keys.insert(plugin.name.to_lowercase(), (!is_master, fs::metadata(&plugin.path)?.modified()?));
+        }
+
+        // Only the entries with a file are sorted, among the positions they already take up
+        let entries = &mut self.plugins[self.implicit_count..];
+        let positions: Vec<usize> = (0..entries.len())
+            .filter(|index| keys.contains_key(&entries[*index].name.to_lowercase()))
+            .collect();
+        let mut sorted: Vec<LoadOrderEntry> = positions.iter().map(|index| entries[*index].clone()).collect();
+        sorted.sort_by_key(|entry| keys[&entry.name.to_lowercase()]);
+        for (position, entry) in positions.into_iter().zip(sorted) {
+            entries[position] = entry;
+        }
+
+        Ok(())
+    }
+
+    /// Set the modification times of plugin files to match the load order
+    ///
+    /// The plugins the game always loads keep their times, the other plugins are spaced a minute apart starting
+    /// a minute after the latest of them. Deployed plugins are links to the files in the mods, so they change
+    /// too.
+    ///
+    /// # Arguments
+    ///
+    /// * `plugins`: The plugin files, plugins in the load order without a file are skipped
+    ///
+    /// returns: Result<(), Error>
+    pub fn write_timestamps(&self, plugins: &[VisiblePlugin]) -> errors::Result<()> {
+        let paths: HashMap<String, &Path> = plugins.iter()
+            .map(|plugin| (plugin.name.to_lowercase(), plugin.path.as_path()))
+            .collect();
+        let path_of = |entry: &LoadOrderEntry| paths.get(&entry.name.to_lowercase()).copied();
+
+        let mut base = None;
+        for entry in &self.plugins[..self.implicit_count] {
+            if let Some(path) = path_of(entry) {
+                let modified = fs::metadata(path)?.modified()?;
+                base = base.max(Some(modified));
+            }
+        }
+        let mut time = match base {
+            Some(base) => base + TIMESTAMP_SPACING,
+            None => {
+                // Without implicit plugins, start from the earliest time so the sequence doesn't drift forward
+                let mut earliest = None;
+                for path in self.plugins.iter().filter_map(path_of) {
+                    let modified = fs::metadata(path)?.modified()?;
+                    earliest = Some(earliest.map_or(modified, |earliest: SystemTime| earliest.min(modified)));
+                }
+                earliest.unwrap_or_else(SystemTime::now)
+            }
+        };
+
+        for entry in &self.plugins[self.implicit_count..] {
+            if let Some(path) = path_of(entry) {
+                File::options().write(true).open(path)?.set_modified(time)?;
+                time += TIMESTAMP_SPACING;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the load order to the game's native files in a directory
+    ///
+    /// The plugins the game always loads are left out of `plugins.txt`, `loadorder.txt` lists every plugin.
+    pub fn write(&self, dir: &Path) -> errors::Result<()> {
+        let listed = &self.plugins[self.implicit_count..];
+
+        let plugins_content: String = match self.method {
+            LoadOrderMethod::Asterisk => listed.iter()
+                .map(|entry| format!("{}{}\r\n", if entry.active { "*" } else { "" }, entry.name))
+                .collect(),
+            _ => listed.iter()
+                .filter(|entry| entry.active)
+                .map(|entry| format!("{}\r\n", entry.name))
+                .collect()
+        };
+        fs::write(dir.join(PLUGINS_FILE), format!("# This file is used by the game to keep track of your downloaded content.\r\n{plugins_content}"))?;
+
+        let load_order_content: String = self.plugins.iter()
+            .map(|entry| format!("{}\r\n", entry.name))
+            .collect();
+        fs::write(dir.join(LOAD_ORDER_FILE), load_order_content)?;
+
+        Ok(())
+    }
+
+    fn refresh(&mut self, visible: &[String], implicit: &[String]) {
+        let visible_names: HashSet<String> = visible.iter().map(|name| name.to_lowercase()).collect();
+        self.plugins.retain(|entry| visible_names.contains(&entry.name.to_lowercase()));
+
+        // Use the case of the file on disk
+        for entry in self.plugins.iter_mut() {
+            if let Some(name) = visible.iter().find(|name| name.eq_ignore_ascii_case(&entry.name)) {
+                entry.name = name.clone();
+            }
+        }
+
+        for name in visible {
+            if self.position(name).is_none() {
+                self.plugins.push(LoadOrderEntry::new(name, false));
+            }
+        }
+
+        let mut implicit_entries = Vec::new();
+        for name in implicit {
+            if let Some(position) = self.position(name) {
+                let mut entry = self.plugins.remove(position);
+                entry.active = true;
+                implicit_entries.push(entry);
+            }
+        }
+
+        self.implicit_count = implicit_entries.len();
+        implicit_entries.append(&mut self.plugins);
+        self.plugins = implicit_entries;
+    }
+
+    pub fn method(&self) -> LoadOrderMethod {
+        self.method
+    }
+
+    /// Get the plugins in load order
+    pub fn plugins(&self) -> &[LoadOrderEntry] {
+        &self.plugins
+    }
+
+    /// Get the active plugins in load order
+    pub fn active_plugins(&self) -> impl Iterator<Item = &LoadOrderEntry> {
+        self.plugins.iter().filter(|entry| entry.active)
+    }
+
+    /// Get the position of a plugin in the load order, plugin names are compared case-insensitively
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.plugins.iter().position(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the game always loads a plugin, and so it can't be deactivated or moved
+    pub fn is_implicit(&self, name: &str) -> bool {
+        self.position(name).is_some_and(|position| position < self.implicit_count)
+    }
+
+    /// Activate or deactivate a plugin
+    pub fn set_active(&mut self, name: &str, active: bool) -> errors::Result<()> {
+        let Some(position) = self.position(name) else {
+            bail!(ErrorKind::UnknownPlugin(name.to_string()))
+        };
+        if position < self.implicit_count {
+            bail!(ErrorKind::ImplicitPlugin(name.to_string()))
+        }
+
+        self.plugins[position].active = active;
+        Ok(())
+    }
+
+    /// Rearrange the plugins after the ones the game always loads
+    ///
+    /// # Arguments
+    ///
+    /// * `order`: The new order, as indexes into the plugins after the ones the game always loads
+    pub(crate) fn reorder(&mut self, order: &[usize]) {
+        let sortable = self.plugins.split_off(self.implicit_count);
+        self.plugins.extend(order.iter().map(|index| sortable[*index].clone()));
+    }
+
+    /// Put the plugins in the order of a saved list and copy their active flags
+    ///
+    /// Plugins in the list that aren't in the load order are skipped, plugins missing from the list are left
+    /// inactive at the end. The plugins the game always loads stay first and active.
+    pub(crate) fn restore_entries(&mut self, entries: &[LoadOrderEntry]) {
+        let mut remaining = self.plugins.split_off(self.implicit_count);
+        for saved in entries {
+            if let Some(position) = remaining.iter().position(|entry| entry.name.eq_ignore_ascii_case(&saved.name)) {
+                let mut entry = remaining.remove(position);
+                entry.active = saved.active;
+                self.plugins.push(entry);
+            }
+        }
+
+        for mut entry in remaining {
+            entry.active = false;
+            self.plugins.push(entry);
+        }
+    }
+
+    /// Move a plugin to a new position in the load order
+    ///
+    /// Plugins can't be moved before the plugins the game always loads, positions before them are clamped.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the plugin to move
+    /// * `position`: The position to move the plugin to
+    ///
+    /// returns: Result<(), Error>
+    pub fn move_plugin(&mut self, name: &str, position: usize) -> errors::Result<()> {
+        let Some(current) = self.position(name) else {
+            bail!(ErrorKind::UnknownPlugin(name.to_string()))
+        };
+        if current < self.implicit_count {
+            bail!(ErrorKind::ImplicitPlugin(name.to_string()))
+        }
+
+        let entry = self.plugins.remove(current);
+        let position = position.clamp(self.implicit_count, self.plugins.len());
+        self.plugins.insert(position, entry);
+
+        Ok(())
+    }
+}
+
+/// Read the lines of a native load order file, skipping comments and blank lines
+fn read_lines(path: &Path) -> errors::Result<Vec<String>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into())
+    };
+
+    Ok(String::from_utf8_lossy(&content).lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use crate::game_plugin::header::MASTER_FLAG;
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::test_util::header;
+    use crate::load_order::{LoadOrder, LoadOrderEntry, LoadOrderMethod, VisiblePlugin};
+
+    fn load_order(method: LoadOrderMethod, plugins: &[(&str, bool)]) -> LoadOrder {
+        LoadOrder {
+            method,
+            plugins: plugins.iter().map(|(name, active)| LoadOrderEntry::new(name, *active)).collect(),
+            implicit_count: 0,
+        }
+    }
+
+    #[test]
+    fn refresh_keeps_official_masters_first() {
+        let mut order = load_order(LoadOrderMethod::Asterisk, &[("b.esp", true), ("Update.esm", false), ("gone.esp", true), ("Skyrim.esm", false)]);
+        let visible = ["skyrim.esm", "a.esp", "B.esp", "Update.esm"].map(String::from);
+        order.refresh(&visible, &["Skyrim.esm".to_string(), "Update.esm".to_string(), "Dawnguard.esm".to_string()]);
+
+        let names: Vec<&str> = order.plugins().iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["skyrim.esm", "Update.esm", "B.esp", "a.esp"]);
+        assert!(order.plugins()[0].active() && order.plugins()[1].active() && order.plugins()[2].active());
+        assert!(!order.plugins()[3].active());
+
+        assert!(order.set_active("Skyrim.esm", false).is_err());
+        assert!(order.move_plugin("update.esm", 3).is_err());
+        order.move_plugin("a.esp", 0).unwrap();
+        assert_eq!(order.position("a.esp"), Some(2));
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let names = ["Oblivion.esm", "c.esm", "b.esp", "a.esp"];
+        let plugins: Vec<VisiblePlugin> = names.iter()
+            .map(|name| {
+                let path = dir.join(name);
+                let flags = if name.ends_with(".esm") { MASTER_FLAG } else { 0 };
+                std::fs::write(&path, header(PluginFormat::Oblivion, flags, &[], 0)).unwrap();
+                VisiblePlugin { name: name.to_string(), path, mod_name: None }
+            })
+            .collect();
+
+        let mut order = load_order(LoadOrderMethod::Timestamp, &names.map(|name| (name, true)));
+        order.implicit_count = 1;
+        order.write_timestamps(&plugins).unwrap();
+
+        let mut read = load_order(LoadOrderMethod::Timestamp, &[("Oblivion.esm", true), ("a.esp", true), ("c.esm", true), ("b.esp", true)]);
+        read.implicit_count = 1;
+        read.sort_by_timestamps(&plugins, PluginFormat::Oblivion).unwrap();
+        assert_eq!(read.plugins(), order.plugins());
+
+        // Masters load first even if they're newer than the other plugins
+        let newest = std::fs::metadata(&plugins[3].path).unwrap().modified().unwrap() + Duration::from_secs(60);
+        File::options().write(true).open(&plugins[1].path).unwrap().set_modified(newest).unwrap();
+        read.sort_by_timestamps(&plugins, PluginFormat::Oblivion).unwrap();
+        assert_eq!(read.plugins(), order.plugins());
+
+        // Entries whose plugin is missing stay where they are
+        let mut read = load_order(LoadOrderMethod::Timestamp, &[("Oblivion.esm", true), ("a.esp", true), ("Missing.esp", true), ("c.esm", true), ("b.esp", true)]);
+        read.implicit_count = 1;
+        read.sort_by_timestamps(&plugins, PluginFormat::Oblivion).unwrap();
+        let names: Vec<&str> = read.plugins().iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["Oblivion.esm", "c.esm", "Missing.esp", "b.esp", "a.esp"]);
+    }
+
+    #[test]
+    fn native_files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        for method in [LoadOrderMethod::Asterisk, LoadOrderMethod::Textfile, LoadOrderMethod::Timestamp] {
+            let mut order = load_order(method, &[("Skyrim.esm", true), ("a.esp", false), ("b.esp", true)]);
+            order.implicit_count = 1;
+            order.write(dir).unwrap();
+
+            let plugins = std::fs::read_to_string(dir.join("plugins.txt")).unwrap();
+            assert!(!plugins.contains("Skyrim.esm"));
+            if method == LoadOrderMethod::Asterisk {
+                assert!(plugins.contains("a.esp\r\n*b.esp\r\n"));
+            }
+
+            // The implicit plugins are added back when the load order is refreshed
+            let read = LoadOrder::read(dir, method).unwrap();
+            let listed: Vec<&LoadOrderEntry> = read.plugins().iter().filter(|entry| entry.name() != "Skyrim.esm").collect();
+            assert_eq!(listed, order.plugins()[1..].iter().collect::<Vec<&LoadOrderEntry>>());
+        }
+    }
+}
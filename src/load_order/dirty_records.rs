@@ -0,0 +1,368 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use error_chain::bail;
+use rayon::prelude::*;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::header::PluginHeader;
+use crate::game_plugin::PluginFormat;
+use crate::game_plugin::record::{COMPRESSED_FLAG, record_data, Records};
+use crate::load_order::{LoadOrder, visible_plugins};
+use crate::mod_info::game::get_game;
+use crate::mod_info::instance::Instance;
+
+/// The record flag marking a record as deleted
+pub const DELETED_FLAG: u32 = 0x20;
+
+/// The record types of placed references, which crash the game when deleted
+const REFERENCE_KINDS: [&[u8; 4]; 11] = [
+    b"REFR", b"ACHR", b"ACRE", b"PGRE", b"PMIS", b"PARW", b"PBAR", b"PBEA", b"PCON", b"PFLA", b"PHZD",
+];
+const NAVMESH_KIND: &[u8; 4] = b"NAVM";
+
+/// A record is keyed by the lowercased name of the plugin defining it and its object index
+type RecordKey = (String, u32);
+
+/// Why a record in a plugin is considered dirty
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirtyKind {
+    /// The record is an exact copy of the version in the plugin's masters
+    IdenticalToMaster,
+    /// The record deletes a placed reference from a master
+    DeletedReference,
+    /// The record deletes a navmesh from a master
+    DeletedNavmesh,
+}
+
+/// A dirty record in a plugin
+#[derive(Debug, PartialEq, Eq)]
+pub struct DirtyRecord {
+    pub kind: [u8; 4],
+    /// The FormID as stored in the plugin
+    pub form_id: u32,
+    pub dirty_kind: DirtyKind,
+}
+
+/// The dirty records found in a plugin
+#[derive(Debug, PartialEq, Eq)]
+pub struct DirtyPlugin {
+    pub name: String,
+    pub records: Vec<DirtyRecord>,
+}
+
+impl DirtyPlugin {
+    /// The number of records of a kind of dirty record
+    pub fn count(&self, dirty_kind: DirtyKind) -> usize {
+        self.records.iter().filter(|record| record.dirty_kind == dirty_kind).count()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.records.is_empty()
+    }
+}
+
+/// The results of scanning plugins for dirty records
+pub struct DirtyScan {
+    /// The results for every scanned plugin
+    pub plugins: Vec<DirtyPlugin>,
+    /// The name of each plugin or master that couldn't be read and the reason. These plugins aren't scanned, and
+    /// plugins are only checked for deletions against these masters.
+    pub unreadable: Vec<(String, String)>,
+}
+
+/// A record as stored for comparing, with its data decompressed
+struct StoredRecord {
+    kind: [u8; 4],
+    /// The record's flags without the compression flag, so compressed and uncompressed copies compare equal
+    flags: u32,
+    form_id: u32,
+    data: Vec<u8>,
+}
+
+/// The records of a plugin that override records from its masters
+struct PluginOverrides {
+    masters: Vec<String>,
+    records: Vec<StoredRecord>,
+}
+
+impl PluginOverrides {
+    /// Read the records of a plugin that override records from its masters
+    fn read<R: Read>(mut reader: R, format: PluginFormat) -> errors::Result<Self> {
+        let header = PluginHeader::read(&mut reader, format)?;
+        let masters = header.masters().to_vec();
+        let records = read_records(reader, format, |form_id| ((form_id >> 24) as usize) < masters.len())?;
+
+        Ok(Self { masters, records })
+    }
+
+    /// The keys of the overridden records
+    fn keys(&self) -> impl Iterator<Item = RecordKey> + '_ {
+        self.records.iter().map(|record| self.key(record.form_id))
+    }
+
+    fn key(&self, form_id: u32) -> RecordKey {
+        (self.masters[(form_id >> 24) as usize].to_lowercase(), form_id & 0x00FF_FFFF)
+    }
+}
+
+/// The versions of records in a master, keyed by the plugin defining them
+struct MasterRecords {
+    records: HashMap<RecordKey, StoredRecord>,
+}
+
+impl MasterRecords {
+    /// Read the versions of a set of records from a master
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the master
+    /// * `reader`: The reader, positioned at the start of the master
+    /// * `format`: The plugin format of the game the plugin is for
+    /// * `keys`: The records to keep, every other record's data is skipped
+    fn read<R: Read>(name: &str, mut reader: R, format: PluginFormat, keys: &HashSet<RecordKey>) -> errors::Result<Self> {
+        let header = PluginHeader::read(&mut reader, format)?;
+        let mut origins: Vec<String> = header.masters().iter().map(|master| master.to_lowercase()).collect();
+        origins.push(name.to_lowercase());
+
+        let key = |form_id: u32| -> RecordKey {
+            (origins[((form_id >> 24) as usize).min(origins.len() - 1)].clone(), form_id & 0x00FF_FFFF)
+        };
+        let records = read_records(reader, format, |form_id| keys.contains(&key(form_id)))?;
+
+        Ok(Self { records: records.into_iter().map(|record| (key(record.form_id), record)).collect() })
+    }
+}
+
+/// Read the records whose FormID passes a filter, skipping the data of the others
+fn read_records<R: Read, F: Fn(u32) -> bool>(reader: R, format: PluginFormat, keep: F) -> errors::Result<Vec<StoredRecord>> {
+    let mut records = Vec::new();
+    for record in Records::new(reader, format, true) {
+        let (header, data) = record?;
+        if !keep(header.form_id) {
+            continue;
+        }
+
+        records.push(StoredRecord {
+            kind: header.kind,
+            flags: header.flags & !COMPRESSED_FLAG,
+            form_id: header.form_id,
+            data: record_data(&header, &data)?,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Compare the overrides of a plugin with their versions in its masters
+///
+/// Each record is compared with the version in the last of the plugin's masters that contains it, records
+/// from masters that weren't read are only checked for deletions. Data is compared byte for byte, so a copy is
+/// only found if the FormIDs it references use the same master indexes as in the master.
+fn find_dirty_records(name: &str, plugin: &PluginOverrides, masters: &HashMap<String, MasterRecords>) -> DirtyPlugin {
+    let mut records = Vec::new();
+    for record in &plugin.records {
+        let dirty_kind = if record.flags & DELETED_FLAG != 0 {
+            match &record.kind {
+                kind if REFERENCE_KINDS.contains(&kind) => Some(DirtyKind::DeletedReference),
+                NAVMESH_KIND => Some(DirtyKind::DeletedNavmesh),
+                _ => None
+            }
+        } else {
+            let key = plugin.key(record.form_id);
+            let master_record = plugin.masters.iter().rev()
+                .filter_map(|master| masters.get(&master.to_lowercase())?.records.get(&key))
+                .next();
+
+            master_record
+                .filter(|master_record| master_record.kind == record.kind
+                    && master_record.flags == record.flags
+                    && master_record.data == record.data)
+                .map(|_| DirtyKind::IdenticalToMaster)
+        };
+
+        if let Some(dirty_kind) = dirty_kind {
+            records.push(DirtyRecord { kind: record.kind, form_id: record.form_id, dirty_kind });
+        }
+    }
+
+    DirtyPlugin { name: name.to_string(), records }
+}
+
+/// Scan plugins for identical to master records and deleted references and navmeshes
+///
+/// Plugins are read in parallel, then each master of the scanned plugins is read once in parallel, only keeping
+/// the records the scanned plugins override. Plugins and masters that can't be read are skipped and listed in
+/// [DirtyScan::unreadable].
+///
+/// # Arguments
+///
+/// * `instance`: The instance the plugins belong to, used to find the plugin files
+/// * `plugins`: The names of the plugins to scan, plugins without a file are skipped
+///
+/// returns: Result<DirtyScan, Error> The results for every readable plugin, in the order given
+pub fn scan_plugins(instance: &Instance, plugins: &[&str]) -> errors::Result<DirtyScan> {
+    let format = get_game(instance.game())?.plugin_format();
+    if !format.uses_form_ids() {
+        bail!(ErrorKind::UnsupportedFeature("Dirty plugin detection".to_string()))
+    }
+
+    let paths: HashMap<String, PathBuf> = visible_plugins(instance)?.into_iter()
+        .map(|plugin| (plugin.name.to_lowercase(), plugin.path))
+        .collect();
+
+    let results: Vec<(&str, errors::Result<PluginOverrides>)> = plugins.par_iter()
+        .filter_map(|name| Some((*name, paths.get(&name.to_lowercase())?)))
+        .map(|(name, path)| {
+            let plugin = File::open(path).map_err(errors::Error::from)
+                .and_then(|file| PluginOverrides::read(BufReader::new(file), format));
+            (name, plugin)
+        })
+        .collect();
+
+    let mut scanned = Vec::new();
+    let mut unreadable = Vec::new();
+    for (name, plugin) in results {
+        match plugin {
+            Ok(plugin) => scanned.push((name, plugin)),
+            Err(err) => unreadable.push((name.to_string(), err.to_string())),
+        }
+    }
+
+    let keys: HashSet<RecordKey> = scanned.iter().flat_map(|(_, plugin)| plugin.keys()).collect();
+    // Keyed by the lowercased name, keeping a name as written in a plugin's header to report it by
+    let mut master_names: HashMap<String, &str> = HashMap::new();
+    for master in scanned.iter().flat_map(|(_, plugin)| &plugin.masters) {
+        master_names.entry(master.to_lowercase()).or_insert(master);
+    }
+
+    let results: Vec<(String, &str, errors::Result<MasterRecords>)> = master_names.into_par_iter()
+        .filter_map(|(key, name)| {
+            let path = paths.get(&key)?;
+            Some((key, name, path))
+        })
+        .map(|(key, name, path)| {
+            let records = File::open(path).map_err(errors::Error::from)
+                .and_then(|file| MasterRecords::read(&key, BufReader::new(file), format, &keys));
+            (key, name, records)
+        })
+        .collect();
+
+    let mut masters = HashMap::new();
+    for (key, name, records) in results {
+        match records {
+            Ok(records) => {
+                masters.insert(key, records);
+            }
+            Err(err) => unreadable.push((name.to_string(), err.to_string())),
+        }
+    }
+
+    Ok(DirtyScan {
+        plugins: scanned.iter().map(|(name, plugin)| find_dirty_records(name, plugin, &masters)).collect(),
+        unreadable,
+    })
+}
+
+/// Scan the active plugins in a load order, except the plugins the game loads implicitly
+///
+/// returns: Result<DirtyScan, Error> The results for every readable plugin, in load order
+pub fn scan_load_order(instance: &Instance, load_order: &LoadOrder) -> errors::Result<DirtyScan> {
+    let plugins: Vec<&str> = load_order.active_plugins()
+        .filter(|entry| !load_order.is_implicit(entry.name()))
+        .map(|entry| entry.name())
+        .collect();
+
+    scan_plugins(instance, &plugins)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::test_util::{header, record, subrecord};
+    use crate::load_order::{LoadOrder, LoadOrderEntry, LoadOrderMethod};
+    use crate::load_order::dirty_records::{DELETED_FLAG, DirtyKind, DirtyRecord, find_dirty_records, MasterRecords, PluginOverrides, scan_load_order};
+    use crate::mod_info::instance::Instance;
+
+    fn plugin(masters: &[&str], records: &[(&[u8; 4], u32, u32, &[u8])]) -> Vec<u8> {
+        let mut plugin = header(PluginFormat::SkyrimSE, 0, masters, records.len() as u32);
+        for (kind, flags, form_id, edid) in records {
+            plugin.extend(record(PluginFormat::SkyrimSE, kind, *flags, *form_id, &subrecord(b"EDID", edid)));
+        }
+        plugin
+    }
+
+    #[test]
+    fn dirty_records() {
+        let master = plugin(&[], &[
+            (b"WEAP", 0, 0x0000_0001, b"Sword\0"),
+            (b"WEAP", 0, 0x0000_0002, b"Axe\0"),
+            (b"REFR", 0, 0x0000_0003, b""),
+            (b"NAVM", 0, 0x0000_0004, b""),
+        ]);
+        let dirty = plugin(&["Base.esm"], &[
+            (b"WEAP", 0, 0x0000_0001, b"Sword\0"),
+            (b"WEAP", 0, 0x0000_0002, b"Better Axe\0"),
+            (b"REFR", DELETED_FLAG, 0x0000_0003, b""),
+            (b"NAVM", DELETED_FLAG, 0x0000_0004, b""),
+            (b"WEAP", 0, 0x0100_0800, b"Sword\0"),
+        ]);
+
+        let overrides = PluginOverrides::read(Cursor::new(dirty), PluginFormat::SkyrimSE).unwrap();
+        assert_eq!(overrides.records.len(), 4);
+
+        let keys: HashSet<(String, u32)> = overrides.keys().collect();
+        let master = MasterRecords::read("Base.esm", Cursor::new(master), PluginFormat::SkyrimSE, &keys).unwrap();
+        let masters = HashMap::from([("base.esm".to_string(), master)]);
+
+        let result = find_dirty_records("Dirty.esp", &overrides, &masters);
+        assert_eq!(result.records, [
+            DirtyRecord { kind: *b"WEAP", form_id: 0x0000_0001, dirty_kind: DirtyKind::IdenticalToMaster },
+            DirtyRecord { kind: *b"REFR", form_id: 0x0000_0003, dirty_kind: DirtyKind::DeletedReference },
+            DirtyRecord { kind: *b"NAVM", form_id: 0x0000_0004, dirty_kind: DirtyKind::DeletedNavmesh },
+        ]);
+        assert_eq!(result.count(DirtyKind::IdenticalToMaster), 1);
+
+        let result = find_dirty_records("Dirty.esp", &overrides, &HashMap::new());
+        assert_eq!(result.count(DirtyKind::IdenticalToMaster), 0);
+        assert_eq!(result.records.len(), 2);
+    }
+
+    #[test]
+    fn skip_unreadable_plugins() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("instance");
+        let game = dir.path().join("game");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &game, None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+
+        let data = game.join("Data");
+        fs::create_dir_all(&data).unwrap();
+        fs::write(data.join("Base.esm"), plugin(&[], &[(b"WEAP", 0, 0x0000_0001, b"Sword\0")])).unwrap();
+        fs::write(data.join("Broken.esm"), b"not a plugin").unwrap();
+        fs::write(data.join("Bad.esp"), b"not a plugin").unwrap();
+        fs::write(data.join("A.esp"), plugin(&["Base.esm"], &[(b"WEAP", 0, 0x0000_0001, b"Sword\0")])).unwrap();
+        fs::write(data.join("B.esp"), plugin(&["Broken.esm"], &[(b"WEAP", 0, 0x0000_0001, b"Sword\0")])).unwrap();
+
+        let load_order = LoadOrder {
+            method: LoadOrderMethod::Asterisk,
+            plugins: ["Base.esm", "Bad.esp", "A.esp", "B.esp"].iter().map(|name| LoadOrderEntry::new(name, true)).collect(),
+            implicit_count: 0,
+        };
+
+        let scan = scan_load_order(&instance, &load_order).unwrap();
+        let scanned: Vec<(&str, usize)> = scan.plugins.iter().map(|plugin| (plugin.name.as_str(), plugin.records.len())).collect();
+        assert_eq!(scanned, [("Base.esm", 0), ("A.esp", 1), ("B.esp", 0)]);
+        let unreadable: Vec<&str> = scan.unreadable.iter().map(|(plugin, _)| plugin.as_str()).collect();
+        assert_eq!(unreadable, ["Bad.esp", "Broken.esm"]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::errors;
+
+/// The default name of the masterlist file in an instance's directory
+pub const MASTERLIST_FILE: &str = "masterlist.yaml";
+/// The default name of the userlist file in an instance's directory
+pub const USERLIST_FILE: &str = "userlist.yaml";
+
+/// The group plugins without a group belong to
+pub const DEFAULT_GROUP: &str = "default";
+
+/// The characters that mark a plugin name in a masterlist as a regular expression
+const REGEX_CHARS: &[char] = &[':', '\\', '*', '?', '|'];
+
+/// A reference to another plugin in a plugin's metadata, either a bare name or a map with a `name` key
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum FileReference {
+    Name(String),
+    Detailed { name: String },
+}
+
+impl FileReference {
+    fn name(&self) -> &str {
+        match self {
+            FileReference::Name(name) => name,
+            FileReference::Detailed { name } => name,
+        }
+    }
+}
+
+/// A named group of plugins, and the groups it loads after
+#[derive(Clone, Debug, Deserialize)]
+pub struct GroupMetadata {
+    name: String,
+    #[serde(default)]
+    after: Vec<String>,
+}
+
+impl GroupMetadata {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The groups whose plugins must load before the plugins in this group
+    pub fn after(&self) -> &[String] {
+        &self.after
+    }
+}
+
+/// The sorting metadata of a plugin, the name may be a regular expression matching several plugins
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginMetadata {
+    name: String,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    after: Vec<FileReference>,
+    #[serde(default)]
+    req: Vec<FileReference>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl PluginMetadata {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// The plugins that must load before this plugin if they're present
+    pub fn after(&self) -> impl Iterator<Item = &str> {
+        self.after.iter().map(|reference| reference.name())
+    }
+
+    /// The plugins this plugin requires, which must load before it
+    pub fn requirements(&self) -> impl Iterator<Item = &str> {
+        self.req.iter().map(|reference| reference.name())
+    }
+
+    /// Whether this metadata applies to a plugin, names are compared case-insensitively
+    pub fn matches(&self, plugin: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(plugin),
+            None => self.name.eq_ignore_ascii_case(plugin)
+        }
+    }
+}
+
+/// The sorting metadata from a LOOT masterlist or userlist
+///
+/// Only the `groups` and `plugins` lists are read, and the conditions on metadata entries aren't evaluated so
+/// every entry applies.
+#[derive(Debug, Deserialize)]
+pub struct Masterlist {
+    #[serde(default)]
+    groups: Vec<GroupMetadata>,
+    #[serde(default)]
+    plugins: Vec<PluginMetadata>,
+    /// Map of lowercase plugin name to the indexes of the metadata entries with that exact name
+    #[serde(skip)]
+    exact: HashMap<String, Vec<usize>>,
+    /// The indexes of the metadata entries whose names are regular expressions
+    #[serde(skip)]
+    patterns: Vec<usize>,
+}
+
+impl Masterlist {
+    /// Read a masterlist or userlist file
+    pub fn from_path(path: &Path) -> errors::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the YAML of a masterlist or userlist
+    pub fn parse(yaml: &str) -> errors::Result<Self> {
+        let mut masterlist: Masterlist = serde_yaml::from_str(yaml)?;
+
+        for plugin in masterlist.plugins.iter_mut() {
+            if plugin.name.contains(REGEX_CHARS) {
+                plugin.regex = Some(RegexBuilder::new(&format!("^(?:{})$", plugin.name)).case_insensitive(true).build()?);
+            }
+        }
+        masterlist.index();
+
+        Ok(masterlist)
+    }
+
+    /// Add the metadata from a userlist to this masterlist
+    ///
+    /// Plugin metadata is added to the masterlist's entries, a group from the userlist gains the `after` entries
+    /// of the masterlist group with the same name.
+    pub fn merge(&mut self, userlist: Masterlist) {
+        for group in userlist.groups {
+            match self.groups.iter_mut().find(|existing| existing.name == group.name) {
+                Some(existing) => existing.after.extend(group.after),
+                None => self.groups.push(group)
+            }
+        }
+
+        self.plugins.extend(userlist.plugins);
+        self.index();
+    }
+
+    /// Get the groups, the default group is always present even if it isn't defined
+    pub fn groups(&self) -> &[GroupMetadata] {
+        &self.groups
+    }
+
+    /// Get the metadata entries that apply to a plugin, in the order they were read
+    pub fn plugin_metadata(&self, plugin: &str) -> Vec<&PluginMetadata> {
+        let mut indexes: Vec<usize> = self.exact.get(&plugin.to_lowercase()).cloned().unwrap_or_default();
+        indexes.extend(self.patterns.iter().filter(|index| self.plugins[**index].matches(plugin)));
+        indexes.sort_unstable();
+
+        indexes.into_iter().map(|index| &self.plugins[index]).collect()
+    }
+
+    fn index(&mut self) {
+        if !self.groups.iter().any(|group| group.name == DEFAULT_GROUP) {
+            self.groups.insert(0, GroupMetadata { name: DEFAULT_GROUP.to_string(), after: Vec::new() });
+        }
+
+        self.exact.clear();
+        self.patterns.clear();
+
+        for (index, plugin) in self.plugins.iter().enumerate() {
+            if plugin.regex.is_some() {
+                self.patterns.push(index);
+            } else {
+                self.exact.entry(plugin.name.to_lowercase()).or_default().push(index);
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors;
+
+/// Morrowind's settings file in the game directory, which lists the active plugins
+pub const MORROWIND_INI: &str = "Morrowind.ini";
+/// OpenMW's settings file, which lists the active content files and the data directories
+pub const OPENMW_CFG: &str = "openmw.cfg";
+
+const GAME_FILES_SECTION: &str = "[Game Files]";
+const GAME_FILE_KEY: &str = "GameFile";
+
+/* ========================================= */
+/* Morrowind.ini                             */
+/* ========================================= */
+
+/// Read the active plugins from the `[Game Files]` section of `Morrowind.ini`
+///
+/// # Arguments
+///
+/// * `path`: The path to `Morrowind.ini`, a missing file has no active plugins
+///
+/// returns: Result<Vec<String>, Error> The plugins in order of their `GameFileN` index
+pub fn read_game_files(path: &Path) -> errors::Result<Vec<String>> {
+    let mut files: Vec<(u32, String)> = read_ini(path)?.iter()
+        .skip_while(|line| !is_game_files_section(line))
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let index = strip_prefix_ignore_case(key.trim(), GAME_FILE_KEY)?.parse().ok()?;
+            Some((index, value.trim().to_string()))
+        })
+        .collect();
+
+    files.sort_by_key(|(index, _)| *index);
+    Ok(files.into_iter().map(|(_, name)| name).collect())
+}
+
+/// Replace the `GameFileN` entries in the `[Game Files]` section of `Morrowind.ini`
+///
+/// The rest of the file is kept as it is, the section is added if it's missing.
+///
+/// # Arguments
+///
+/// * `path`: The path to `Morrowind.ini`
+/// * `plugins`: The active plugins in load order
+///
+/// returns: Result<(), Error>
+pub fn write_game_files(path: &Path, plugins: &[&str]) -> errors::Result<()> {
+    let mut lines = read_ini(path)?;
+    let entries = plugins.iter().enumerate().map(|(index, name)| format!("{GAME_FILE_KEY}{index}={name}"));
+
+    match lines.iter().position(|line| is_game_files_section(line)) {
+        Some(section) => {
+            let mut end = section + 1;
+            while end < lines.len() && !lines[end].trim_start().starts_with('[') {
+                if strip_prefix_ignore_case(lines[end].trim_start(), GAME_FILE_KEY).is_some() {
+                    lines.remove(end);
+                } else {
+                    end += 1;
+                }
+            }
+            lines.splice(section + 1..section + 1, entries);
+        }
+        None => {
+            lines.push(GAME_FILES_SECTION.to_string());
+            lines.extend(entries);
+        }
+    }
+
+    // Morrowind.ini is Windows-1252, characters outside Latin-1 can't be stored
+    let content: Vec<u8> = lines.iter()
+        .flat_map(|line| line.chars().chain("\r\n".chars()))
+        .map(|char| u8::try_from(char).unwrap_or(b'?'))
+        .collect();
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Read the lines of `Morrowind.ini`, decoding each byte as a character so the file is written back unchanged
+fn read_ini(path: &Path) -> errors::Result<Vec<String>> {
+    match fs::read(path) {
+        Ok(content) => {
+            let mut lines: Vec<String> = content.split(|byte| *byte == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line).iter().map(|byte| *byte as char).collect())
+                .collect();
+            if lines.last().is_some_and(|line| line.is_empty()) {
+                lines.pop();
+            }
+            Ok(lines)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into())
+    }
+}
+
+fn is_game_files_section(line: &str) -> bool {
+    line.trim().eq_ignore_ascii_case(GAME_FILES_SECTION)
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    value.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &value[prefix.len()..])
+}
+
+/* ========================================= */
+/* openmw.cfg                                */
+/* ========================================= */
+
+/// The lines of an `openmw.cfg` file
+///
+/// Only the `content=` and `data=` lines are changed, everything else is written back as it was read.
+pub struct OpenMwConfig {
+    lines: Vec<String>,
+}
+
+impl OpenMwConfig {
+    /// Load an `openmw.cfg` file, a missing file is treated as empty
+    pub fn load(path: &Path) -> errors::Result<Self> {
+        let lines = match fs::read_to_string(path) {
+            Ok(content) => content.lines().map(|line| line.to_string()).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into())
+        };
+
+        Ok(Self { lines })
+    }
+
+    pub fn save(&self, path: &Path) -> errors::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content: String = self.lines.iter().map(|line| format!("{line}\n")).collect();
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Get the active content files in load order
+    pub fn content(&self) -> Vec<String> {
+        self.values("content").map(|value| value.to_string()).collect()
+    }
+
+    /// Replace the active content files
+    pub fn set_content(&mut self, plugins: &[&str]) {
+        self.replace("content", |_| false, plugins.iter().map(|name| format!("content={name}")).collect());
+    }
+
+    /// Get the data directories, lowest priority first
+    pub fn data_paths(&self) -> Vec<PathBuf> {
+        self.values("data").map(|value| PathBuf::from(unquote(value))).collect()
+    }
+
+    /// Replace some of the data directories
+    ///
+    /// # Arguments
+    ///
+    /// * `keep`: Whether to keep an existing data directory
+    /// * `paths`: The directories to add with a higher priority than the kept ones
+    pub fn set_data_paths<F: Fn(&Path) -> bool>(&mut self, keep: F, paths: &[PathBuf]) {
+        let lines = paths.iter().map(|path| format!("data={}", quote(path))).collect();
+        self.replace("data", |value| keep(Path::new(&unquote(value))), lines);
+    }
+
+    fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.lines.iter().filter_map(move |line| value_of(line, key))
+    }
+
+    /// Remove the lines of a key that aren't kept, and add new lines after the last remaining line of the key
+    fn replace<F: Fn(&str) -> bool>(&mut self, key: &str, keep: F, new_lines: Vec<String>) {
+        let mut insert_at = None;
+        let mut index = 0;
+        while index < self.lines.len() {
+            match value_of(&self.lines[index], key) {
+                Some(value) if !keep(value) => {
+                    self.lines.remove(index);
+                    insert_at = Some(index);
+                    continue;
+                }
+                Some(_) => insert_at = Some(index + 1),
+                None => {}
+            }
+            index += 1;
+        }
+
+        let insert_at = insert_at.unwrap_or(self.lines.len());
+        self.lines.splice(insert_at..insert_at, new_lines);
+    }
+}
+
+fn value_of<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (line_key, value) = line.split_once('=')?;
+    (line_key.trim() == key).then(|| value.trim())
+}
+
+/// Quote a path for `openmw.cfg`, which escapes `&` and `"` inside quotes with `&`
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('&', "&&").replace('"', "&\"");
+    format!("\"{path}\"")
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) else {
+        return value.to_string()
+    };
+
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '&' => unquoted.extend(chars.next()),
+            _ => unquoted.push(char)
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::load_order::morrowind::{OpenMwConfig, read_game_files, write_game_files};
+
+    #[test]
+    fn morrowind_ini() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Morrowind.ini");
+        std::fs::write(&path, b"[General]\r\nKey=\xe9\r\n[Game Files]\r\nGameFile1=b.esp\r\nGameFile0=Morrowind.esm\r\n[Archives]\r\nArchive 0=Tribunal.bsa\r\n").unwrap();
+
+        assert_eq!(read_game_files(&path).unwrap(), ["Morrowind.esm", "b.esp"]);
+
+        write_game_files(&path, &["Morrowind.esm", "Tribunal.esm", "a.esp"]).unwrap();
+        assert_eq!(read_game_files(&path).unwrap(), ["Morrowind.esm", "Tribunal.esm", "a.esp"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"[General]\r\nKey=\xe9\r\n[Game Files]\r\nGameFile0=Morrowind.esm\r\nGameFile1=Tribunal.esm\r\nGameFile2=a.esp\r\n[Archives]\r\nArchive 0=Tribunal.bsa\r\n");
+    }
+
+    #[test]
+    fn openmw_config() {
+        let mut config = OpenMwConfig {
+            lines: ["data=\"/games/Morrowind/Data Files\"", "data=\"/mods/Old&&Mod\"", "fallback=a,b", "content=Morrowind.esm"]
+                .map(String::from).to_vec()
+        };
+        assert_eq!(config.data_paths()[1], PathBuf::from("/mods/Old&Mod"));
+
+        config.set_data_paths(|path| !path.starts_with("/mods"), &[PathBuf::from("/mods/A \"B\"")]);
+        config.set_content(&["Morrowind.esm", "a.esp"]);
+        assert_eq!(config.lines, [
+            "data=\"/games/Morrowind/Data Files\"", "data=\"/mods/A &\"B&\"\"", "fallback=a,b",
+            "content=Morrowind.esm", "content=a.esp"
+        ]);
+        assert_eq!(config.data_paths()[1], PathBuf::from("/mods/A \"B\""));
+    }
+}
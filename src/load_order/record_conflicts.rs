@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use error_chain::bail;
+use rayon::prelude::*;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::header::PluginHeader;
+use crate::game_plugin::PluginFormat;
+use crate::game_plugin::record::Records;
+use crate::load_order::{LoadOrder, visible_plugins};
+use crate::mod_info::game::get_game;
+use crate::mod_info::instance::Instance;
+
+/// The records in a single plugin, with FormIDs as stored in the plugin
+pub struct PluginRecords {
+    masters: Vec<String>,
+    /// The FormID and type of every record except the header
+    records: Vec<(u32, [u8; 4])>,
+}
+
+impl PluginRecords {
+    /// Read the records of a plugin
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The reader, positioned at the start of the plugin
+    /// * `format`: The plugin format of the game the plugin is for
+    ///
+    /// returns: Result<PluginRecords, Error>
+    pub fn read<R: Read>(mut reader: R, format: PluginFormat) -> errors::Result<Self> {
+        if !format.uses_form_ids() {
+            bail!(ErrorKind::UnsupportedFeature("Record conflict detection".to_string()))
+        }
+
+        let header = PluginHeader::read(&mut reader, format)?;
+        let records = Records::new(reader, format, false)
+            .map(|record| record.map(|(header, _)| (header.form_id, header.kind)))
+            .collect::<errors::Result<Vec<(u32, [u8; 4])>>>()?;
+
+        Ok(Self { masters: header.masters().to_vec(), records })
+    }
+}
+
+/// A record that is overridden by at least one plugin
+pub struct RecordOverrides<'a> {
+    /// The type of the record, e.g. `ARMO`
+    pub kind: [u8; 4],
+    /// The plugin that defines the record
+    pub origin: &'a str,
+    /// The object index of the record's FormID, without the master index
+    pub object_id: u32,
+    /// The plugins that override the record, in load order
+    pub overrides: Vec<&'a str>,
+}
+
+impl<'a> RecordOverrides<'a> {
+    /// The plugin whose version of the record the game uses
+    pub fn winner(&self) -> &'a str {
+        self.overrides.last().copied().unwrap_or(self.origin)
+    }
+
+    /// Whether more than one plugin overrides the record, so only one of their changes is used
+    pub fn is_conflict(&self) -> bool {
+        self.overrides.len() > 1
+    }
+}
+
+struct RecordEntry {
+    kind: [u8; 4],
+    /// The indexes of the plugins containing the record, in load order
+    plugins: Vec<u32>,
+}
+
+/// An index of which plugins contain which records, with FormIDs resolved to the plugin defining the record
+pub struct RecordIndex {
+    /// The names of the indexed plugins in load order, followed by any masters that aren't in the load order
+    plugins: Vec<String>,
+    /// Map of plugin index and object index to the record's entry, keyed as `plugin << 32 | object`
+    records: HashMap<u64, RecordEntry>,
+    /// The name of each plugin that couldn't be read and the reason, these are left out of the index
+    unreadable: Vec<(String, String)>,
+}
+
+impl RecordIndex {
+    /// Index the records of the active plugins in a load order
+    ///
+    /// Plugins are read in parallel, plugins without a file are skipped and plugins that can't be read are left
+    /// out and listed by [RecordIndex::unreadable].
+    pub fn for_load_order(instance: &Instance, load_order: &LoadOrder) -> errors::Result<Self> {
+        let format = get_game(instance.game())?.plugin_format();
+        let paths: HashMap<String, PathBuf> = visible_plugins(instance)?.into_iter()
+            .map(|plugin| (plugin.name.to_lowercase(), plugin.path))
+            .collect();
+
+        let results: Vec<(String, errors::Result<PluginRecords>)> = load_order.active_plugins()
+            .filter_map(|entry| Some((entry.name().to_string(), paths.get(&entry.name().to_lowercase())?)))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(name, path)| {
+                let records = File::open(path).map_err(errors::Error::from)
+                    .and_then(|file| PluginRecords::read(BufReader::new(file), format));
+                (name, records)
+            })
+            .collect();
+
+        let mut plugins = Vec::new();
+        let mut unreadable = Vec::new();
+        for (name, records) in results {
+            match records {
+                Ok(records) => plugins.push((name, records)),
+                Err(err) => unreadable.push((name, err.to_string())),
+            }
+        }
+
+        let mut index = Self::from_plugins(plugins);
+        index.unreadable = unreadable;
+        Ok(index)
+    }
+
+    /// Build an index from plugins that have already been read
+    ///
+    /// # Arguments
+    ///
+    /// * `plugins`: The name and records of each plugin, in load order
+    pub fn from_plugins(plugins: Vec<(String, PluginRecords)>) -> Self {
+        let mut index = Self { plugins: Vec::new(), records: HashMap::new(), unreadable: Vec::new() };
+        let mut ids: HashMap<String, u32> = HashMap::new();
+        let mut intern = |names: &mut Vec<String>, name: &str| *ids.entry(name.to_lowercase()).or_insert_with(|| {
+            names.push(name.to_string());
+            names.len() as u32 - 1
+        });
+
+        for (name, _) in &plugins {
+            intern(&mut index.plugins, name);
+        }
+
+        for (position, (name, plugin)) in plugins.into_iter().enumerate() {
+            let position = position as u32;
+            // Master index n refers to the nth master, anything past the masters is the plugin itself
+            let mut origins: Vec<u32> = plugin.masters.iter().map(|master| intern(&mut index.plugins, master)).collect();
+            origins.push(intern(&mut index.plugins, &name));
+
+            for (form_id, kind) in plugin.records {
+                let origin = origins[((form_id >> 24) as usize).min(origins.len() - 1)];
+                let key = (origin as u64) << 32 | (form_id & 0x00FF_FFFF) as u64;
+
+                let entry = index.records.entry(key).or_insert_with(|| RecordEntry { kind, plugins: Vec::new() });
+                if entry.plugins.last() != Some(&position) {
+                    entry.plugins.push(position);
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Get the name of each plugin that couldn't be read and the reason, in load order
+    pub fn unreadable(&self) -> &[(String, String)] {
+        &self.unreadable
+    }
+
+    /// Get every record that is overridden by at least one plugin, sorted by origin then object index
+    pub fn overridden_records(&self) -> Vec<RecordOverrides<'_>> {
+        let mut records: Vec<RecordOverrides> = self.records.iter()
+            .filter_map(|(key, entry)| {
+                let origin = (key >> 32) as u32;
+                let overrides: Vec<&str> = entry.plugins.iter()
+                    .filter(|plugin| **plugin != origin)
+                    .map(|plugin| self.plugins[*plugin as usize].as_str())
+                    .collect();
+                if overrides.is_empty() {
+                    return None
+                }
+
+                Some(RecordOverrides {
+                    kind: entry.kind,
+                    origin: &self.plugins[origin as usize],
+                    object_id: *key as u32,
+                    overrides,
+                })
+            })
+            .collect();
+
+        let positions: HashMap<&str, usize> = self.plugins.iter().enumerate()
+            .map(|(index, name)| (name.as_str(), index))
+            .collect();
+        records.sort_by_key(|record| (positions[record.origin], record.object_id));
+        records
+    }
+
+    /// Get every record that more than one plugin overrides
+    pub fn conflicts(&self) -> Vec<RecordOverrides<'_>> {
+        self.overridden_records().into_iter().filter(|record| record.is_conflict()).collect()
+    }
+
+    /// Get the records a plugin overrides, or that other plugins override from it
+    pub fn plugin_overrides(&self, plugin: &str) -> Vec<RecordOverrides<'_>> {
+        self.overridden_records().into_iter()
+            .filter(|record| record.origin.eq_ignore_ascii_case(plugin)
+                || record.overrides.iter().any(|name| name.eq_ignore_ascii_case(plugin)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::test_util::{header, record};
+    use crate::load_order::{LoadOrder, LoadOrderEntry, LoadOrderMethod};
+    use crate::load_order::record_conflicts::{PluginRecords, RecordIndex};
+    use crate::mod_info::instance::Instance;
+
+    fn plugin_bytes(masters: &[&str], form_ids: &[u32]) -> Vec<u8> {
+        let mut plugin = header(PluginFormat::SkyrimSE, 0, masters, form_ids.len() as u32);
+        for form_id in form_ids {
+            plugin.extend(record(PluginFormat::SkyrimSE, b"WEAP", 0, *form_id, &[]));
+        }
+        plugin
+    }
+
+    fn plugin(masters: &[&str], form_ids: &[u32]) -> PluginRecords {
+        PluginRecords::read(Cursor::new(plugin_bytes(masters, form_ids)), PluginFormat::SkyrimSE).unwrap()
+    }
+
+    #[test]
+    fn record_overrides() {
+        let index = RecordIndex::from_plugins(vec![
+            ("Base.esm".to_string(), plugin(&[], &[0x0000_0001, 0x0000_0002, 0x0000_0003])),
+            ("A.esp".to_string(), plugin(&["Base.esm"], &[0x0000_0001, 0x0000_0002, 0x0100_0800])),
+            ("B.esp".to_string(), plugin(&["Base.esm", "A.esp"], &[0x0000_0002, 0x0100_0800])),
+            ("C.esp".to_string(), plugin(&["Missing.esm"], &[0x0000_0005])),
+        ]);
+
+        let overridden = index.overridden_records();
+        assert_eq!(overridden.len(), 4);
+
+        assert_eq!(overridden[0].origin, "Base.esm");
+        assert_eq!(overridden[0].object_id, 1);
+        assert_eq!(overridden[0].overrides, ["A.esp"]);
+        assert!(!overridden[0].is_conflict());
+
+        assert_eq!(overridden[1].overrides, ["A.esp", "B.esp"]);
+        assert_eq!(overridden[1].winner(), "B.esp");
+        assert!(overridden[1].is_conflict());
+
+        assert_eq!(overridden[2].origin, "A.esp");
+        assert_eq!(overridden[2].object_id, 0x800);
+        assert_eq!(overridden[2].overrides, ["B.esp"]);
+
+        assert_eq!(overridden[3].origin, "Missing.esm");
+        assert_eq!(overridden[3].winner(), "C.esp");
+
+        assert_eq!(index.conflicts().len(), 1);
+        assert_eq!(index.plugin_overrides("b.esp").len(), 2);
+    }
+
+    #[test]
+    fn skip_unreadable_plugins() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("instance");
+        let game = dir.path().join("game");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &game, None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+
+        let data = game.join("Data");
+        fs::create_dir_all(&data).unwrap();
+        fs::write(data.join("Base.esm"), plugin_bytes(&[], &[0x0000_0001])).unwrap();
+        fs::write(data.join("Bad.esp"), b"not a plugin").unwrap();
+        fs::write(data.join("A.esp"), plugin_bytes(&["Base.esm"], &[0x0000_0001])).unwrap();
+
+        let load_order = LoadOrder {
+            method: LoadOrderMethod::Asterisk,
+            plugins: ["Base.esm", "Bad.esp", "A.esp"].iter().map(|name| LoadOrderEntry::new(name, true)).collect(),
+            implicit_count: 0,
+        };
+
+        let index = RecordIndex::for_load_order(&instance, &load_order).unwrap();
+        assert_eq!(index.unreadable().len(), 1);
+        assert_eq!(index.unreadable()[0].0, "Bad.esp");
+        assert_eq!(index.overridden_records().len(), 1);
+        assert_eq!(index.overridden_records()[0].overrides, ["A.esp"]);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::load_order::{LoadOrder, LoadOrderEntry};
+use crate::mod_info::instance::Instance;
+
+/// The directory in a profile snapshots are stored in
+const SNAPSHOTS_DIR: &str = "snapshots";
+/// The prefix of the names of snapshots taken automatically
+pub const AUTOMATIC_PREFIX: &str = "auto-";
+/// The number of automatic snapshots kept per profile, older ones are deleted
+const MAX_AUTOMATIC_SNAPSHOTS: usize = 20;
+
+/// A saved copy of a profile's plugin order and active flags
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(skip)]
+    name: String,
+    created: DateTime<Utc>,
+    plugins: Vec<LoadOrderEntry>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of a load order
+    pub fn new(name: &str, load_order: &LoadOrder) -> Self {
+        Self { name: name.to_string(), created: Utc::now(), plugins: load_order.plugins().to_vec() }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    /// Whether the snapshot was taken automatically before a change
+    pub fn is_automatic(&self) -> bool {
+        self.name.starts_with(AUTOMATIC_PREFIX)
+    }
+
+    pub fn plugins(&self) -> &[LoadOrderEntry] {
+        &self.plugins
+    }
+}
+
+/// A difference between two snapshots
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotChange {
+    /// The plugin is only in the second snapshot
+    Added(String),
+    /// The plugin is only in the first snapshot
+    Removed(String),
+    /// The plugin was moved relative to the other plugins, positions are in each snapshot
+    Moved { name: String, from: usize, to: usize },
+    Activated(String),
+    Deactivated(String),
+}
+
+impl SnapshotChange {
+    pub fn describe(&self) -> String {
+        match self {
+            SnapshotChange::Added(name) => format!("+ {name}"),
+            SnapshotChange::Removed(name) => format!("- {name}"),
+            SnapshotChange::Moved { name, from, to } => format!("~ {name} moved from {from} to {to}"),
+            SnapshotChange::Activated(name) => format!("* {name} activated"),
+            SnapshotChange::Deactivated(name) => format!("* {name} deactivated"),
+        }
+    }
+}
+
+/* ========================================= */
+/* Storage                                   */
+/* ========================================= */
+
+fn snapshots_path(instance: &Instance) -> PathBuf {
+    instance.profile_path().join(SNAPSHOTS_DIR)
+}
+
+fn snapshot_path(instance: &Instance, name: &str) -> errors::Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!(ErrorKind::InvalidSnapshotName(name.to_string()))
+    }
+
+    Ok(snapshots_path(instance).join(format!("{name}.toml")))
+}
+
+/// Save a snapshot of a load order to the instance's active profile, replacing any snapshot with the same name
+///
+/// # Arguments
+///
+/// * `instance`: The instance whose active profile the load order belongs to
+/// * `load_order`: The load order to snapshot
+/// * `name`: The name of the snapshot, which can't contain path separators
+///
+/// returns: Result<Snapshot, Error> The saved snapshot
+pub fn save_snapshot(instance: &Instance, load_order: &LoadOrder, name: &str) -> errors::Result<Snapshot> {
+    let path = snapshot_path(instance, name)?;
+    let snapshot = Snapshot::new(name, load_order);
+
+    fs::create_dir_all(snapshots_path(instance))?;
+    fs::write(path, toml::to_string(&snapshot)?)?;
+    Ok(snapshot)
+}
+
+/// Take a snapshot before a change to the load order, and delete the oldest automatic snapshots
+///
+/// # Arguments
+///
+/// * `instance`: The instance whose active profile the load order belongs to
+/// * `load_order`: The load order before the change
+/// * `reason`: A short name for the change, added to the snapshot's name
+///
+/// returns: Result<Snapshot, Error> The saved snapshot
+pub fn automatic_snapshot(instance: &Instance, load_order: &LoadOrder, reason: &str) -> errors::Result<Snapshot> {
+    let name = format!("{AUTOMATIC_PREFIX}{reason}-{}", Utc::now().format("%Y%m%d-%H%M%S%.3f"));
+    let snapshot = save_snapshot(instance, load_order, &name)?;
+
+    let automatic: Vec<Snapshot> = list_snapshots(instance)?.into_iter()
+        .filter(|snapshot| snapshot.is_automatic())
+        .collect();
+    for old in automatic.iter().take(automatic.len().saturating_sub(MAX_AUTOMATIC_SNAPSHOTS)) {
+        delete_snapshot(instance, old.name())?;
+    }
+
+    Ok(snapshot)
+}
+
+/// Load a snapshot from the instance's active profile
+pub fn load_snapshot(instance: &Instance, name: &str) -> errors::Result<Snapshot> {
+    let content = match fs::read_to_string(snapshot_path(instance, name)?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => bail!(ErrorKind::UnknownSnapshot(name.to_string())),
+        Err(err) => return Err(err.into())
+    };
+
+    let mut snapshot: Snapshot = toml::from_str(&content)?;
+    snapshot.name = name.to_string();
+    Ok(snapshot)
+}
+
+/// Get the snapshots of the instance's active profile, oldest first
+///
+/// Snapshots that can't be read are skipped with a warning, so one bad file doesn't block taking new snapshots.
+pub fn list_snapshots(instance: &Instance) -> errors::Result<Vec<Snapshot>> {
+    let entries = match fs::read_dir(snapshots_path(instance)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into())
+    };
+
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "toml") {
+            continue;
+        }
+
+        match load_snapshot(instance, &path.file_stem().unwrap().to_string_lossy()) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(err) => eprintln!("Skipping unreadable snapshot {}: {err}", path.display())
+        }
+    }
+
+    snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)));
+    Ok(snapshots)
+}
+
+pub fn delete_snapshot(instance: &Instance, name: &str) -> errors::Result<()> {
+    match fs::remove_file(snapshot_path(instance, name)?) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => bail!(ErrorKind::UnknownSnapshot(name.to_string())),
+        result => Ok(result?)
+    }
+}
+
+/// Restore a snapshot to a load order and save it
+///
+/// An automatic snapshot of the current load order is taken first. Plugins that have gone since the snapshot
+/// was taken are skipped, plugins that are new are left inactive at the end.
+///
+/// # Arguments
+///
+/// * `instance`: The instance whose active profile the load order belongs to
+/// * `load_order`: The current load order, which is changed to match the snapshot
+/// * `name`: The name of the snapshot to restore
+///
+/// returns: Result<(), Error>
+pub fn restore_snapshot(instance: &Instance, load_order: &mut LoadOrder, name: &str) -> errors::Result<()> {
+    let snapshot = load_snapshot(instance, name)?;
+    automatic_snapshot(instance, load_order, "restore")?;
+
+    load_order.restore_entries(snapshot.plugins());
+    load_order.save(instance)
+}
+
+/* ========================================= */
+/* Diff                                      */
+/* ========================================= */
+
+/// Compare the plugins in two snapshots
+///
+/// Plugins are only reported as moved if they changed position relative to the other plugins, so adding a
+/// plugin doesn't mark every plugin after it as moved.
+///
+/// returns: Vec<SnapshotChange> The changes in order of the plugins in the second snapshot, followed by removals
+pub fn diff(from: &[LoadOrderEntry], to: &[LoadOrderEntry]) -> Vec<SnapshotChange> {
+    let from_positions: HashMap<String, usize> = from.iter().enumerate()
+        .map(|(index, entry)| (entry.name().to_lowercase(), index))
+        .collect();
+    let to_names: HashSet<String> = to.iter().map(|entry| entry.name().to_lowercase()).collect();
+
+    // The plugins in both snapshots that keep their relative order are the longest increasing run of their old
+    // positions, taken in their new order
+    let common: Vec<(usize, usize)> = to.iter().enumerate()
+        .filter_map(|(index, entry)| Some((index, *from_positions.get(&entry.name().to_lowercase())?)))
+        .collect();
+    let unmoved = longest_increasing(&common.iter().map(|(_, from)| *from).collect::<Vec<usize>>());
+    let unmoved: Vec<usize> = unmoved.into_iter().map(|index| common[index].0).collect();
+
+    let mut changes = Vec::new();
+    for (index, entry) in to.iter().enumerate() {
+        let name = entry.name().to_string();
+        let Some(from_index) = from_positions.get(&name.to_lowercase()).copied() else {
+            changes.push(SnapshotChange::Added(name));
+            continue;
+        };
+
+        if unmoved.binary_search(&index).is_err() {
+            changes.push(SnapshotChange::Moved { name: name.clone(), from: from_index, to: index });
+        }
+        match (from[from_index].active(), entry.active()) {
+            (false, true) => changes.push(SnapshotChange::Activated(name)),
+            (true, false) => changes.push(SnapshotChange::Deactivated(name)),
+            _ => {}
+        }
+    }
+
+    changes.extend(from.iter()
+        .filter(|entry| !to_names.contains(&entry.name().to_lowercase()))
+        .map(|entry| SnapshotChange::Removed(entry.name().to_string())));
+    changes
+}
+
+/// Find the indexes of a longest strictly increasing subsequence, in increasing order
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // tails[length - 1] is the index of the smallest value ending an increasing run of that length
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (index, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|tail| values[*tail] < *value);
+        previous[index] = length.checked_sub(1).map(|length| tails[length]);
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut indexes = Vec::with_capacity(tails.len());
+    let mut current = tails.last().copied();
+    while let Some(index) = current {
+        indexes.push(index);
+        current = previous[index];
+    }
+    indexes.reverse();
+    indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::load_order::{LoadOrder, LoadOrderEntry, LoadOrderMethod};
+    use crate::load_order::snapshot::{automatic_snapshot, diff, list_snapshots, save_snapshot, SnapshotChange};
+    use crate::mod_info::instance::Instance;
+
+    fn entries(plugins: &[(&str, bool)]) -> Vec<LoadOrderEntry> {
+        plugins.iter().map(|(name, active)| LoadOrderEntry::new(name, *active)).collect()
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let from = entries(&[("a.esp", true), ("b.esp", true), ("c.esp", false), ("d.esp", true), ("gone.esp", true)]);
+        let to = entries(&[("a.esp", true), ("new.esp", false), ("c.esp", true), ("d.esp", true), ("B.esp", true)]);
+
+        assert_eq!(diff(&from, &to), [
+            SnapshotChange::Added("new.esp".to_string()),
+            SnapshotChange::Activated("c.esp".to_string()),
+            SnapshotChange::Moved { name: "B.esp".to_string(), from: 1, to: 4 },
+            SnapshotChange::Removed("gone.esp".to_string()),
+        ]);
+        assert!(diff(&from, &from).is_empty());
+    }
+
+    #[test]
+    fn skip_unreadable_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("instance");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &dir.path().join("game"), None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+
+        let load_order = LoadOrder { method: LoadOrderMethod::Asterisk, plugins: entries(&[("a.esp", true)]), implicit_count: 0 };
+        save_snapshot(&instance, &load_order, "saved").unwrap();
+        fs::write(instance.profile_path().join("snapshots/bad.toml"), "not a snapshot").unwrap();
+
+        let automatic = automatic_snapshot(&instance, &load_order, "sort").unwrap();
+        let names: Vec<String> = list_snapshots(&instance).unwrap().iter().map(|snapshot| snapshot.name().to_string()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"saved".to_string()));
+        assert!(names.contains(&automatic.name().to_string()));
+    }
+}
@@ -0,0 +1,404 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use error_chain::bail;
+use rayon::prelude::*;
+
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::header::PluginHeader;
+use crate::game_plugin::PluginFormat;
+use crate::load_order::{LoadOrder, visible_plugins};
+use crate::load_order::masterlist::{DEFAULT_GROUP, Masterlist};
+use crate::load_order::snapshot::automatic_snapshot;
+use crate::mod_info::game::get_game;
+use crate::mod_info::instance::Instance;
+
+/// A plugin to be sorted, with the information read from its header
+pub struct SortPlugin {
+    pub name: String,
+    /// Whether the game loads the plugin as a master, before every plugin that isn't one
+    pub is_master: bool,
+    pub masters: Vec<String>,
+}
+
+/// Why one node of the sorting graph must load before another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EdgeKind {
+    /// The second plugin has the first as a master
+    Master,
+    /// The first plugin is a master and the second isn't
+    MasterFlag,
+    /// The second plugin requires the first in its metadata
+    Requirement,
+    /// The second plugin loads after the first in its metadata
+    After,
+    /// The groups of the plugins load in this order, the only kind of edge dropped to break a cycle
+    Group,
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Master => "master",
+            EdgeKind::MasterFlag => "master flag",
+            EdgeKind::Requirement => "requirement",
+            EdgeKind::After => "after",
+            EdgeKind::Group => "group",
+        }
+    }
+}
+
+/// The graph of which plugins must load before which
+///
+/// Plugins are the first nodes, followed by a node separating masters from other plugins and a pair of entry and
+/// exit nodes per group, so that orderings between sets of plugins don't need an edge per pair of plugins.
+struct SortGraph {
+    names: Vec<String>,
+    edges: Vec<Vec<(usize, EdgeKind)>>,
+    plugin_count: usize,
+}
+
+impl SortGraph {
+    fn add_node(&mut self, name: String) -> usize {
+        self.names.push(name);
+        self.edges.push(Vec::new());
+        self.names.len() - 1
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, kind: EdgeKind) {
+        if from != to && !self.edges[from].contains(&(to, kind)) {
+            self.edges[from].push((to, kind));
+        }
+    }
+
+    /// Order the plugins so every edge is satisfied, keeping plugins in their current order where possible
+    ///
+    /// returns: Result<Vec<usize>, Vec<(usize, EdgeKind)>> The plugin indexes in load order, or a cycle of nodes
+    /// with the kind of edge leading to the next node
+    fn order(&self) -> Result<Vec<usize>, Vec<(usize, EdgeKind)>> {
+        let mut in_degree = vec![0usize; self.names.len()];
+        for edges in &self.edges {
+            for (to, _) in edges {
+                in_degree[*to] += 1;
+            }
+        }
+
+        // Helper nodes are taken first, plugins by their current position
+        let rank = |node: usize| if node < self.plugin_count { node + 1 } else { 0 };
+        let mut available: BinaryHeap<Reverse<(usize, usize)>> = (0..self.names.len())
+            .filter(|node| in_degree[*node] == 0)
+            .map(|node| Reverse((rank(node), node)))
+            .collect();
+
+        let mut order = Vec::with_capacity(self.plugin_count);
+        let mut visited = 0;
+        while let Some(Reverse((_, node))) = available.pop() {
+            visited += 1;
+            if node < self.plugin_count {
+                order.push(node);
+            }
+
+            for (to, _) in &self.edges[node] {
+                in_degree[*to] -= 1;
+                if in_degree[*to] == 0 {
+                    available.push(Reverse((rank(*to), *to)));
+                }
+            }
+        }
+
+        if visited == self.names.len() {
+            Ok(order)
+        } else {
+            Err(self.find_cycle(&in_degree))
+        }
+    }
+
+    /// Find a cycle among the nodes left unvisited by a topological sort
+    ///
+    /// Every unvisited node has an unvisited predecessor, so walking predecessors must loop.
+    fn find_cycle(&self, in_degree: &[usize]) -> Vec<(usize, EdgeKind)> {
+        let mut predecessors: Vec<Vec<(usize, EdgeKind)>> = vec![Vec::new(); self.names.len()];
+        for (from, edges) in self.edges.iter().enumerate() {
+            for (to, kind) in edges {
+                if in_degree[from] > 0 && in_degree[*to] > 0 {
+                    predecessors[*to].push((from, *kind));
+                }
+            }
+        }
+
+        let start = in_degree.iter().position(|degree| *degree > 0).unwrap();
+        let mut path: Vec<(usize, EdgeKind)> = Vec::new();
+        let mut seen: HashMap<usize, usize> = HashMap::new();
+        let mut node = start;
+        loop {
+            if let Some(position) = seen.get(&node) {
+                let mut cycle = path.split_off(*position);
+                cycle.reverse();
+                return cycle;
+            }
+
+            seen.insert(node, path.len());
+            let (previous, kind) = predecessors[node][0];
+            path.push((previous, kind));
+            node = previous;
+        }
+    }
+
+    /// Remove the edges placing a plugin in its group
+    fn remove_from_group(&mut self, plugin: usize) {
+        self.edges[plugin].retain(|(_, kind)| *kind != EdgeKind::Group);
+        for edges in self.edges.iter_mut() {
+            edges.retain(|edge| *edge != (plugin, EdgeKind::Group));
+        }
+    }
+
+    /// Describe a cycle as `a.esp -[master]-> b.esp -[after]-> a.esp`
+    fn describe_cycle(&self, cycle: &[(usize, EdgeKind)]) -> String {
+        let mut description = String::new();
+        for (node, kind) in cycle {
+            description.push_str(&format!("{} -[{}]-> ", self.names[*node], kind.name()));
+        }
+        description.push_str(&self.names[cycle[0].0]);
+        description
+    }
+}
+
+/// Sort plugins using their masters and the metadata from a masterlist
+///
+/// Masters load before the plugins that depend on them and master flagged plugins before other plugins, metadata
+/// adds the `req` and `after` entries and orders plugins by group. Plugins keep their relative order unless an
+/// edge moves them. A group ordering that conflicts with the other edges is dropped for the plugin with a
+/// warning, any other cycle is an error.
+///
+/// # Arguments
+///
+/// * `plugins`: The plugins to sort, in their current order
+/// * `masterlist`: The metadata, with any userlist already merged in
+///
+/// returns: Result<(Vec<usize>, Vec<String>), Error> The indexes of the plugins in sorted order, and warnings
+pub fn sort_plugins(plugins: &[SortPlugin], masterlist: &Masterlist) -> errors::Result<(Vec<usize>, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let mut graph = SortGraph {
+        names: plugins.iter().map(|plugin| plugin.name.clone()).collect(),
+        edges: vec![Vec::new(); plugins.len()],
+        plugin_count: plugins.len(),
+    };
+    let indexes: HashMap<String, usize> = plugins.iter().enumerate()
+        .map(|(index, plugin)| (plugin.name.to_lowercase(), index))
+        .collect();
+
+    let masters_node = graph.add_node("<masters>".to_string());
+    let mut groups = HashMap::new();
+    for group in masterlist.groups() {
+        let entry = graph.add_node(format!("<group {}>", group.name()));
+        let exit = graph.add_node(format!("<end of group {}>", group.name()));
+        graph.add_edge(entry, exit, EdgeKind::Group);
+        groups.insert(group.name().to_string(), (entry, exit));
+    }
+    for group in masterlist.groups() {
+        for after in group.after() {
+            match groups.get(after) {
+                Some((_, exit)) => graph.add_edge(*exit, groups[group.name()].0, EdgeKind::Group),
+                None => warnings.push(format!("Group {} loads after {}, which isn't defined", group.name(), after))
+            }
+        }
+    }
+
+    for (index, plugin) in plugins.iter().enumerate() {
+        if plugin.is_master {
+            graph.add_edge(index, masters_node, EdgeKind::MasterFlag);
+        } else {
+            graph.add_edge(masters_node, index, EdgeKind::MasterFlag);
+        }
+
+        for master in &plugin.masters {
+            if let Some(master) = indexes.get(&master.to_lowercase()) {
+                graph.add_edge(*master, index, EdgeKind::Master);
+            }
+        }
+
+        let metadata = masterlist.plugin_metadata(&plugin.name);
+        for entry in &metadata {
+            let references = entry.requirements().map(|name| (name, EdgeKind::Requirement))
+                .chain(entry.after().map(|name| (name, EdgeKind::After)));
+            for (name, kind) in references {
+                if let Some(other) = indexes.get(&name.to_lowercase()) {
+                    graph.add_edge(*other, index, kind);
+                }
+            }
+        }
+
+        let group = metadata.iter().rev().find_map(|entry| entry.group()).unwrap_or(DEFAULT_GROUP);
+        let (entry, exit) = match groups.get(group) {
+            Some(nodes) => *nodes,
+            None => {
+                warnings.push(format!("{} is in group {}, which isn't defined", plugin.name, group));
+                groups[DEFAULT_GROUP]
+            }
+        };
+        graph.add_edge(entry, index, EdgeKind::Group);
+        graph.add_edge(index, exit, EdgeKind::Group);
+    }
+
+    loop {
+        let cycle = match graph.order() {
+            Ok(order) => return Ok((order, warnings)),
+            Err(cycle) => cycle
+        };
+
+        // Break the cycle by taking a plugin out of its group
+        let next = |position: usize| cycle[(position + 1) % cycle.len()].0;
+        let breakable = cycle.iter().enumerate()
+            .find(|(position, (node, kind))| *kind == EdgeKind::Group
+                && (*node < graph.plugin_count || next(*position) < graph.plugin_count));
+        match breakable {
+            Some((position, (node, _))) => {
+                let plugin = if *node < graph.plugin_count { *node } else { next(position) };
+                warnings.push(format!("Ignored the group of {} because it conflicts with: {}",
+                    graph.names[plugin], graph.describe_cycle(&cycle)));
+                graph.remove_from_group(plugin);
+            }
+            None => bail!(ErrorKind::LoadOrderCycle(graph.describe_cycle(&cycle)))
+        }
+    }
+}
+
+/// Sort the plugins of a load order using a masterlist
+///
+/// The plugins the game always loads stay at the start of the load order. An automatic snapshot of the load
+/// order is taken before it's sorted.
+///
+/// # Arguments
+///
+/// * `instance`: The instance the load order belongs to, used to read the plugin headers
+/// * `load_order`: The load order to sort
+/// * `masterlist`: The metadata, with any userlist already merged in
+///
+/// returns: Result<Vec<String>, Error> Warnings about metadata that couldn't be used
+pub fn sort(instance: &Instance, load_order: &mut LoadOrder, masterlist: &Masterlist) -> errors::Result<Vec<String>> {
+    let format = get_game(instance.game())?.plugin_format();
+    let paths: HashMap<String, _> = visible_plugins(instance)?.into_iter()
+        .map(|plugin| (plugin.name.to_lowercase(), plugin.path))
+        .collect();
+
+    let sortable = &load_order.plugins()[load_order.implicit_count..];
+    let headers: Vec<(SortPlugin, Option<String>)> = sortable.par_iter()
+        .map(|entry| {
+            let mut plugin = SortPlugin {
+                name: entry.name().to_string(),
+                is_master: extension_is_master(entry.name(), format),
+                masters: Vec::new(),
+            };
+
+            let header = paths.get(&entry.name().to_lowercase())
+                .map(|path| PluginHeader::from_path(path, format));
+            match header {
+                Some(Ok(header)) => {
+                    plugin.is_master |= header.is_master();
+                    plugin.masters = header.masters().to_vec();
+                    (plugin, None)
+                }
+                Some(Err(err)) => (plugin, Some(format!("{} couldn't be read: {err}", entry.name()))),
+                None => (plugin, None)
+            }
+        })
+        .collect();
+
+    let mut warnings: Vec<String> = headers.iter().filter_map(|(_, warning)| warning.clone()).collect();
+    let plugins: Vec<SortPlugin> = headers.into_iter().map(|(plugin, _)| plugin).collect();
+
+    let (order, sort_warnings) = sort_plugins(&plugins, masterlist)?;
+    warnings.extend(sort_warnings);
+    automatic_snapshot(instance, load_order, "sort")?;
+    load_order.reorder(&order);
+
+    Ok(warnings)
+}
+
+/// Whether the game loads a plugin as a master because of its extension
+fn extension_is_master(name: &str, format: PluginFormat) -> bool {
+    let name = name.to_lowercase();
+    format.extension_implies_master() && (name.ends_with(".esm") || name.ends_with(".esl"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load_order::masterlist::Masterlist;
+    use crate::load_order::sorting::{sort_plugins, SortPlugin};
+
+    fn plugin(name: &str, is_master: bool, masters: &[&str]) -> SortPlugin {
+        SortPlugin {
+            name: name.to_string(),
+            is_master,
+            masters: masters.iter().map(|master| master.to_string()).collect(),
+        }
+    }
+
+    fn sorted<'a>(plugins: &'a [SortPlugin], masterlist: &Masterlist) -> Vec<&'a str> {
+        let (order, _) = sort_plugins(plugins, masterlist).unwrap();
+        order.into_iter().map(|index| plugins[index].name.as_str()).collect()
+    }
+
+    #[test]
+    fn sort_with_metadata() {
+        let masterlist = Masterlist::parse("
+groups:
+  - name: default
+  - name: late
+    after: [default]
+plugins:
+  - name: 'Patch.esp'
+    group: late
+  - name: 'Addon.esp'
+    after: ['Other.esp']
+  - name: 'Needs\\.*\\.esp'
+    req:
+      - name: 'Base.esm'
+        display: 'Base'
+").unwrap();
+
+        let plugins = [
+            plugin("Patch.esp", false, &[]),
+            plugin("Addon.esp", false, &["Base.esm"]),
+            plugin("Other.esp", false, &[]),
+            plugin("NeedsBase.esp", false, &[]),
+            plugin("Base.esm", true, &[]),
+            plugin("Untouched.esp", false, &[]),
+        ];
+        assert_eq!(sorted(&plugins, &masterlist),
+            ["Base.esm", "Other.esp", "Addon.esp", "NeedsBase.esp", "Untouched.esp", "Patch.esp"]);
+    }
+
+    #[test]
+    fn sorted_order_is_stable() {
+        let masterlist = Masterlist::parse("plugins: []").unwrap();
+        let plugins = [plugin("C.esp", false, &[]), plugin("A.esp", false, &[]), plugin("B.esp", false, &["C.esp"])];
+        assert_eq!(sorted(&plugins, &masterlist), ["C.esp", "A.esp", "B.esp"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let masterlist = Masterlist::parse("
+groups:
+  - name: early
+  - name: default
+    after: [early]
+plugins:
+  - name: 'A.esp'
+    after: ['B.esp']
+  - name: 'C.esp'
+    group: early
+").unwrap();
+
+        let plugins = [plugin("A.esp", false, &[]), plugin("B.esp", false, &["A.esp"])];
+        let err = sort_plugins(&plugins, &masterlist).err().unwrap();
+        assert!(err.to_string().contains("A.esp -[master]-> B.esp -[after]-> A.esp"));
+
+        // C.esp needs a plugin from a later group, so its group is ignored
+        let plugins = [plugin("D.esp", false, &[]), plugin("C.esp", false, &["D.esp"])];
+        let (order, warnings) = sort_plugins(&plugins, &masterlist).unwrap();
+        assert_eq!(order, [0, 1]);
+        assert_eq!(warnings.len(), 1);
+    }
+}
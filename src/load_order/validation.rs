@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+
+use crate::errors;
+use crate::game_plugin::header::PluginHeader;
+use crate::load_order::dirty_records::{DirtyKind, DirtyPlugin, scan_load_order};
+use crate::load_order::{LoadOrder, visible_plugins};
+use crate::mod_info::game::get_game;
+use crate::mod_info::instance::Instance;
+
+/// A problem with the masters of an active plugin that will stop the game from loading
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A master of the plugin isn't installed
+    MissingMaster { plugin: String, master: String },
+    /// A master of the plugin is installed but isn't active
+    InactiveMaster { plugin: String, master: String },
+    /// A master of the plugin loads after it
+    MasterLoadsAfter { plugin: String, master: String },
+    /// The plugin's header couldn't be read
+    UnreadablePlugin { plugin: String, error: String },
+    /// The plugin or one of its masters couldn't be read while scanning for dirty records
+    UnscannedPlugin { plugin: String, error: String },
+    /// The plugin has identical to master records or deletes references or navmeshes from its masters
+    DirtyPlugin { plugin: String, identical_to_master: usize, deleted_references: usize, deleted_navmeshes: usize },
+}
+
+impl ValidationIssue {
+    /// Whether the issue stops the game from loading or is likely to crash it
+    ///
+    /// Identical to master records and deleted references only cause subtle problems, deleted navmeshes crash
+    /// the game when the cell they were in loads.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            ValidationIssue::DirtyPlugin { deleted_navmeshes, .. } => *deleted_navmeshes > 0,
+            ValidationIssue::UnscannedPlugin { .. } => false,
+            _ => true
+        }
+    }
+}
+
+impl From<&DirtyPlugin> for ValidationIssue {
+    fn from(plugin: &DirtyPlugin) -> Self {
+        ValidationIssue::DirtyPlugin {
+            plugin: plugin.name.clone(),
+            identical_to_master: plugin.count(DirtyKind::IdenticalToMaster),
+            deleted_references: plugin.count(DirtyKind::DeletedReference),
+            deleted_navmeshes: plugin.count(DirtyKind::DeletedNavmesh),
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingMaster { plugin, master } =>
+                write!(f, "{plugin} requires {master}, which is missing"),
+            ValidationIssue::InactiveMaster { plugin, master } =>
+                write!(f, "{plugin} requires {master}, which isn't active"),
+            ValidationIssue::MasterLoadsAfter { plugin, master } =>
+                write!(f, "{plugin} requires {master}, which loads after it"),
+            ValidationIssue::UnreadablePlugin { plugin, error } =>
+                write!(f, "{plugin} couldn't be read: {error}"),
+            ValidationIssue::UnscannedPlugin { plugin, error } =>
+                write!(f, "{plugin} couldn't be scanned for dirty records: {error}"),
+            ValidationIssue::DirtyPlugin { plugin, identical_to_master, deleted_references, deleted_navmeshes } =>
+                write!(f, "{plugin} is dirty: {identical_to_master} identical to master records, \
+                    {deleted_references} deleted references, {deleted_navmeshes} deleted navmeshes"),
+        }
+    }
+}
+
+/// Check the masters of every active plugin in a load order
+///
+/// # Arguments
+///
+/// * `instance`: The instance the load order belongs to, used to find the plugin files
+/// * `load_order`: The load order to check
+///
+/// returns: Result<Vec<ValidationIssue>, Error> The issues found, in load order
+pub fn validate(instance: &Instance, load_order: &LoadOrder) -> errors::Result<Vec<ValidationIssue>> {
+    let format = get_game(instance.game())?.plugin_format();
+    let paths: HashMap<String, PathBuf> = visible_plugins(instance)?.into_iter()
+        .map(|plugin| (plugin.name.to_lowercase(), plugin.path))
+        .collect();
+
+    let masters: Vec<(usize, errors::Result<Vec<String>>)> = load_order.plugins().par_iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.active())
+        .filter_map(|(index, entry)| {
+            let path = paths.get(&entry.name().to_lowercase())?;
+            Some((index, PluginHeader::from_path(path, format).map(|header| header.masters().to_vec())))
+        })
+        .collect();
+
+    let mut issues = Vec::new();
+    for (index, masters) in masters {
+        let plugin = load_order.plugins()[index].name().to_string();
+        let masters = match masters {
+            Ok(masters) => masters,
+            Err(err) => {
+                issues.push(ValidationIssue::UnreadablePlugin { plugin, error: err.to_string() });
+                continue;
+            }
+        };
+
+        for master in masters {
+            match load_order.position(&master) {
+                None => issues.push(ValidationIssue::MissingMaster { plugin: plugin.clone(), master }),
+                Some(position) if !load_order.plugins()[position].active() =>
+                    issues.push(ValidationIssue::InactiveMaster { plugin: plugin.clone(), master }),
+                Some(position) if position > index =>
+                    issues.push(ValidationIssue::MasterLoadsAfter { plugin: plugin.clone(), master }),
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Load the load order of an instance's active profile and check it
+///
+/// This is run before deploying and launching the game.
+///
+/// returns: Result<Vec<ValidationIssue>, Error> The issues found, in load order
+pub fn validate_instance(instance: &Instance) -> errors::Result<Vec<ValidationIssue>> {
+    let load_order = LoadOrder::load(instance)?;
+    validate(instance, &load_order)
+}
+
+/// Scan the active plugins in a load order for dirty records
+///
+/// This reads every record of the plugins and their masters, so it's slower than [validate] and only runs
+/// before launching the game if enabled.
+///
+/// returns: Result<Vec<ValidationIssue>, Error> An issue for every dirty plugin in load order, followed by the
+/// plugins that couldn't be scanned
+pub fn validate_dirty_plugins(instance: &Instance, load_order: &LoadOrder) -> errors::Result<Vec<ValidationIssue>> {
+    let scan = scan_load_order(instance, load_order)?;
+    Ok(scan.plugins.iter()
+        .filter(|plugin| plugin.is_dirty())
+        .map(ValidationIssue::from)
+        .chain(scan.unreadable.into_iter().map(|(plugin, error)| ValidationIssue::UnscannedPlugin { plugin, error }))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::game_plugin::PluginFormat;
+    use crate::game_plugin::test_util::header;
+    use crate::load_order::{LoadOrder, LoadOrderEntry, LoadOrderMethod};
+    use crate::load_order::validation::{validate, ValidationIssue};
+    use crate::mod_info::instance::Instance;
+
+    #[test]
+    fn master_issues() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("instance");
+        let game = dir.path().join("game");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &game, None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+
+        let data = game.join("Data");
+        fs::create_dir_all(&data).unwrap();
+        let plugins: [(&str, &[&str]); 8] = [
+            ("Base.esm", &[]),
+            ("A.esp", &["Base.esm"]),
+            ("B.esp", &["Missing.esm"]),
+            ("C.esp", &["Off.esp"]),
+            ("Off.esp", &["Gone.esm"]),
+            ("D.esp", &["Base.esm", "Late.esp"]),
+            ("Late.esp", &[]),
+            ("Extra.esp", &[]),
+        ];
+        for (name, masters) in plugins {
+            fs::write(data.join(name), header(PluginFormat::SkyrimSE, 0, masters, 0)).unwrap();
+        }
+        fs::write(data.join("Bad.esp"), b"not a plugin").unwrap();
+
+        let load_order = LoadOrder {
+            method: LoadOrderMethod::Asterisk,
+            plugins: ["Base.esm", "a.esp", "B.esp", "C.esp", "Off.esp", "D.esp", "Late.esp", "Bad.esp"].iter()
+                .map(|name| LoadOrderEntry::new(name, *name != "Off.esp"))
+                .chain([LoadOrderEntry::new("Extra.esp", false)])
+                .collect(),
+            implicit_count: 0,
+        };
+
+        let issues = validate(&instance, &load_order).unwrap();
+        assert_eq!(issues.len(), 4);
+        assert_eq!(issues[..3], [
+            ValidationIssue::MissingMaster { plugin: "B.esp".to_string(), master: "Missing.esm".to_string() },
+            ValidationIssue::InactiveMaster { plugin: "C.esp".to_string(), master: "Off.esp".to_string() },
+            ValidationIssue::MasterLoadsAfter { plugin: "D.esp".to_string(), master: "Late.esp".to_string() },
+        ]);
+        assert!(matches!(&issues[3], ValidationIssue::UnreadablePlugin { plugin, .. } if plugin == "Bad.esp"));
+        assert!(issues.iter().all(ValidationIssue::is_fatal));
+    }
+}
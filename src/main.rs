@@ -2,28 +2,48 @@ use std::collections::HashMap;
 use std::{io, thread};
 use std::fmt::Write;
 use std::io::stdin;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::mpsc::{channel, Receiver};
 use std::time::Duration;
-use clap::{Arg, ArgAction, ArgGroup, command, Command, value_parser, ValueHint};
-use clap::builder::ValueParser;
+use chrono::Local;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, command, Command, value_parser, ValueHint};
 use clap::ValueHint::DirPath;
-use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use error_chain::bail;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use rayon::prelude::*;
 use regex::Regex;
 use url::Url;
 
 use dat_mod_manager::gui_application::gui_application;
 use dat_mod_manager::constants;
+use dat_mod_manager::conflicts::ConflictMap;
+use dat_mod_manager::deployment;
+use dat_mod_manager::download_manager::{DownloadManager, DownloadState};
+use dat_mod_manager::download_manager::metadata as download_metadata;
+use dat_mod_manager::download_manager::metadata::{DownloadMetadata, DownloadStatus};
+use dat_mod_manager::deployment::DeploymentManifest;
+use dat_mod_manager::{errors, ipc, launch_file, url_handler};
+use dat_mod_manager::errors::ErrorKind;
+use dat_mod_manager::game_plugin::light::{copy_as_light, light_eligibility, LightEligibility};
+use dat_mod_manager::load_order::{implicit_plugins, LoadOrder, visible_plugins, VisiblePlugin};
+use dat_mod_manager::load_order::record_conflicts::RecordIndex;
+use dat_mod_manager::load_order::masterlist::{Masterlist, MASTERLIST_FILE, USERLIST_FILE};
+use dat_mod_manager::load_order::{dirty_records, snapshot, sorting, validation};
+use dat_mod_manager::load_order::dirty_records::{DirtyKind, DirtyPlugin};
+use dat_mod_manager::load_order::validation::ValidationIssue;
 use dat_mod_manager::manager_config::ManagerConfig;
-use dat_mod_manager::mod_info::instance;
+use dat_mod_manager::mod_info::{hidden_files, instance};
+use dat_mod_manager::mod_info::game::get_game;
 use dat_mod_manager::mod_info::instance::{Instance, get_instances, delete_instance, list_instances};
-use dat_mod_manager::plugin::downloader::DownloadFailed;
+use dat_mod_manager::plugin::downloader::parse_source;
 use dat_mod_manager::plugin::plugin_manager::PluginManager;
-use dat_mod_manager::plugin::PluginManager;
 
+use dat_mod_manager::util;
 use dat_mod_manager::util::delete_dir_with_callback;
 
-mod util;
+/// How often the downloads commands check their downloads for new states
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 fn main() -> ExitCode {
     util::ensure_config_dir();
@@ -31,7 +51,7 @@ fn main() -> ExitCode {
 
     let cli = cmd(&config).get_matches();
     let mut plugin_man = PluginManager::default();
-    plugin_man.register_plugins();
+    plugin_man.register_plugins(&config);
 
     if let Some(subcommand) = cli.subcommand() {
         return match subcommand {
@@ -47,6 +67,8 @@ fn main() -> ExitCode {
                                         matches.get_one::<PathBuf>("DOWNLOADS_PATH"),
                                         matches.get_one::<PathBuf>("OVERWRITE_PATH"),
                                         matches.get_one::<PathBuf>("PROFILES_PATH"),
+                                        matches.get_one::<PathBuf>("GAME_PATH"),
+                                        matches.get_one::<PathBuf>("APP_DATA_PATH"),
                                         *matches.get_one::<bool>("DEFAULT").unwrap()),
             ("delete-instance", matches) =>
                 delete_instance_command(&mut config,
@@ -54,12 +76,77 @@ fn main() -> ExitCode {
                                         *matches.get_one::<bool>("CLEAR").unwrap(),
                                         *matches.get_one::<bool>("FORCE").unwrap()),
             ("download", matches) => {
-
                 download_command(&mut config, &mut plugin_man,
                                  matches.get_one::<Url>("URL").cloned().unwrap(),
                                  matches.get_one::<String>("INSTANCE").cloned(),
                                  *matches.get_one::<bool>("NO-PROMPT").unwrap())
             }
+            ("downloads", matches) =>
+                downloads_command(&config, &plugin_man, matches.get_one::<String>("INSTANCE").cloned(), matches.subcommand()),
+            ("conflicts", matches) =>
+                conflicts_command(&config,
+                                  matches.get_one::<String>("INSTANCE").cloned(),
+                                  matches.get_one::<String>("MOD").cloned()),
+            ("hide", matches) =>
+                hide_command(&config,
+                             matches.get_one::<String>("INSTANCE").cloned(),
+                             matches.get_one::<String>("MOD").cloned().unwrap(),
+                             matches.get_many::<String>("PATTERN").unwrap().cloned().collect(),
+                             true),
+            ("unhide", matches) =>
+                hide_command(&config,
+                             matches.get_one::<String>("INSTANCE").cloned(),
+                             matches.get_one::<String>("MOD").cloned().unwrap(),
+                             matches.get_many::<String>("PATTERN").unwrap().cloned().collect(),
+                             false),
+            ("plugins", matches) =>
+                plugins_command(&config, matches.get_one::<String>("INSTANCE").cloned(), matches.subcommand()),
+            ("record-conflicts", matches) =>
+                record_conflicts_command(&config,
+                                         matches.get_one::<String>("INSTANCE").cloned(),
+                                         matches.get_one::<String>("PLUGIN").cloned(),
+                                         matches.get_flag("ALL")),
+            ("dirty-plugins", matches) =>
+                dirty_plugins_command(&config,
+                                      matches.get_one::<String>("INSTANCE").cloned(),
+                                      matches.get_many::<String>("PLUGIN").map(|plugins| plugins.cloned().collect()),
+                                      matches.get_flag("RECORDS")),
+            ("esl-candidates", matches) =>
+                esl_candidates_command(&config, matches.get_one::<String>("INSTANCE").cloned()),
+            ("esl-flag", matches) =>
+                esl_flag_command(&config,
+                                 matches.get_one::<String>("INSTANCE").cloned(),
+                                 matches.get_many::<String>("PLUGIN").unwrap().cloned().collect(),
+                                 matches.get_one::<String>("MOD").cloned()),
+            ("deploy", matches) =>
+                deploy_command(&config, matches.get_one::<String>("INSTANCE").cloned()),
+            ("launch", matches) =>
+                launch_command(&config,
+                               matches.get_one::<String>("INSTANCE").cloned(),
+                               matches.get_one::<String>("PROGRAM").cloned().unwrap(),
+                               matches.get_many::<String>("ARGS").map(|args| args.cloned().collect()).unwrap_or_default()),
+            ("purge", matches) =>
+                purge_command(&config, matches.get_one::<String>("INSTANCE").cloned()),
+            ("register-handler", matches) =>
+                register_handler_command(&plugin_man,
+                                         matches.get_many::<String>("SCHEME").map(|schemes| schemes.cloned().collect()),
+                                         matches.get_flag("SHOW")),
+            ("unregister-handler", matches) =>
+                unregister_handler_command(&plugin_man, matches.get_many::<String>("SCHEME").map(|schemes| schemes.cloned().collect())),
+            ("download-limits", matches) =>
+                download_limits_command(&mut config,
+                                        matches.get_one::<u64>("SPEED").copied(),
+                                        matches.get_one::<u64>("PER_DOWNLOAD").copied(),
+                                        matches.get_one::<usize>("PER_HOST").copied()),
+            ("verify-downloads", matches) =>
+                verify_downloads_command(&config,
+                                         matches.get_one::<String>("INSTANCE").cloned(),
+                                         matches.get_many::<String>("FILE").map(|files| files.cloned().collect())),
+            ("verify-deployment", matches) =>
+                verify_deployment_command(&config,
+                                          matches.get_one::<String>("INSTANCE").cloned(),
+                                          *matches.get_one::<bool>("REPAIR").unwrap(),
+                                          *matches.get_one::<bool>("NO-PROMPT").unwrap()),
             _ => {
                 println!("Unknown Subcommand");
                 ExitCode::FAILURE
@@ -127,6 +214,8 @@ fn create_instance_command(
     downloads_path: Option<&PathBuf>,
     overwrite_path: Option<&PathBuf>,
     profiles_path: Option<&PathBuf>,
+    game_path: Option<&PathBuf>,
+    app_data_path: Option<&PathBuf>,
     default: bool
 ) -> ExitCode {
     let instances = get_instances();
@@ -224,7 +313,19 @@ fn create_instance_command(
         Some(path) => path.clone()
     };
 
-    match instance::create_instance(&name, &game, &base_path, &mods_path, &downloads_path, &overwrite_path, &profiles_path) {
+    let game_path = match game_path {
+        None => {
+            println!("Enter the directory the game is installed in:");
+            let mut string_path: String = "".to_string();
+            io::stdin()
+                .read_line(&mut string_path)
+                .expect("Failed to get user input");
+            PathBuf::from(string_path.trim())
+        }
+        Some(path) => path.clone()
+    };
+
+    match instance::create_instance(&name, &game, &base_path, &mods_path, &downloads_path, &overwrite_path, &profiles_path, &game_path, app_data_path.map(|path| path.as_path())) {
         Ok(_) => {
             println!("Successfully created instance")
         }
@@ -338,6 +439,23 @@ fn delete_instance_command(config: &mut ManagerConfig, name: String, remove: boo
 }
 
 fn download_command(config: &mut ManagerConfig, plugin_man: &mut PluginManager, url: Url, instance: Option<String>, no_prompt: bool) -> ExitCode {
+    // Hand the download to the manager that's already running instead of competing with it
+    let server = match ipc::serve_downloads() {
+        Ok(Some(server)) => Some(server),
+        Ok(None) => return match ipc::forward_download(&url, instance.as_deref()) {
+            Ok(_) => {
+                println!("Sent the download to the running manager");
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                println!("Failed to send the download to the running manager, error: {err}");
+                ExitCode::FAILURE
+            }
+        },
+        // Without a session bus there's nothing to forward to, so just download
+        Err(_) => None
+    };
+
     // Get instance
     let instance: Instance = {
         let instances = list_instances();
@@ -378,183 +496,1538 @@ fn download_command(config: &mut ManagerConfig, plugin_man: &mut PluginManager,
         }
     };
 
-    // Select downloader
-    let protocol = match url.scheme() {
-        "" => "http",
-        scheme => scheme
-    };
-    let downloaders = plugin_man.downloaders().get_downloaders_for_protocol(protocol);
-
-    if (downloaders.is_empty()) {
+    // Select downloaders
+    let downloaders = plugin_man.downloaders_for_url(&url);
+    if downloaders.is_empty() {
         println!("Failed to find downloader for that protocol, is it supposed to be http?");
         return ExitCode::FAILURE
     }
 
-    let downloader = downloaders[0];
+    // Download
+    let download_manager = DownloadManager::with_limits(config.max_concurrent_downloads, config.download_limits())
+        .with_retry_policy(config.retry_policy());
+    let progress = MultiProgress::new();
+    let (sender, receiver) = channel();
+    let name = DownloadMetadata::new(&url).file_name;
+    download_manager.download(downloaders, url, instance.downloads_path(), sender);
+
+    // Queue the downloads other processes forward while this one runs
+    let failed = watch_downloads(&progress, vec![watch_download(&progress, name, receiver)], |downloads| {
+        let Some(server) = &server else {
+            return
+        };
+
+        if let Some(limits) = server.limit_changes().last() {
+            download_manager.set_limits(limits);
+        }
+        let mut requests: Vec<ipc::DownloadRequest> = server.requests().collect();
+        // Stop taking downloads before returning, so one forwarded while this exits isn't lost
+        if downloads.is_empty() && requests.is_empty() {
+            requests = server.stop();
+        }
+        for request in requests {
+            let downloads_path = match &request.instance {
+                Some(name) => match Instance::from_name(name) {
+                    Ok(instance) => instance.downloads_path(),
+                    Err(err) => {
+                        progress.suspend(|| println!("Failed to get instance {name} for {}, error: {err}", request.url));
+                        continue
+                    }
+                },
+                None => instance.downloads_path()
+            };
+            let downloaders = plugin_man.downloaders_for_url(&request.url);
+            if downloaders.is_empty() {
+                progress.suspend(|| println!("No downloader supports {}", request.url));
+                continue
+            }
+
+            let (sender, receiver) = channel();
+            let name = DownloadMetadata::new(&request.url).file_name;
+            download_manager.download(downloaders, request.url, downloads_path, sender);
+            downloads.push(watch_download(&progress, name, receiver));
+        }
+    });
+
+    if failed > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+/// A download whose progress is being shown
+struct WatchedDownload {
+    name: String,
+    pb: ProgressBar,
+    receiver: Receiver<DownloadState>,
+}
+
+/// Add a progress bar for a download
+fn watch_download(progress: &MultiProgress, name: String, receiver: Receiver<DownloadState>) -> WatchedDownload {
+    let pb = progress.add(ProgressBar::new(0));
+    pb.set_style(download_progress_style());
+    pb.set_message(format!("{name}: Queued"));
+    WatchedDownload { name, pb, receiver }
+}
+
+/// Show the progress of downloads until they've all finished
+///
+/// # Arguments
+///
+/// * `progress`: The progress bars of the downloads
+/// * `downloads`: The downloads to watch
+/// * `poll`: Called between each check of the downloads, can add more downloads to watch
+///
+/// returns: usize The number of downloads that failed or were cancelled
+fn watch_downloads(progress: &MultiProgress, mut downloads: Vec<WatchedDownload>, mut poll: impl FnMut(&mut Vec<WatchedDownload>)) -> usize {
+    let mut failed = 0;
+
+    loop {
+        poll(&mut downloads);
+        if downloads.is_empty() {
+            return failed
+        }
+
+        downloads.retain(|WatchedDownload { name, pb, receiver }| {
+            while let Ok(state) = receiver.try_recv() {
+                match state {
+                    DownloadState::Queued => pb.set_message(format!("{name}: Queued")),
+                    DownloadState::Connecting => pb.set_message(format!("{name}: Connecting")),
+                    DownloadState::Downloading(download_progress) => {
+                        if let Some(total) = download_progress.total {
+                            pb.set_length(total);
+                        }
+                        pb.set_position(download_progress.downloaded);
+                        pb.set_message(name.clone());
+                    }
+                    DownloadState::Retrying { error, delay } => {
+                        pb.set_message(format!("{name}: Retrying in {}s", delay.as_secs_f64().ceil()));
+                        progress.suspend(|| println!("Failed to download {name}, retrying, error: {error}"));
+                    }
+                    DownloadState::Paused => pb.set_message(format!("{name}: Paused")),
+                    DownloadState::Completed(path) => {
+                        pb.finish_and_clear();
+                        progress.suspend(|| println!("Downloaded {}", path.display()));
+                        return false
+                    }
+                    DownloadState::Failed(err) => {
+                        pb.finish_and_clear();
+                        progress.suspend(|| println!("Failed to download {name}, error: {err}"));
+                        failed += 1;
+                        return false
+                    }
+                    DownloadState::Cancelled => {
+                        pb.finish_and_clear();
+                        progress.suspend(|| println!("Cancelled {name}"));
+                        failed += 1;
+                        return false
+                    }
+                }
+            }
+            true
+        });
+        thread::sleep(DOWNLOAD_POLL_INTERVAL);
+    }
+}
 
-    let style = ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {msg:!40} {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+fn download_progress_style() -> ProgressStyle {
+    ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {msg:!40} {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
         .unwrap()
         .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-");
-    let pb = ProgressBar::new(100000);
-    pb.set_style(style);
+        .progress_chars("#>-")
+}
 
-    let mut callback = |total: u64, progress: u64, file: String| {
-        // pb.set_length(total);
-        pb.set_position(progress);
-        pb.set_message(file);
+fn downloads_command(config: &ManagerConfig, plugin_man: &PluginManager, instance: Option<String>, subcommand: Option<(&str, &ArgMatches)>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
     };
+    let downloads_path = instance.downloads_path();
 
-    // Download
-    let result = downloader.download(&url, instance.downloads_path(), &mut callback);
+    let result = match subcommand {
+        None | Some(("list", _)) => download_metadata::load_all(&downloads_path).map(|downloads| {
+            for download in &downloads {
+                let size = download.size.map(|size| HumanBytes(size).to_string()).unwrap_or_else(|| "?".to_string());
+                let status = download_status_name(download);
+                println!("{status:<12} {} ({size}) added {} from {}",
+                         download.file_name, download.added.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), download.url);
+                if let (Some(mod_id), Some(file_id)) = (download.mod_id, download.file_id) {
+                    println!("             {} mod {mod_id} file {file_id}{}", download.game.as_deref().unwrap_or("Unknown game"),
+                             download.version.as_ref().map(|version| format!(" version {version}")).unwrap_or_default());
+                }
+                if let Some(error) = download.error.as_ref().filter(|_| download.status == DownloadStatus::Failed) {
+                    println!("             {error}");
+                }
+                // Only worth showing once something went wrong, a single successful attempt says nothing new
+                if download.attempts.iter().any(|attempt| attempt.error.is_some()) {
+                    for attempt in &download.attempts {
+                        let result = attempt.error.as_deref().unwrap_or("Downloaded");
+                        println!("             {} {}: {result}", attempt.started.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), attempt.downloader);
+                    }
+                }
+            }
+            println!("{} downloads", downloads.len());
+        }),
+        Some(("retry", matches)) => {
+            let downloads = match matches.get_many::<String>("FILE") {
+                Some(files) => files.map(|file| download_metadata::find(&downloads_path, file)).collect(),
+                None => download_metadata::load_all(&downloads_path)
+                    .map(|downloads| downloads.into_iter().filter(|download| download.status.is_unfinished()).collect())
+            };
 
-    pb.finish_and_clear();
+            return match downloads {
+                Ok(downloads) => retry_downloads(config, plugin_man, &downloads_path, downloads),
+                Err(err) => {
+                    println!("Failed to load downloads, error: {err}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Some(("remove", matches)) => {
+            let keep_file = matches.get_flag("KEEP_FILE");
+            matches.get_many::<String>("FILE").unwrap().try_for_each(|file| {
+                let download = download_metadata::find(&downloads_path, file)?;
+                download_metadata::remove(&downloads_path, &download, keep_file)?;
+                println!("Removed {file}");
+                Ok(())
+            })
+        }
+        Some(_) => {
+            println!("Unknown Subcommand");
+            return ExitCode::FAILURE
+        }
+    };
 
     match result {
         Ok(_) => ExitCode::SUCCESS,
         Err(err) => {
-            println!("{err}");
+            println!("Downloads command failed, error: {err}");
             ExitCode::FAILURE
         }
     }
 }
 
-fn cmd(config: &ManagerConfig) -> Command {
-    command!()
-        .subcommand(
-            Command::new("list-instances")
-                .about("List all the instances")
-        )
-        .subcommand(
-            Command::new("set-default-instance")
-                .about("Set the default instance")
-                .long_about("Set the default instance. This is the instance that gets used when the \
-                             downloader cannot figure out which instance to download a mod to. \
-                             Pass none to be prompted everytime instead")
-                .arg(
-                    Arg::new("INSTANCE")
-                        .allow_hyphen_values(true)
-                        .required(true)
-                )
-        )
-        .subcommand(
-            Command::new("create-instance")
-                .about("Create a new instance")
-                .after_help("For the paths (besides the BASE_PATH), relative paths will be relative to the BASE_PATH, absolute paths will work as expected\n\
-                Any arguments or options not provided will be prompted for")
-                .group(
-                    ArgGroup::new("Paths")
-                        .args(["BASE_PATH", "MODS_PATH", "DOWNLOADS_PATH", "OVERWRITE_PATH", "PROFILES_PATH"])
-                )
+fn download_status_name(download: &DownloadMetadata) -> &'static str {
+    match download.status {
+        DownloadStatus::Queued => "Queued",
+        DownloadStatus::Downloading => "Interrupted",
+        DownloadStatus::Paused => "Paused",
+        DownloadStatus::Completed => "Completed",
+        DownloadStatus::Failed => "Failed",
+        DownloadStatus::Cancelled => "Cancelled",
+    }
+}
 
-                .arg(
-                    Arg::new("NAME")
-                        .allow_hyphen_values(true)
-                        .help("The name of the new profile")
-                        .required(true)
-                )
-                .arg(
-                    Arg::new("GAME")
-                        .allow_hyphen_values(true)
-                        .help("The game this profile is for")
-                        .required(true)
-                        // .value_parser() TODO: available games
-                )
-                .arg(
-                    Arg::new("BASE_PATH")
-                        .long("base-path")
-                        .short('b')
-                        .value_parser(value_parser!(std::path::PathBuf))
-                        .help("The base directory of the instance")
-                        .value_hint(DirPath)
-                )
-                .arg(
-                    Arg::new("MODS_PATH")
-                        .long("mods-path")
-                        .short('m')
-                        .value_parser(value_parser!(std::path::PathBuf))
-                        .help("The directory in which mods are stored")
-                        .value_hint(DirPath)
-                )
-                .arg(
-                    Arg::new("DOWNLOADS_PATH")
-                        .long("downloads-path")
-                        .short('d')
-                        .value_parser(value_parser!(std::path::PathBuf))
-                        .help("The directory in which mods are downloaded to")
-                        .value_hint(DirPath)
-                )
-                .arg(
-                    Arg::new("OVERWRITE_PATH")
-                        .long("overwrite-path")
-                        .short('o')
-                        .value_parser(value_parser!(std::path::PathBuf))
-                        .help("The directory in which runtime changes are written to")
-                        .value_hint(DirPath)
-                )
-                .arg(
-                    Arg::new("PROFILES_PATH")
-                        .long("profiles-path")
-                        .short('p')
-                        .help("The directory in which profiles information is stored")
-                        .value_parser(value_parser!(std::path::PathBuf))
-                        .value_hint(DirPath)
-                )
-                .arg(
-                    Arg::new("DEFAULT")
-                        .long("default")
-                        .short('D')
-                        .help("Set the newly created profile as the default profile")
-                        .action(ArgAction::SetTrue)
-                )
-        )
-        .subcommand(
-            Command::new("delete-instance")
-                .about("Delete an instance, optionally removing all it's content")
-                .arg(
-                    Arg::new("NAME")
-                        .allow_hyphen_values(true)
-                        .help("The name of the profile to delete")
-                        .required(true)
-                )
-                .arg(
-                    Arg::new("CLEAR")
-                        .long("clear")
-                        .short('c')
-                        .help("Remove this instances data (mods, downloads, etc)")
-                        .action(ArgAction::SetTrue)
-                )
-                .arg(
-                    Arg::new("FORCE")
-                        .long("force")
-                        .short('f')
-                        .help("Skip the prompt double-checking you're sure you want to delete the profile")
-                        .action(ArgAction::SetTrue)
-                )
-        )
-        .subcommand(
-            Command::new("download")
-                .about("Download a mod")
-                .long_about("Download a mod using the given URL, uses the default instance if no instance is specified")
-                .arg(
-                    Arg::new("URL")
-                        .value_hint(ValueHint::Url)
-                        .help("The URL of the mod to download")
-                        .required(true)
-                        .value_parser(value_parser!(Url))
-                )
-                .arg(
-                    Arg::new("INSTANCE")
-                        .long("instance")
-                        .short('i')
-                        .help("Specify an instance to download the mod to")
-                )
-                .arg(
-                    Arg::new("NO-PROMPT")
-                        .long("no-prompt")
-                        .short('n')
-                        .help("Fail instead of prompting if no instance is set")
-                        .action(ArgAction::SetTrue)
-                )
-        )
+/// Queue downloads again from their metadata and wait for them all to finish
+fn retry_downloads(config: &ManagerConfig, plugin_man: &PluginManager, downloads_path: &Path, downloads: Vec<DownloadMetadata>) -> ExitCode {
+    if downloads.is_empty() {
+        println!("No downloads to retry");
+        return ExitCode::SUCCESS
+    }
+
+    let download_manager = DownloadManager::with_limits(config.max_concurrent_downloads, config.download_limits())
+        .with_retry_policy(config.retry_policy());
+    let progress = MultiProgress::new();
+    let mut running = Vec::new();
+    let mut failed = 0;
+
+    for mut download in downloads {
+        let downloaders = download.url().map(|url| plugin_man.downloaders_for_url(&url)).unwrap_or_default();
+        if downloaders.is_empty() {
+            println!("No downloader supports {}", download.url);
+            failed += 1;
+            continue;
+        }
+
+        let name = download.file_name.clone();
+        download.status = DownloadStatus::Queued;
+        let (sender, receiver) = channel();
+        match download_manager.restore(downloaders, download, downloads_path.to_path_buf(), sender) {
+            Ok(_) => running.push(watch_download(&progress, name, receiver)),
+            Err(err) => {
+                println!("Failed to retry {name}, error: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    failed += watch_downloads(&progress, running, |_| {});
+
+    if failed > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+fn download_limits_command(config: &mut ManagerConfig, speed: Option<u64>, per_download: Option<u64>, per_host: Option<usize>) -> ExitCode {
+    if speed.is_some() || per_download.is_some() || per_host.is_some() {
+        // 0 removes a limit
+        if let Some(speed) = speed {
+            config.download_speed_limit = (speed > 0).then_some(speed);
+        }
+        if let Some(per_download) = per_download {
+            config.per_download_speed_limit = (per_download > 0).then_some(per_download);
+        }
+        if let Some(per_host) = per_host {
+            config.max_downloads_per_host = (per_host > 0).then_some(per_host);
+        }
+
+        if let Err(err) = config.save() {
+            println!("Failed to save the config, error: {err}");
+            return ExitCode::FAILURE
+        }
+
+        // Apply them to the downloads of a manager that's already running too
+        match ipc::forward_limits(config.download_limits()) {
+            Ok(true) => println!("Updated the limits of the running downloads"),
+            Ok(false) => {}
+            Err(err) => println!("Failed to update the limits of the running downloads, error: {err}"),
+        }
+    }
+
+    let speed_limit = |limit: Option<u64>| limit.map(|limit| format!("{}/s", HumanBytes(limit * 1024))).unwrap_or_else(|| "Unlimited".to_string());
+    println!("Total speed:        {}", speed_limit(config.download_speed_limit));
+    println!("Per download speed: {}", speed_limit(config.per_download_speed_limit));
+    println!("Downloads per host: {}", config.max_downloads_per_host.map(|limit| limit.to_string()).unwrap_or_else(|| "Unlimited".to_string()));
+
+    ExitCode::SUCCESS
+}
+
+/// Get the schemes the handler commands work on, defaulting to every scheme a downloader supports
+fn handler_schemes(plugin_man: &PluginManager, schemes: Option<Vec<String>>) -> Vec<String> {
+    schemes.unwrap_or_else(|| url_handler::handler_schemes(&plugin_man.registry().downloaders().supported_protocols()))
+}
+
+fn register_handler_command(plugin_man: &PluginManager, schemes: Option<Vec<String>>, show: bool) -> ExitCode {
+    let schemes = handler_schemes(plugin_man, schemes);
+
+    if !show {
+        let result = std::env::current_exe()
+            .map_err(errors::Error::from)
+            .and_then(|executable| url_handler::register(&executable, &schemes));
+        if let Err(err) = result {
+            println!("Failed to register the URL handler, error: {err}");
+            return ExitCode::FAILURE
+        }
+        println!("Registered as the handler for {}", schemes.join(", "));
+    }
+
+    show_handlers(&schemes)
+}
+
+fn unregister_handler_command(plugin_man: &PluginManager, schemes: Option<Vec<String>>) -> ExitCode {
+    let schemes = handler_schemes(plugin_man, schemes);
+
+    match url_handler::unregister(&schemes) {
+        Ok(true) => println!("Unregistered as the handler for {}", schemes.join(", ")),
+        Ok(false) => println!("Not registered as a URL handler"),
+        Err(err) => {
+            println!("Failed to unregister the URL handler, error: {err}");
+            return ExitCode::FAILURE
+        }
+    }
+
+    show_handlers(&schemes)
+}
+
+/// Print the desktop entry handling each scheme
+fn show_handlers(schemes: &[String]) -> ExitCode {
+    for scheme in schemes {
+        match url_handler::current_handler(scheme) {
+            Ok(Some(handler)) if handler == url_handler::DESKTOP_FILE_NAME => println!("{scheme}: {handler} (this manager)"),
+            Ok(Some(handler)) => println!("{scheme}: {handler}"),
+            Ok(None) => println!("{scheme}: No handler"),
+            Err(err) => {
+                println!("Failed to find the handler for {scheme}, error: {err}");
+                return ExitCode::FAILURE
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
 }
+
+fn verify_downloads_command(config: &ManagerConfig, instance: Option<String>, files: Option<Vec<String>>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+    let downloads_path = instance.downloads_path();
+
+    let downloads: errors::Result<Vec<DownloadMetadata>> = match files {
+        Some(files) => files.iter().map(|file| download_metadata::find(&downloads_path, file)).collect(),
+        None => download_metadata::load_all(&downloads_path)
+            .map(|downloads| downloads.into_iter().filter(|download| download.status == DownloadStatus::Completed).collect())
+    };
+    let downloads = match downloads {
+        Ok(downloads) => downloads,
+        Err(err) => {
+            println!("Failed to load downloads, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let results: Vec<(DownloadMetadata, errors::Result<()>)> = downloads.into_par_iter()
+        .map(|download| {
+            let result = download.verify(&downloads_path);
+            (download, result)
+        })
+        .collect();
+
+    let mut broken = 0;
+    for (mut download, result) in results {
+        match result {
+            Ok(_) => println!("OK       {}", download.file_name),
+            Err(err) => {
+                broken += 1;
+                match err.kind() {
+                    ErrorKind::Io(io_err) if io_err.kind() == io::ErrorKind::NotFound => println!("Missing  {}", download.file_name),
+                    _ => println!("Mismatch {}: {err}", download.file_name)
+                }
+
+                // Mark it as failed so retrying the downloads downloads it again
+                download.set_state(&DownloadState::Failed(err.to_string()));
+                if let Err(err) = download.save(&downloads_path) {
+                    println!("Failed to save the metadata of {}, error: {err}", download.file_name);
+                }
+            }
+        }
+    }
+
+    if broken > 0 {
+        println!("{broken} downloads are broken, retry them with `downloads retry`");
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn conflicts_command(config: &ManagerConfig, instance: Option<String>, mod_name: Option<String>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let conflict_map = match ConflictMap::for_instance(&instance) {
+        Ok(conflict_map) => conflict_map,
+        Err(err) => {
+            println!("Failed to build conflicts, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    match mod_name {
+        None => {
+            for mod_name in conflict_map.mods() {
+                println!("{mod_name}\t{}", conflict_map.state(mod_name).unwrap().name());
+            }
+        }
+        Some(mod_name) => {
+            let Some(conflicts) = conflict_map.conflicts(&mod_name) else {
+                println!("Unknown or disabled mod: {mod_name}");
+                return ExitCode::FAILURE
+            };
+
+            println!("{mod_name} is {}", conflict_map.state(&mod_name).unwrap().name());
+            for (other, files) in &conflicts.overwrites {
+                println!("\nOverwrites {other}:");
+                files.iter().for_each(|file| println!("\t{file}"));
+            }
+            for (other, files) in &conflicts.overwritten_by {
+                println!("\nOverwritten by {other}:");
+                files.iter().for_each(|file| println!("\t{file}"));
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn hide_command(config: &ManagerConfig, instance: Option<String>, mod_name: String, patterns: Vec<String>, hide: bool) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let mod_path = instance.mod_path(&mod_name);
+    if !mod_path.is_dir() {
+        println!("Unknown mod: {mod_name}");
+        return ExitCode::FAILURE
+    }
+
+    let result = if hide {
+        hidden_files::hide_files(&mod_path, &patterns)
+    } else {
+        hidden_files::unhide_files(&mod_path, &patterns)
+    };
+
+    match result {
+        Ok(files) => {
+            files.iter().for_each(|file| println!("{}", file.display()));
+            println!("Successfully {} {} files", if hide { "hid" } else { "unhid" }, files.len());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to {} files, error: {err}", if hide { "hide" } else { "unhide" });
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn plugins_command(config: &ManagerConfig, instance: Option<String>, subcommand: Option<(&str, &ArgMatches)>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let mut load_order = match LoadOrder::load(&instance) {
+        Ok(load_order) => load_order,
+        Err(err) => {
+            println!("Failed to load the load order, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let result = match subcommand {
+        None | Some(("list", _)) => {
+            for (index, entry) in load_order.plugins().iter().enumerate() {
+                println!("{index:>4} {} {}", if entry.active() { "*" } else { " " }, entry.name());
+            }
+            return ExitCode::SUCCESS
+        }
+        Some((subcommand @ ("enable" | "disable"), matches)) => {
+            let plugins: Vec<&String> = matches.get_many::<String>("PLUGIN").unwrap().collect();
+            let snapshot = match plugins.len() {
+                1 => Ok(()),
+                _ => snapshot::automatic_snapshot(&instance, &load_order, subcommand).map(|_| ())
+            };
+
+            snapshot.and_then(|_| plugins.iter().try_for_each(|plugin| load_order.set_active(plugin, subcommand == "enable")))
+        }
+        Some(("snapshot", matches)) => return snapshot_command(&instance, &mut load_order, matches.subcommand()),
+        Some(("validate", matches)) => {
+            let issues = validation::validate(&instance, &load_order).and_then(|mut issues| {
+                if matches.get_flag("DIRTY") {
+                    issues.extend(validation::validate_dirty_plugins(&instance, &load_order)?);
+                }
+                Ok(issues)
+            });
+
+            return match issues {
+                Ok(issues) if issues.is_empty() => {
+                    println!("No load order issues found");
+                    ExitCode::SUCCESS
+                }
+                Ok(issues) => {
+                    print_validation_issues(&issues);
+                    ExitCode::FAILURE
+                }
+                Err(err) => {
+                    println!("Failed to validate load order, error: {err}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Some(("sort", matches)) => {
+            let masterlist_path = matches.get_one::<PathBuf>("MASTERLIST").cloned()
+                .unwrap_or_else(|| instance.base_path().join(MASTERLIST_FILE));
+            let userlist_path = matches.get_one::<PathBuf>("USERLIST").cloned()
+                .unwrap_or_else(|| instance.base_path().join(USERLIST_FILE));
+
+            let masterlist = Masterlist::from_path(&masterlist_path).and_then(|mut masterlist| {
+                if userlist_path.exists() {
+                    masterlist.merge(Masterlist::from_path(&userlist_path)?);
+                }
+                Ok(masterlist)
+            });
+
+            masterlist.and_then(|masterlist| sorting::sort(&instance, &mut load_order, &masterlist))
+                .map(|warnings| warnings.iter().for_each(|warning| println!("Warning: {warning}")))
+        }
+        Some(("move", matches)) => load_order.move_plugin(
+            matches.get_one::<String>("PLUGIN").unwrap(),
+            *matches.get_one::<usize>("POSITION").unwrap()
+        ),
+        Some(_) => {
+            println!("Unknown Subcommand");
+            return ExitCode::FAILURE
+        }
+    };
+
+    match result.and_then(|_| load_order.save(&instance)) {
+        Ok(_) => {
+            println!("Successfully updated load order");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to update load order, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn record_conflicts_command(config: &ManagerConfig, instance: Option<String>, plugin: Option<String>, all: bool) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let index = match LoadOrder::load(&instance).and_then(|load_order| RecordIndex::for_load_order(&instance, &load_order)) {
+        Ok(index) => index,
+        Err(err) => {
+            println!("Failed to read plugins, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    for (plugin, err) in index.unreadable() {
+        println!("Skipping {plugin}, it couldn't be read: {err}");
+    }
+
+    let records = match &plugin {
+        Some(plugin) => index.plugin_overrides(plugin),
+        None => index.overridden_records()
+    };
+
+    let mut count = 0;
+    for record in records.iter().filter(|record| all || record.is_conflict()) {
+        println!("{} {}:{:06X} overridden by {}, winner: {}",
+                 String::from_utf8_lossy(&record.kind), record.origin, record.object_id,
+                 record.overrides.join(", "), record.winner());
+        count += 1;
+    }
+    println!("Found {count} {}", if all { "overridden records" } else { "conflicting records" });
+
+    ExitCode::SUCCESS
+}
+
+fn dirty_plugins_command(config: &ManagerConfig, instance: Option<String>, plugins: Option<Vec<String>>, list_records: bool) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let result = match &plugins {
+        Some(plugins) => dirty_records::scan_plugins(&instance, &plugins.iter().map(String::as_str).collect::<Vec<&str>>()),
+        None => LoadOrder::load(&instance).and_then(|load_order| dirty_records::scan_load_order(&instance, &load_order))
+    };
+
+    let scan = match result {
+        Ok(scan) => scan,
+        Err(err) => {
+            println!("Failed to scan plugins, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    for (plugin, err) in &scan.unreadable {
+        println!("Skipping {plugin}, it couldn't be read: {err}");
+    }
+
+    let plugins = scan.plugins;
+    let dirty: Vec<&DirtyPlugin> = plugins.iter().filter(|plugin| plugin.is_dirty()).collect();
+    for plugin in &dirty {
+        println!("{plugin}: {} ITM, {} deleted references, {} deleted navmeshes",
+                 plugin.count(DirtyKind::IdenticalToMaster),
+                 plugin.count(DirtyKind::DeletedReference),
+                 plugin.count(DirtyKind::DeletedNavmesh),
+                 plugin = plugin.name);
+
+        if list_records {
+            for record in &plugin.records {
+                let dirty_kind = match record.dirty_kind {
+                    DirtyKind::IdenticalToMaster => "identical to master",
+                    DirtyKind::DeletedReference => "deleted reference",
+                    DirtyKind::DeletedNavmesh => "deleted navmesh",
+                };
+                println!("  {} {:08X} {dirty_kind}", String::from_utf8_lossy(&record.kind), record.form_id);
+            }
+        }
+    }
+    println!("Scanned {} plugins, {} are dirty", plugins.len(), dirty.len());
+
+    ExitCode::SUCCESS
+}
+
+fn esl_candidates_command(config: &ManagerConfig, instance: Option<String>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let result = get_game(instance.game()).and_then(|game| {
+        let implicit = implicit_plugins(&instance, game)?;
+        let plugins: Vec<VisiblePlugin> = visible_plugins(&instance)?.into_iter()
+            .filter(|plugin| !implicit.iter().any(|name| name.eq_ignore_ascii_case(&plugin.name)))
+            .collect();
+
+        Ok(plugins.into_par_iter()
+            .map(|plugin| {
+                let eligibility = light_eligibility(&plugin.path, game.plugin_format());
+                (plugin.name, eligibility)
+            })
+            .collect::<Vec<_>>())
+    });
+
+    match result {
+        Ok(plugins) => {
+            let mut candidates = 0;
+            for (name, eligibility) in plugins {
+                match eligibility {
+                    Ok(LightEligibility::Eligible { new_records }) => {
+                        println!("{name} ({new_records} new records)");
+                        candidates += 1;
+                    }
+                    Ok(_) => {}
+                    Err(err) => println!("Failed to check {name}, error: {err}")
+                }
+            }
+            println!("Found {candidates} plugins that can be flagged as light");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to check plugins, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn esl_flag_command(config: &ManagerConfig, instance: Option<String>, plugins: Vec<String>, mod_name: Option<String>) -> ExitCode {
+    let Some(mut instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let result = get_game(instance.game()).and_then(|game| {
+        let visible = visible_plugins(&instance)?;
+        let dest_dir = match &mod_name {
+            Some(mod_name) => instance.mod_path(mod_name),
+            None => instance.overwrite_path()
+        };
+
+        for name in &plugins {
+            let Some(plugin) = visible.iter().find(|plugin| plugin.name.eq_ignore_ascii_case(name)) else {
+                bail!(ErrorKind::UnknownPlugin(name.clone()))
+            };
+
+            let eligibility = light_eligibility(&plugin.path, game.plugin_format())?;
+            if !eligibility.is_eligible() {
+                println!("Skipping {}, it can't be flagged as light: {eligibility:?}", plugin.name);
+                continue;
+            }
+
+            let dest = dest_dir.join(&plugin.name);
+            copy_as_light(&plugin.path, &dest, game.plugin_format())?;
+            println!("Flagged {} as light in {}", plugin.name, dest.display());
+        }
+
+        if let Some(mod_name) = &mod_name {
+            instance.add_mod(mod_name, true)?;
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(_) => {
+            println!("Successfully flagged plugins");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to flag plugins, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn snapshot_command(instance: &Instance, load_order: &mut LoadOrder, subcommand: Option<(&str, &ArgMatches)>) -> ExitCode {
+    let result = match subcommand {
+        None | Some(("list", _)) => snapshot::list_snapshots(instance).map(|snapshots| {
+            for snapshot in snapshots {
+                println!("{} {}", snapshot.created().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), snapshot.name());
+            }
+        }),
+        Some(("save", matches)) => snapshot::save_snapshot(instance, load_order, matches.get_one::<String>("NAME").unwrap())
+            .map(|snapshot| println!("Saved snapshot {}", snapshot.name())),
+        Some(("diff", matches)) => {
+            let from = snapshot::load_snapshot(instance, matches.get_one::<String>("FROM").unwrap());
+            let to = match matches.get_one::<String>("TO") {
+                Some(name) => snapshot::load_snapshot(instance, name).map(|snapshot| snapshot.plugins().to_vec()),
+                None => Ok(load_order.plugins().to_vec())
+            };
+
+            from.and_then(|from| Ok((from, to?))).map(|(from, to)| {
+                let changes = snapshot::diff(from.plugins(), &to);
+                changes.iter().for_each(|change| println!("{}", change.describe()));
+                println!("Found {} differences", changes.len());
+            })
+        }
+        Some(("restore", matches)) => {
+            let name = matches.get_one::<String>("NAME").unwrap();
+            snapshot::restore_snapshot(instance, load_order, name).map(|_| println!("Restored snapshot {name}"))
+        }
+        Some(("delete", matches)) => {
+            let name = matches.get_one::<String>("NAME").unwrap();
+            snapshot::delete_snapshot(instance, name).map(|_| println!("Deleted snapshot {name}"))
+        }
+        Some(_) => {
+            println!("Unknown Subcommand");
+            return ExitCode::FAILURE
+        }
+    };
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            println!("Snapshot command failed, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_validation_issues(issues: &[ValidationIssue]) {
+    if issues.is_empty() {
+        return
+    }
+
+    println!("Found {} load order issues:", issues.len());
+    for issue in issues {
+        println!("  {issue}");
+    }
+}
+
+fn deploy_command(config: &ManagerConfig, instance: Option<String>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    match validation::validate_instance(&instance) {
+        Ok(issues) => print_validation_issues(&issues),
+        Err(err) => println!("Failed to validate load order, error: {err}"),
+    }
+
+    match deployment::deploy(&instance) {
+        Ok(manifest) => {
+            println!("Successfully deployed {} files", manifest.files().len());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to deploy, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn launch_command(config: &ManagerConfig, instance: Option<String>, program: String, args: Vec<String>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    match launch_file::check_load_order(&instance, config) {
+        Ok(issues) => {
+            print_validation_issues(&issues);
+            if launch_file::blocks_launch(config, &issues) {
+                println!("Not launching until the load order issues are fixed");
+                return ExitCode::FAILURE
+            }
+        }
+        Err(err) => println!("Failed to validate load order, error: {err}"),
+    }
+
+    match launch_file::run_exe(&instance, &program, &args) {
+        Ok(status) if status.success() => ExitCode::SUCCESS,
+        Ok(status) => {
+            println!("{program} exited with {status}");
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            println!("Failed to launch {program}, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn purge_command(config: &ManagerConfig, instance: Option<String>) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    match deployment::purge(&instance) {
+        Ok(_) => {
+            println!("Successfully purged deployment");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to purge, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn verify_deployment_command(config: &ManagerConfig, instance: Option<String>, repair: bool, no_prompt: bool) -> ExitCode {
+    let Some(instance) = get_instance(config, instance) else {
+        return ExitCode::FAILURE
+    };
+
+    let mut manifest = match DeploymentManifest::load(&instance) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            println!("Failed to load deployment manifest, error: {err}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let issues = deployment::verify(&instance, &manifest);
+    if issues.is_empty() {
+        println!("All {} deployed files are intact", manifest.files().len());
+        return ExitCode::SUCCESS
+    }
+
+    for issue in &issues {
+        let file = &manifest.files()[issue.index];
+        println!("{}\t{} (from {})", issue.kind.name(), file.target.display(), file.mod_name);
+    }
+
+    if !repair {
+        if no_prompt {
+            return ExitCode::FAILURE
+        }
+
+        println!("Redeploy the {} broken files? (y/n)", issues.len());
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .expect("Failed to get user input");
+
+        if !answer.starts_with('y') {
+            println!("Deployment not repaired");
+            return ExitCode::FAILURE
+        }
+    }
+
+    match deployment::repair(&instance, &mut manifest, &issues) {
+        Ok(_) => {
+            println!("Successfully repaired deployment");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("Failed to repair deployment, error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Load the given instance, or the default instance if no instance is given
+///
+/// Prints the reason to the user if the instance cannot be loaded
+fn get_instance(config: &ManagerConfig, name: Option<String>) -> Option<Instance> {
+    let name = match name {
+        Some(name) => name,
+        None if !config.default_instance.is_empty() => config.default_instance.clone(),
+        None => {
+            println!("No instance specified or default instance configured");
+            return None
+        }
+    };
+
+    match Instance::from_name(&name) {
+        Ok(instance) => Some(instance),
+        Err(err) => {
+            println!("Failed to get instance, error: {err}");
+            None
+        }
+    }
+}
+
+fn cmd(config: &ManagerConfig) -> Command {
+    command!()
+        .subcommand(
+            Command::new("list-instances")
+                .about("List all the instances")
+        )
+        .subcommand(
+            Command::new("set-default-instance")
+                .about("Set the default instance")
+                .long_about("Set the default instance. This is the instance that gets used when the \
+                             downloader cannot figure out which instance to download a mod to. \
+                             Pass none to be prompted everytime instead")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .allow_hyphen_values(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("create-instance")
+                .about("Create a new instance")
+                .after_help("For the paths (besides the BASE_PATH), relative paths will be relative to the BASE_PATH, absolute paths will work as expected\n\
+                Any arguments or options not provided will be prompted for")
+                .group(
+                    ArgGroup::new("Paths")
+                        .args(["BASE_PATH", "MODS_PATH", "DOWNLOADS_PATH", "OVERWRITE_PATH", "PROFILES_PATH"])
+                )
+
+                .arg(
+                    Arg::new("NAME")
+                        .allow_hyphen_values(true)
+                        .help("The name of the new profile")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("GAME")
+                        .allow_hyphen_values(true)
+                        .help("The game this profile is for")
+                        .required(true)
+                        // .value_parser() TODO: available games
+                )
+                .arg(
+                    Arg::new("BASE_PATH")
+                        .long("base-path")
+                        .short('b')
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("The base directory of the instance")
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("MODS_PATH")
+                        .long("mods-path")
+                        .short('m')
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("The directory in which mods are stored")
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("DOWNLOADS_PATH")
+                        .long("downloads-path")
+                        .short('d')
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("The directory in which mods are downloaded to")
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("OVERWRITE_PATH")
+                        .long("overwrite-path")
+                        .short('o')
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .help("The directory in which runtime changes are written to")
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("PROFILES_PATH")
+                        .long("profiles-path")
+                        .short('p')
+                        .help("The directory in which profiles information is stored")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("GAME_PATH")
+                        .long("game-path")
+                        .short('g')
+                        .help("The directory the game is installed in")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("APP_DATA_PATH")
+                        .long("app-data-path")
+                        .short('a')
+                        .help("The game's local app data directory, which the game reads plugins.txt from, or the directory containing openmw.cfg for OpenMW")
+                        .value_parser(value_parser!(std::path::PathBuf))
+                        .value_hint(DirPath)
+                )
+                .arg(
+                    Arg::new("DEFAULT")
+                        .long("default")
+                        .short('D')
+                        .help("Set the newly created profile as the default profile")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("delete-instance")
+                .about("Delete an instance, optionally removing all it's content")
+                .arg(
+                    Arg::new("NAME")
+                        .allow_hyphen_values(true)
+                        .help("The name of the profile to delete")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("CLEAR")
+                        .long("clear")
+                        .short('c')
+                        .help("Remove this instances data (mods, downloads, etc)")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("FORCE")
+                        .long("force")
+                        .short('f')
+                        .help("Skip the prompt double-checking you're sure you want to delete the profile")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("download")
+                .about("Download a mod")
+                .long_about("Download a mod using the given URL, uses the default instance if no instance is specified")
+                .arg(
+                    Arg::new("URL")
+                        .value_hint(ValueHint::Url)
+                        .help("The URL of the mod to download, or the path of an archive to import")
+                        .required(true)
+                        .value_parser(parse_source)
+                )
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("Specify an instance to download the mod to")
+                )
+                .arg(
+                    Arg::new("NO-PROMPT")
+                        .long("no-prompt")
+                        .short('n')
+                        .help("Fail instead of prompting if no instance is set")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("downloads")
+                .about("List, retry and remove the downloads of an instance")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance whose downloads to manage, uses the default instance if not specified")
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List every download with its status and where it came from, oldest first")
+                )
+                .subcommand(
+                    Command::new("retry")
+                        .about("Download unfinished downloads again, resuming them where possible")
+                        .arg(
+                            Arg::new("FILE")
+                                .help("The downloads to retry, defaults to every unfinished download")
+                                .num_args(1..)
+                        )
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove downloads and their metadata")
+                        .arg(
+                            Arg::new("FILE")
+                                .help("The downloads to remove")
+                                .required(true)
+                                .num_args(1..)
+                        )
+                        .arg(
+                            Arg::new("KEEP_FILE")
+                                .long("keep-file")
+                                .short('k')
+                                .help("Keep the downloaded file, only removing its metadata")
+                                .action(ArgAction::SetTrue)
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("download-limits")
+                .about("Show or change the download speed and per host limits")
+                .long_about("Show the download speed and per host limits, or change them if any are given. \
+                             Changes also apply to the downloads of a manager that's already running")
+                .arg(
+                    Arg::new("SPEED")
+                        .long("speed")
+                        .short('s')
+                        .help("The most KiB per second every download together can download, 0 for unlimited")
+                        .value_parser(value_parser!(u64))
+                )
+                .arg(
+                    Arg::new("PER_DOWNLOAD")
+                        .long("per-download")
+                        .short('d')
+                        .help("The most KiB per second each download can download, 0 for unlimited")
+                        .value_parser(value_parser!(u64))
+                )
+                .arg(
+                    Arg::new("PER_HOST")
+                        .long("per-host")
+                        .short('p')
+                        .help("The most downloads that can run against the same host at once, 0 for unlimited")
+                        .value_parser(value_parser!(usize))
+                )
+        )
+        .subcommand(
+            Command::new("register-handler")
+                .about("Register as the desktop's handler for mod download links")
+                .long_about("Install a desktop entry under $XDG_DATA_HOME that makes the manager the default handler \
+                             for the URL schemes its downloaders support, such as nxm links from the Nexus Mods \
+                             website, then show the handler of each scheme")
+                .arg(
+                    Arg::new("SCHEME")
+                        .help("The URL schemes to handle, defaults to every scheme the downloaders support except http, https and file")
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("SHOW")
+                        .long("show")
+                        .short('s')
+                        .help("Only show the current handler of each scheme")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("unregister-handler")
+                .about("Remove the desktop entry and links registered by register-handler")
+                .arg(
+                    Arg::new("SCHEME")
+                        .help("The URL schemes to stop handling, defaults to every scheme the downloaders support except http, https and file")
+                        .num_args(1..)
+                )
+        )
+        .subcommand(
+            Command::new("verify-downloads")
+                .about("Check downloaded files against their stored sizes and hashes")
+                .long_about("Check completed downloads still match the size and hashes they were downloaded with, \
+                             marking broken ones as failed so `downloads retry` downloads them again")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance whose downloads to verify, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .help("The downloads to verify, defaults to every completed download")
+                        .num_args(1..)
+                )
+        )
+        .subcommand(
+            Command::new("conflicts")
+                .about("Show the files mods overwrite in each other")
+                .long_about("Show whether each enabled mod in the active profile wins or loses its file conflicts, \
+                             or list the files a single mod overwrites and is overwritten by")
+                .arg(
+                    Arg::new("MOD")
+                        .allow_hyphen_values(true)
+                        .help("The mod to list the conflicting files of")
+                )
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to check, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("deploy")
+                .about("Link the enabled mods into the game directory")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to deploy, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("launch")
+                .about("Check the load order and run a program from the game directory")
+                .long_about("Check the load order of the active profile and run a program, such as the game or its \
+                             script extender, from the game directory. The active plugins are scanned for dirty \
+                             records first if scan_dirty_plugins_before_launch is set in the config. If \
+                             block_launch_on_invalid_load_order is set, the program isn't run while the load order \
+                             has fatal issues")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to launch, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("PROGRAM")
+                        .help("The program to run")
+                        .required(true)
+                        .value_hint(ValueHint::CommandName)
+                )
+                .arg(
+                    Arg::new("ARGS")
+                        .help("The arguments to pass to the program")
+                        .num_args(1..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true)
+                )
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Remove the deployed mods from the game directory")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to purge, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("verify-deployment")
+                .about("Check the deployed files are still in place")
+                .long_about("Check every deployed file is still in place, reporting files that are missing or that \
+                             have been replaced, such as by a game update, and offer to redeploy them")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to verify, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("REPAIR")
+                        .long("repair")
+                        .short('r')
+                        .help("Redeploy the broken files without prompting")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("NO-PROMPT")
+                        .long("no-prompt")
+                        .short('n')
+                        .help("Only report the broken files instead of prompting to redeploy them")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("hide")
+                .about("Hide files in a mod so they aren't deployed")
+                .long_about("Hide the files in a mod that match any of the given glob patterns so they are ignored \
+                             by conflicts and deployment. Hidden files are renamed with a .mohidden extension")
+                .arg(
+                    Arg::new("MOD")
+                        .allow_hyphen_values(true)
+                        .help("The mod to hide files in")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("PATTERN")
+                        .help("Glob patterns of the files to hide, relative to the mod directory")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance the mod is in, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("unhide")
+                .about("Unhide hidden files in a mod")
+                .arg(
+                    Arg::new("MOD")
+                        .allow_hyphen_values(true)
+                        .help("The mod to unhide files in")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("PATTERN")
+                        .help("Glob patterns of the files to unhide, relative to the mod directory")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance the mod is in, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("plugins")
+                .about("Manage the load order of the active profile")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .global(true)
+                        .help("The instance to manage, uses the default instance if not specified")
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the plugins in load order, active plugins are marked with *")
+                )
+                .subcommand(
+                    Command::new("enable")
+                        .about("Activate plugins")
+                        .arg(
+                            Arg::new("PLUGIN")
+                                .help("The plugins to activate")
+                                .required(true)
+                                .num_args(1..)
+                        )
+                )
+                .subcommand(
+                    Command::new("disable")
+                        .about("Deactivate plugins")
+                        .arg(
+                            Arg::new("PLUGIN")
+                                .help("The plugins to deactivate")
+                                .required(true)
+                                .num_args(1..)
+                        )
+                )
+                .subcommand(
+                    Command::new("snapshot")
+                        .about("Save, compare and restore snapshots of the plugin order and active plugins")
+                        .subcommand(
+                            Command::new("list")
+                                .about("List the snapshots of the active profile, oldest first")
+                        )
+                        .subcommand(
+                            Command::new("save")
+                                .about("Save a snapshot of the current load order")
+                                .arg(
+                                    Arg::new("NAME")
+                                        .help("The name of the snapshot, an existing snapshot with the name is replaced")
+                                        .required(true)
+                                )
+                        )
+                        .subcommand(
+                            Command::new("diff")
+                                .about("Show the differences between two snapshots")
+                                .arg(
+                                    Arg::new("FROM")
+                                        .help("The snapshot to compare from")
+                                        .required(true)
+                                )
+                                .arg(
+                                    Arg::new("TO")
+                                        .help("The snapshot to compare to, the current load order if not specified")
+                                )
+                        )
+                        .subcommand(
+                            Command::new("restore")
+                                .about("Restore a snapshot, the current load order is snapshotted first")
+                                .arg(
+                                    Arg::new("NAME")
+                                        .help("The snapshot to restore")
+                                        .required(true)
+                                )
+                        )
+                        .subcommand(
+                            Command::new("delete")
+                                .about("Delete a snapshot")
+                                .arg(
+                                    Arg::new("NAME")
+                                        .help("The snapshot to delete")
+                                        .required(true)
+                                )
+                        )
+                )
+                .subcommand(
+                    Command::new("sort")
+                        .about("Sort the load order using a LOOT masterlist and userlist")
+                        .arg(
+                            Arg::new("MASTERLIST")
+                                .long("masterlist")
+                                .short('m')
+                                .help("The masterlist to sort with, defaults to masterlist.yaml in the instance directory")
+                                .value_parser(value_parser!(PathBuf))
+                                .value_hint(ValueHint::FilePath)
+                        )
+                        .arg(
+                            Arg::new("USERLIST")
+                                .long("userlist")
+                                .short('u')
+                                .help("The userlist to add to the masterlist, defaults to userlist.yaml in the instance directory if it exists")
+                                .value_parser(value_parser!(PathBuf))
+                                .value_hint(ValueHint::FilePath)
+                        )
+                )
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the active plugins for missing, inactive and misordered masters")
+                        .arg(
+                            Arg::new("DIRTY")
+                                .long("dirty")
+                                .short('d')
+                                .help("Also scan the active plugins for identical to master records and deleted references, which reads every record")
+                                .action(ArgAction::SetTrue)
+                        )
+                )
+                .subcommand(
+                    Command::new("move")
+                        .about("Move a plugin to a new position in the load order")
+                        .arg(
+                            Arg::new("PLUGIN")
+                                .help("The plugin to move")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::new("POSITION")
+                                .help("The position to move the plugin to, starting from 0")
+                                .required(true)
+                                .value_parser(value_parser!(usize))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("esl-candidates")
+                .about("List the plugins that can be flagged as light without changing their FormIDs")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to check, uses the default instance if not specified")
+                )
+        )
+        .subcommand(
+            Command::new("esl-flag")
+                .about("Flag copies of plugins as light, storing them in the overwrite directory or a new mod")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance the plugins are in, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("PLUGIN")
+                        .help("The plugins to flag")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("MOD")
+                        .long("mod")
+                        .short('m')
+                        .help("Store the copies in this mod, created and enabled if it doesn't exist, instead of the overwrite directory")
+                )
+        )
+        .subcommand(
+            Command::new("dirty-plugins")
+                .about("Find identical to master records and deleted references and navmeshes in plugins")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to check, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("PLUGIN")
+                        .help("The plugins to scan, defaults to the active plugins the game doesn't load implicitly")
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("RECORDS")
+                        .long("records")
+                        .short('r')
+                        .help("List each dirty record")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("record-conflicts")
+                .about("List records that more than one active plugin overrides, and the plugin that wins each")
+                .arg(
+                    Arg::new("INSTANCE")
+                        .long("instance")
+                        .short('i')
+                        .help("The instance to check, uses the default instance if not specified")
+                )
+                .arg(
+                    Arg::new("PLUGIN")
+                        .help("Only list records this plugin defines or overrides")
+                )
+                .arg(
+                    Arg::new("ALL")
+                        .long("all")
+                        .short('a')
+                        .help("List every overridden record, including records only one plugin overrides")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+}
\ No newline at end of file
@@ -1,19 +1,73 @@
 use std::{fs, io};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use crate::errors::{ErrorKind, Result};
 use crate::constants;
+use crate::download_manager::DEFAULT_CONCURRENT_DOWNLOADS;
+use crate::download_manager::limits::DownloadLimits;
+use crate::download_manager::retry::{DEFAULT_RETRIES, DEFAULT_RETRY_DELAY, RetryPolicy};
 
+/// The Nexus Mods API used when the config doesn't set one
+pub const DEFAULT_NEXUS_API_URL: &str = "https://api.nexusmods.com/v1/";
 
 #[derive(Serialize, Deserialize)]
 pub struct ManagerConfig {
     pub default_instance: String,
+    /// Refuse to launch the game while the load order has missing or misordered masters
+    #[serde(default)]
+    pub block_launch_on_invalid_load_order: bool,
+    /// Scan the active plugins for identical to master records and deleted references before launching the game
+    #[serde(default)]
+    pub scan_dirty_plugins_before_launch: bool,
+    /// The maximum number of downloads to run at once
+    #[serde(default = "default_concurrent_downloads")]
+    pub max_concurrent_downloads: usize,
+    /// The most KiB per second every download together can download, unlimited if not set
+    pub download_speed_limit: Option<u64>,
+    /// The most KiB per second each download can download, unlimited if not set
+    pub per_download_speed_limit: Option<u64>,
+    /// The most downloads that can run against the same host at once, unlimited if not set
+    pub max_downloads_per_host: Option<usize>,
+    /// The most times a download is retried with each downloader when it fails for a reason that may pass
+    #[serde(default = "default_download_retries")]
+    pub download_retries: u32,
+    /// The seconds to wait before retrying a download, doubling with each retry
+    #[serde(default = "default_download_retry_delay")]
+    pub download_retry_delay: u64,
+    /// The names of the downloaders to try first when several support a URL, most preferred first. The rest are
+    /// tried after them in order of name
+    #[serde(default)]
+    pub downloader_preference: Vec<String>,
+    /// The base URL of the Nexus Mods API, nxm links are resolved through it
+    #[serde(default = "default_nexus_api_url")]
+    pub nexus_api_url: String,
+    /// The user's personal Nexus Mods API key, from their account settings
+    pub nexus_api_key: Option<String>,
+}
+
+fn default_concurrent_downloads() -> usize {
+    DEFAULT_CONCURRENT_DOWNLOADS
+}
+
+fn default_download_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+fn default_download_retry_delay() -> u64 {
+    DEFAULT_RETRY_DELAY.as_secs()
+}
+
+fn default_nexus_api_url() -> String {
+    DEFAULT_NEXUS_API_URL.to_string()
 }
 
 impl Default for ManagerConfig {
     fn default() -> Self {
-        Self{default_instance: "".to_string()}
+        Self{default_instance: "".to_string(), block_launch_on_invalid_load_order: false, scan_dirty_plugins_before_launch: false, max_concurrent_downloads: DEFAULT_CONCURRENT_DOWNLOADS,
+             download_speed_limit: None, per_download_speed_limit: None, max_downloads_per_host: None, download_retries: DEFAULT_RETRIES,
+             download_retry_delay: default_download_retry_delay(), downloader_preference: Vec::new(), nexus_api_url: default_nexus_api_url(), nexus_api_key: None}
     }
 }
 
@@ -66,6 +120,20 @@ impl ManagerConfig {
         }
     }
 
+    /// Get the download limits the download manager should enforce
+    pub fn download_limits(&self) -> DownloadLimits {
+        DownloadLimits {
+            speed: self.download_speed_limit.map(|limit| limit * 1024),
+            per_download_speed: self.per_download_speed_limit.map(|limit| limit * 1024),
+            per_host: self.max_downloads_per_host,
+        }
+    }
+
+    /// Get how the download manager should retry failed downloads
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy { retries: self.download_retries, initial_delay: Duration::from_secs(self.download_retry_delay) }
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = constants::config_file_path();
 
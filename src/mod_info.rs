@@ -2,6 +2,7 @@ pub mod game;
 pub mod game_mod;
 pub mod mod_list;
 pub mod instance;
+pub mod hidden_files;
 
 #[cfg(test)]
 mod tests {
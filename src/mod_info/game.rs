@@ -1,12 +1,101 @@
 use std::collections::HashMap;
+use error_chain::bail;
+use lazy_static::lazy_static;
+
+use crate::deployment::DeployMethod;
+use crate::errors;
+use crate::errors::ErrorKind;
+use crate::game_plugin::PluginFormat;
+use crate::load_order::LoadOrderMethod;
+
+lazy_static! {
+    static ref GAMES: HashMap<&'static str, Game> = HashMap::from([
+        ("morrowind", Game::new(
+            "Morrowind", "The Elder Scrolls III: Morrowind", "Data Files",
+            PluginFormat::Morrowind, LoadOrderMethod::MorrowindIni, DeployMethod::Link,
+            &["Morrowind.esm"], None
+        )),
+        ("openmw", Game::new(
+            "OpenMW", "OpenMW", "Data Files",
+            PluginFormat::Morrowind, LoadOrderMethod::OpenMw, DeployMethod::DataPaths,
+            &["Morrowind.esm"], None
+        )),
+        ("oblivion", Game::new(
+            "Oblivion", "The Elder Scrolls IV: Oblivion", "Data",
+            PluginFormat::Oblivion, LoadOrderMethod::Timestamp, DeployMethod::Link,
+            &["Oblivion.esm"], None
+        )),
+        ("skyrim", Game::new(
+            "Skyrim", "The Elder Scrolls V: Skyrim", "Data",
+            PluginFormat::Skyrim, LoadOrderMethod::Textfile, DeployMethod::Link,
+            &["Skyrim.esm", "Update.esm"], None
+        )),
+        ("skyrimse", Game::new(
+            "Skyrim Special Edition", "The Elder Scrolls V: Skyrim Special Edition", "Data",
+            PluginFormat::SkyrimSE, LoadOrderMethod::Asterisk, DeployMethod::Link,
+            &["Skyrim.esm", "Update.esm", "Dawnguard.esm", "HearthFires.esm", "Dragonborn.esm"], Some("Skyrim.ccc")
+        )),
+        ("fallout3", Game::new(
+            "Fallout 3", "Fallout 3", "Data",
+            PluginFormat::Fallout3, LoadOrderMethod::Timestamp, DeployMethod::Link,
+            &["Fallout3.esm"], None
+        )),
+        ("falloutnv", Game::new(
+            "Fallout New Vegas", "Fallout: New Vegas", "Data",
+            PluginFormat::Fallout3, LoadOrderMethod::Timestamp, DeployMethod::Link,
+            &["FalloutNV.esm"], None
+        )),
+        ("fallout4", Game::new(
+            "Fallout 4", "Fallout 4", "Data",
+            PluginFormat::Fallout4, LoadOrderMethod::Asterisk, DeployMethod::Link,
+            &["Fallout4.esm", "DLCRobot.esm", "DLCworkshop01.esm", "DLCCoast.esm", "DLCworkshop02.esm",
+                "DLCworkshop03.esm", "DLCNukaWorld.esm", "DLCUltraHighResolution.esm"], Some("Fallout4.ccc")
+        )),
+        ("starfield", Game::new(
+            "Starfield", "Starfield", "Data",
+            PluginFormat::Starfield, LoadOrderMethod::Asterisk, DeployMethod::Link,
+            &["Starfield.esm", "Constellation.esm", "OldMars.esm", "BlueprintShips-Starfield.esm"], Some("Starfield.ccc")
+        )),
+    ]);
+}
 
 pub struct Game {
     name: String,
     description: String,
-    categories: HashMap<u32, String>
+    categories: HashMap<u32, String>,
+    data_dir: String,
+    plugin_format: PluginFormat,
+    load_order_method: LoadOrderMethod,
+    deploy_method: DeployMethod,
+    official_masters: Vec<String>,
+    ccc_file: Option<String>,
 }
 
 impl Game {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        name: &str,
+        description: &str,
+        data_dir: &str,
+        plugin_format: PluginFormat,
+        load_order_method: LoadOrderMethod,
+        deploy_method: DeployMethod,
+        official_masters: &[&str],
+        ccc_file: Option<&str>
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            categories: HashMap::new(),
+            data_dir: data_dir.to_string(),
+            plugin_format,
+            load_order_method,
+            deploy_method,
+            official_masters: official_masters.iter().map(|master| master.to_string()).collect(),
+            ccc_file: ccc_file.map(|file| file.to_string()),
+        }
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -16,4 +105,49 @@ impl Game {
     pub fn categories(&self) -> &HashMap<u32, String> {
         &self.categories
     }
-}
\ No newline at end of file
+    /// The directory mods are deployed to, relative to the game's install directory
+    pub fn data_dir(&self) -> &str {
+        &self.data_dir
+    }
+    /// The variant of the plugin format the game's plugins use
+    pub fn plugin_format(&self) -> PluginFormat {
+        self.plugin_format
+    }
+    /// How the game stores its load order
+    pub fn load_order_method(&self) -> LoadOrderMethod {
+        self.load_order_method
+    }
+    /// How mods are made visible to the game
+    pub fn deploy_method(&self) -> DeployMethod {
+        self.deploy_method
+    }
+    /// The plugins the game always loads first, in the order it loads them
+    pub fn official_masters(&self) -> &[String] {
+        &self.official_masters
+    }
+    /// The file in the game directory listing the Creation Club plugins the game always loads
+    pub fn ccc_file(&self) -> Option<&str> {
+        self.ccc_file.as_deref()
+    }
+}
+
+/// Get the definition of a supported game
+///
+/// # Arguments
+///
+/// * `id`: The id of the game, as stored in an instance
+///
+/// returns: Result<&Game, Error> The game definition, or a NoMatchingGame error if the game isn't supported
+pub fn get_game(id: &str) -> errors::Result<&'static Game> {
+    match GAMES.get(id.to_lowercase().as_str()) {
+        Some(game) => Ok(game),
+        None => bail!(ErrorKind::NoMatchingGame)
+    }
+}
+
+/// Get the ids of every supported game
+pub fn game_ids() -> Vec<&'static str> {
+    let mut ids: Vec<&'static str> = GAMES.keys().copied().collect();
+    ids.sort();
+    ids
+}
@@ -0,0 +1,175 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use walkdir::WalkDir;
+
+use crate::errors;
+
+/// The extension appended to hidden files, matching the scheme used by Mod Organizer 2
+pub const HIDDEN_EXTENSION: &str = "mohidden";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Check if a file in a mod is hidden
+pub fn is_hidden(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case(HIDDEN_EXTENSION))
+}
+
+/// Get the files in a mod that are hidden
+///
+/// returns: Result<Vec<(PathBuf, PathBuf)>, Error> The paths of the hidden files relative to the mod directory,
+/// without the hidden extension, along with their paths on disk
+pub fn hidden_files(mod_path: &Path) -> errors::Result<Vec<(PathBuf, PathBuf)>> {
+    Ok(mod_files(mod_path)?.into_iter()
+        .filter(|file| is_hidden(file))
+        .map(|file| (file.with_extension(""), file))
+        .collect())
+}
+
+/// Hide the files in a mod that match any of the given glob patterns
+///
+/// Patterns are matched case-insensitively against the path of each file relative to the mod directory,
+/// and `*` matches across directories, so `*.dds` hides every texture in the mod.
+/// Files that would replace a hidden file of the same name are left visible.
+///
+/// # Arguments
+///
+/// * `mod_path`: The directory of the mod
+/// * `patterns`: The glob patterns of the files to hide
+///
+/// returns: Result<Vec<PathBuf>, Error> The paths of the newly hidden files relative to the mod directory
+pub fn hide_files(mod_path: &Path, patterns: &[String]) -> errors::Result<Vec<PathBuf>> {
+    let patterns = compile_patterns(patterns)?;
+    let mut hidden = Vec::new();
+
+    for file in mod_files(mod_path)? {
+        if is_hidden(&file) || !matches_any(&patterns, &file) || mod_path.join(hidden_path(&file)).exists() {
+            continue;
+        }
+
+        fs::rename(mod_path.join(&file), mod_path.join(hidden_path(&file)))?;
+        hidden.push(file);
+    }
+
+    Ok(hidden)
+}
+
+/// Unhide the hidden files in a mod that match any of the given glob patterns
+///
+/// Patterns are matched the same way as [`hide_files`], against the path without the hidden extension.
+/// Hidden files that would replace a visible file of the same name are left hidden.
+///
+/// returns: Result<Vec<PathBuf>, Error> The paths of the unhidden files relative to the mod directory
+pub fn unhide_files(mod_path: &Path, patterns: &[String]) -> errors::Result<Vec<PathBuf>> {
+    let patterns = compile_patterns(patterns)?;
+    let mut unhidden = Vec::new();
+
+    for (file, hidden_file) in hidden_files(mod_path)? {
+        if !matches_any(&patterns, &file) || mod_path.join(&file).exists() {
+            continue;
+        }
+
+        fs::rename(mod_path.join(hidden_file), mod_path.join(&file))?;
+        unhidden.push(file);
+    }
+
+    Ok(unhidden)
+}
+
+/// Get the path a file is moved to when it is hidden
+fn hidden_path(file: &Path) -> PathBuf {
+    let mut hidden_name = file.as_os_str().to_os_string();
+    hidden_name.push(".");
+    hidden_name.push(HIDDEN_EXTENSION);
+    PathBuf::from(hidden_name)
+}
+
+fn compile_patterns(patterns: &[String]) -> errors::Result<Vec<Pattern>> {
+    Ok(patterns.iter()
+        .map(|pattern| Pattern::new(pattern))
+        .collect::<Result<Vec<Pattern>, glob::PatternError>>()?)
+}
+
+fn matches_any(patterns: &[Pattern], file: &Path) -> bool {
+    patterns.iter().any(|pattern| pattern.matches_path_with(file, MATCH_OPTIONS))
+}
+
+fn mod_files(mod_path: &Path) -> errors::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(mod_path).min_depth(1) {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_dir() {
+            files.push(entry.path().strip_prefix(mod_path).unwrap().to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::conflicts::{ConflictMap, Namespace};
+    use crate::deployment::deploy;
+    use crate::mod_info::hidden_files::{hidden_files, hide_files, unhide_files};
+    use crate::mod_info::instance::Instance;
+
+    #[test]
+    fn hide_and_unhide() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("instance");
+        let game = dir.path().join("game");
+        let mut instance = Instance::new(&base, &base.join("mods"), &base.join("downloads"), &base.join("overwrite"),
+                                         &base.join("profiles"), &game, None, "skyrimse");
+        fs::create_dir_all(instance.profile_path()).unwrap();
+        fs::create_dir_all(game.join("Data")).unwrap();
+        instance.add_mod("Mod", true).unwrap();
+
+        let mod_path = instance.mod_path("Mod");
+        fs::create_dir_all(mod_path.join("Textures")).unwrap();
+        fs::write(mod_path.join("Textures/a.DDS"), "a").unwrap();
+        fs::write(mod_path.join("b.esp"), "b").unwrap();
+
+        assert_eq!(hide_files(&mod_path, &["textures/*.dds".to_string()]).unwrap(), vec![PathBuf::from("Textures/a.DDS")]);
+        assert!(mod_path.join("Textures/a.DDS.mohidden").exists());
+        assert_eq!(hidden_files(&mod_path).unwrap(), vec![(PathBuf::from("Textures/a.DDS"), PathBuf::from("Textures/a.DDS.mohidden"))]);
+
+        let mut map = ConflictMap::new();
+        map.add_mod("Mod", &mod_path).unwrap();
+        assert_eq!(map.winner(Namespace::Data, Path::new("Textures/a.DDS")), None);
+        assert_eq!(map.winner(Namespace::Data, Path::new("b.esp")), Some("Mod"));
+
+        let manifest = deploy(&instance).unwrap();
+        let deployed: Vec<&Path> = manifest.files().iter().map(|file| file.target.as_path()).collect();
+        assert_eq!(deployed, vec![Path::new("Data/b.esp")]);
+
+        assert_eq!(unhide_files(&mod_path, &["*".to_string()]).unwrap(), vec![PathBuf::from("Textures/a.DDS")]);
+        assert_eq!(fs::read_to_string(mod_path.join("Textures/a.DDS")).unwrap(), "a");
+        assert!(hidden_files(&mod_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hidden_name_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mod_path = dir.path();
+        fs::write(mod_path.join("a.esp"), "visible").unwrap();
+        fs::write(mod_path.join("a.esp.mohidden"), "hidden").unwrap();
+        fs::write(mod_path.join("b.esp.MOHIDDEN"), "b").unwrap();
+
+        assert!(hide_files(mod_path, &["a.esp".to_string()]).unwrap().is_empty());
+        assert_eq!(fs::read_to_string(mod_path.join("a.esp")).unwrap(), "visible");
+        assert_eq!(fs::read_to_string(mod_path.join("a.esp.mohidden")).unwrap(), "hidden");
+
+        assert_eq!(unhide_files(mod_path, &["b.esp".to_string()]).unwrap(), vec![PathBuf::from("b.esp")]);
+        assert_eq!(fs::read_to_string(mod_path.join("b.esp")).unwrap(), "b");
+        assert!(!mod_path.join("b.esp.MOHIDDEN").exists());
+    }
+}
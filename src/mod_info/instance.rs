@@ -7,6 +7,7 @@ use error_chain::bail;
 use crate::errors;
 use crate::constants::instance_dir;
 use crate::errors::ErrorKind;
+use crate::mod_info::game::get_game;
 use crate::mod_info::mod_list::ModList;
 
 /* ========================================= */
@@ -20,17 +21,25 @@ pub struct Instance {
     downloads_path: PathBuf,
     overwrite_path: PathBuf,
     profiles_path: PathBuf,
+    #[serde(default)]
+    game_path: PathBuf,
+    #[serde(default)]
+    app_data_path: Option<PathBuf>,
 
     game: String,
 
+    #[serde(default = "default_profile")]
+    profile: String,
+
     #[serde(skip)]
     #[serde(default = "ModList::new")]
     mod_list: ModList,
 }
 
 impl Instance {
-    pub fn new(base_path: &Path, mods_path: &Path, downloads_path: &Path, overwrite_path: &Path, profile_path: &Path, game: &str) -> Self {
-        Self { base_path: base_path.to_path_buf(), mods_path: mods_path.to_path_buf(), downloads_path: downloads_path.to_path_buf(), overwrite_path: overwrite_path.to_path_buf(), profiles_path: profile_path.to_path_buf(), game: game.to_string(), mod_list: ModList::new() }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(base_path: &Path, mods_path: &Path, downloads_path: &Path, overwrite_path: &Path, profile_path: &Path, game_path: &Path, app_data_path: Option<&Path>, game: &str) -> Self {
+        Self { base_path: base_path.to_path_buf(), mods_path: mods_path.to_path_buf(), downloads_path: downloads_path.to_path_buf(), overwrite_path: overwrite_path.to_path_buf(), profiles_path: profile_path.to_path_buf(), game_path: game_path.to_path_buf(), app_data_path: app_data_path.map(|path| path.to_path_buf()), game: game.to_string(), profile: default_profile(), mod_list: ModList::new() }
     }
 
     pub fn from_name(profile_name: &str) -> errors::Result<Instance> {
@@ -42,7 +51,8 @@ impl Instance {
     fn from_path(path: &Path) -> errors::Result<Instance> {
         let toml_content = fs::read_to_string(path)?;
 
-        let instance = toml::from_str(&toml_content)?;
+        let mut instance: Instance = toml::from_str(&toml_content)?;
+        instance.reload_mod_list()?;
 
         Ok(instance)
     }
@@ -54,7 +64,10 @@ impl Instance {
             downloads_path: PathBuf::from("downloads"),
             overwrite_path: PathBuf::from("overwrite"),
             profiles_path: PathBuf::from("profiles"),
+            game_path: PathBuf::new(),
+            app_data_path: None,
             game: game.to_string(),
+            profile: default_profile(),
             mod_list: ModList::new()
         }
     }
@@ -95,9 +108,60 @@ impl Instance {
         }
     }
 
+    /// The directory the game is installed in
+    pub fn game_path(&self) -> &Path {
+        self.game_path.as_path()
+    }
+
+    /// The game's local app data directory, which the game reads its load order from
+    pub fn app_data_path(&self) -> Option<&Path> {
+        self.app_data_path.as_deref()
+    }
+
+    /// The directory mods are deployed to
+    pub fn data_path(&self) -> errors::Result<PathBuf> {
+        if self.game_path.as_os_str().is_empty() {bail!(ErrorKind::NoGamePath)}
+
+        Ok(self.game_path.join(get_game(&self.game)?.data_dir()))
+    }
+
     pub fn game(&self) -> &str {
         &self.game
     }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// The directory containing the active profile's files
+    pub fn profile_path(&self) -> PathBuf {
+        self.profiles_path().join(&self.profile)
+    }
+
+    /// The directory a mod is installed to
+    pub fn mod_path(&self, mod_name: &str) -> PathBuf {
+        self.mods_path().join(mod_name)
+    }
+
+    pub fn mod_list(&self) -> &ModList {
+        &self.mod_list
+    }
+
+    /// Add a mod to the active profile's mod list with the highest priority and save the mod list
+    pub fn add_mod(&mut self, mod_name: &str, enabled: bool) -> errors::Result<()> {
+        self.mod_list.add(mod_name, enabled);
+        self.mod_list.save(&self.profile_path().join("modlist.txt"))
+    }
+
+    /// Reload the mod list from the active profile
+    pub fn reload_mod_list(&mut self) -> errors::Result<()> {
+        self.mod_list = ModList::load(&self.profile_path().join("modlist.txt"))?;
+        Ok(())
+    }
+}
+
+fn default_profile() -> String {
+    "Default".to_string()
 }
 
 /* ========================================= */
@@ -162,10 +226,12 @@ pub fn list_instances() -> Vec<String> {
     profiles
 }
 
-pub fn create_instance(name: &str, game: &str, base_path: &Path, mods_path: &Path, downloads_path: &Path, overwrite_path: &Path, profile_path: &Path) -> errors::Result<Instance> {
+#[allow(clippy::too_many_arguments)]
+pub fn create_instance(name: &str, game: &str, base_path: &Path, mods_path: &Path, downloads_path: &Path, overwrite_path: &Path, profile_path: &Path, game_path: &Path, app_data_path: Option<&Path>) -> errors::Result<Instance> {
     if get_instances().contains_key(name) {bail!(ErrorKind::InstanceExists)}
+    get_game(game)?;
 
-    let instance = Instance::new(base_path, mods_path, downloads_path, overwrite_path, profile_path, game);
+    let instance = Instance::new(base_path, mods_path, downloads_path, overwrite_path, profile_path, game_path, app_data_path, game);
 
     if !instance.base_path().exists() {
         fs::create_dir_all(instance.base_path())?
@@ -187,6 +253,10 @@ pub fn create_instance(name: &str, game: &str, base_path: &Path, mods_path: &Pat
         fs::create_dir_all(instance.profiles_path())?
     }
 
+    if !instance.profile_path().exists() {
+        fs::create_dir_all(instance.profile_path())?
+    }
+
     fs::write(instance_dir().join(name.to_string() + ".toml"), toml::to_string(&instance)?)?;
 
     Ok(instance)
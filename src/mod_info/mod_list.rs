@@ -1,13 +1,99 @@
-use super::game_mod::ModTrait;
+use std::fs;
+use std::io;
+use std::path::Path;
 
-type ModReference = Box<dyn ModTrait>;
+use crate::errors;
 
+/// A mod in a profile's mod list
+pub struct ModListEntry {
+    name: String,
+    enabled: bool,
+}
+
+impl ModListEntry {
+    pub fn new(name: &str, enabled: bool) -> Self {
+        Self { name: name.to_string(), enabled }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// The ordered list of mods in a profile
+///
+/// Mods are stored lowest priority first, so a mod later in the list overwrites the files of mods
+/// earlier in the list.
 pub struct ModList {
-    mods: Vec<ModReference>
+    mods: Vec<ModListEntry>
 }
 
 impl ModList {
     pub fn new() -> Self {
         Self { mods: vec![] }
     }
-}
\ No newline at end of file
+
+    /// Load a mod list from a `modlist.txt` file
+    ///
+    /// Each line is a mod name prefixed with `+` if the mod is enabled or `-` if it is disabled,
+    /// lines starting with `#` are ignored. A missing file is treated as an empty mod list.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the mod list file
+    ///
+    /// returns: Result<ModList, Error> The loaded mod list
+    pub fn load(path: &Path) -> errors::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err.into())
+        };
+
+        let mods = content.lines()
+            .map(|line| line.trim())
+            .filter_map(|line| {
+                if let Some(name) = line.strip_prefix('+') {
+                    Some(ModListEntry::new(name, true))
+                } else {
+                    line.strip_prefix('-').map(|name| ModListEntry::new(name, false))
+                }
+            })
+            .collect();
+
+        Ok(Self { mods })
+    }
+
+    pub fn save(&self, path: &Path) -> errors::Result<()> {
+        let content: String = self.mods.iter()
+            .map(|entry| format!("{}{}\n", if entry.enabled { '+' } else { '-' }, entry.name))
+            .collect();
+
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn mods(&self) -> &[ModListEntry] {
+        &self.mods
+    }
+
+    /// Get the enabled mods, lowest priority first
+    pub fn enabled_mods(&self) -> impl Iterator<Item = &ModListEntry> {
+        self.mods.iter().filter(|entry| entry.enabled)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModListEntry> {
+        self.mods.iter().find(|entry| entry.name == name)
+    }
+
+    /// Add a mod with a higher priority than every other mod, does nothing if the mod is already in the list
+    pub fn add(&mut self, name: &str, enabled: bool) {
+        if self.get(name).is_none() {
+            self.mods.push(ModListEntry::new(name, enabled));
+        }
+    }
+}
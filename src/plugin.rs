@@ -30,4 +30,45 @@ macro_rules! declare_plugin {
             Box::into_raw(boxed)
         }
     };
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::thread::JoinHandle;
+
+    use url::Url;
+
+    /// Serve one canned response to each connection on a local port
+    ///
+    /// returns: (Url, JoinHandle<Vec<String>>) The server's base URL, and a thread returning every request's line
+    /// and headers in lowercase once all the responses are served
+    pub fn serve(responses: Vec<String>) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let handle = thread::spawn(move || {
+            responses.into_iter().map(|response| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while reader.read_line(&mut request).unwrap() > 2 && !request.ends_with("\r\n\r\n") {}
+                stream.write_all(response.as_bytes()).unwrap();
+                request.to_lowercase()
+            }).collect()
+        });
+
+        (url, handle)
+    }
+
+    /// Build an HTTP response that closes the connection
+    pub fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n", body.len());
+        for header in headers {
+            response.push_str(&format!("{header}\r\n"));
+        }
+        response + "\r\n" + body
+    }
+}
@@ -1,15 +1,138 @@
 use std::fmt;
 use std::fmt::Formatter;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use url::Url;
+use crate::download_manager::checksum::FileHash;
+use crate::plugin::plugin_errors;
 
-pub trait Downloader {
+/// The suffix added to a file while it's being downloaded, it's renamed to its final name once complete
+pub const PARTIAL_SUFFIX: &str = ".part";
+/// The suffix added to a partial file for the file storing what's needed to resume it
+pub const RESUME_INFO_SUFFIX: &str = ".toml";
+
+/// The file name used when a URL doesn't end with one
+const DEFAULT_FILE_NAME: &str = "download";
+
+/// Get the file name at the end of a URL's path, which downloads are saved as unless the downloader knows better
+pub fn url_file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_FILE_NAME)
+        .to_string()
+}
+
+/// Parse what to download, either a URL or the path of a local file which becomes a `file://` URL
+pub fn parse_source(source: &str) -> Result<Url, String> {
+    let path = Path::new(source);
+    if path.exists() {
+        let path = fs::canonicalize(path).map_err(|err| format!("Failed to resolve {source}: {err}"))?;
+        return Url::from_file_path(&path).map_err(|_| format!("{} can't be used as a file URL", path.display()))
+    }
+
+    Url::parse(source).map_err(|err| format!("{source} isn't a URL or an existing file: {err}"))
+}
+
+/// Get the path a file is written to while it's being downloaded
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(PARTIAL_SUFFIX);
+    PathBuf::from(partial)
+}
+
+/// Get the path of the file storing what's needed to resume a partial download
+pub fn resume_info_path(path: &Path) -> PathBuf {
+    let mut info = partial_path(path).into_os_string();
+    info.push(RESUME_INFO_SUFFIX);
+    PathBuf::from(info)
+}
+
+/// Remove a partly downloaded file and its resume information, if they exist
+pub fn remove_partial(path: &Path) {
+    let _ = fs::remove_file(partial_path(path));
+    let _ = fs::remove_file(resume_info_path(path));
+}
+
+/// Whether a downloader should keep downloading, returned by the progress callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadControl {
+    Continue,
+    /// Stop the download as soon as possible, the downloader should return without finishing the file
+    Stop,
+}
+
+pub trait Downloader: Send + Sync {
     fn name(&self) -> &'static str;
     fn supported_protocols(&self) -> Vec<&'static str>;
-    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64, u64, String)) -> Result<(), DownloadFailed>;
+
+    /// Download a file into a directory
+    ///
+    /// Downloaders should write to the file's [partial_path] and rename it once complete, so an interrupted
+    /// download can be resumed or removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `src`: The URL to download
+    /// * `dest`: The directory to download the file into
+    /// * `callback`: Called with the total size (0 if unknown), the bytes downloaded and the file name as the
+    ///   download progresses, the download stops if it returns [DownloadControl::Stop]
+    ///
+    /// returns: Result<DownloadedFile, DownloadFailed> The downloaded file, with the size and hash it should have
+    /// if the source provides them
+    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed>;
+}
+
+/// A file a downloader has finished downloading
+#[derive(Debug)]
+pub struct DownloadedFile {
+    pub path: PathBuf,
+    /// The size the source says the file should be, checked once downloaded
+    pub expected_size: Option<u64>,
+    /// The hash the source says the file should have, checked once downloaded
+    pub expected_hash: Option<FileHash>,
+    /// The game the file is a mod for, as the site it came from names it
+    pub game: Option<String>,
+    /// The ID of the mod on the site the file came from
+    pub mod_id: Option<u64>,
+    /// The ID of the file within the mod on the site it came from
+    pub file_id: Option<u64>,
+    /// The version of the mod the file is
+    pub version: Option<String>,
+}
+
+impl From<PathBuf> for DownloadedFile {
+    fn from(path: PathBuf) -> Self {
+        Self { path, expected_size: None, expected_hash: None, game: None, mod_id: None, file_id: None, version: None }
+    }
 }
 
 #[derive(Debug)]
 pub struct DownloadFailed {
-    pub msg: String
-}
\ No newline at end of file
+    pub msg: String,
+    /// Whether the failure may pass, such as a dropped connection, so the download is worth retrying
+    pub transient: bool,
+}
+
+impl DownloadFailed {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self { msg: msg.into(), transient: false }
+    }
+
+    /// A failure that may pass if the download is retried later
+    pub fn transient(msg: impl Into<String>) -> Self {
+        Self { msg: msg.into(), transient: true }
+    }
+}
+
+impl From<plugin_errors::Error> for DownloadFailed {
+    fn from(err: plugin_errors::Error) -> Self {
+        Self { msg: err.to_string(), transient: err.kind().is_transient() }
+    }
+}
+
+impl fmt::Display for DownloadFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
@@ -14,5 +14,46 @@ error_chain! {
         DownloadFailedToCreateDestFile
         DownloadErrorDuringDownload
         DownloadNoFileSize
+        DownloadStopped
+        DownloadBadStatus(status: u16) {
+            description("The server didn't return the file")
+            display("The server responded with status {}", status)
+        }
+
+        // Nexus Mods
+        NexusNoApiKey {
+            description("No Nexus Mods API key is configured")
+            display("No Nexus Mods API key is configured, set nexus_api_key in the config")
+        }
+        NexusInvalidApiUrl(url: String, reason: String) {
+            description("The Nexus Mods API URL is invalid")
+            display("The Nexus Mods API URL {} is invalid: {}", url, reason)
+        }
+        NexusInvalidLink(link: String, reason: String) {
+            description("The nxm link is invalid")
+            display("The nxm link {} is invalid, {}", link, reason)
+        }
+        NexusApiError(status: u16, message: String) {
+            description("The Nexus Mods API returned an error")
+            display("The Nexus Mods API responded with status {}: {}", status, message)
+        }
+        NexusBadResponse(reason: String) {
+            description("The Nexus Mods API returned an unexpected response")
+            display("The Nexus Mods API returned an unexpected response: {}", reason)
+        }
+        NexusNoDownloadLinks {
+            description("The Nexus Mods API returned no download links")
+            display("The Nexus Mods API returned no download links")
+        }
+    }
+}
+impl ErrorKind {
+    /// Whether the error may pass if the download is tried again, such as a lost connection or an overloaded server
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ErrorKind::DownloadFailedToConnect | ErrorKind::DownloadErrorDuringDownload => true,
+            ErrorKind::DownloadBadStatus(status) | ErrorKind::NexusApiError(status, _) => *status == 408 || *status == 429 || *status >= 500,
+            _ => false
+        }
     }
-}
\ No newline at end of file
+}
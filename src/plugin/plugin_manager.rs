@@ -2,12 +2,18 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
+use std::sync::Arc;
 use error_chain::bail;
 use libloading::{Library, Symbol};
+use url::Url;
 use crate::constants::plugins_dir;
 use crate::plugin::plugin_errors::ResultExt;
 use crate::plugin::{Plugin, plugin_errors};
 use crate::plugin::downloader::Downloader;
+use crate::manager_config::ManagerConfig;
+use crate::plugin::plugin_manager::built_in_plugins::http_downloader::HttpDownloader;
+use crate::plugin::plugin_manager::built_in_plugins::local_file_downloader::LocalFileDownloader;
+use crate::plugin::plugin_manager::built_in_plugins::nexus_downloader::NexusDownloader;
 use crate::plugin::plugin_manager::plugin_register::PluginRegister;
 
 pub mod plugin_register;
@@ -16,12 +22,15 @@ pub mod built_in_plugins;
 #[derive(Default)]
 pub struct PluginManager {
     plugins: HashMap<String, Lib>,
-    registry: PluginRegistry
+    registry: PluginRegistry,
+    /// The IDs of the downloaders to try first when several support a URL
+    downloader_preference: Vec<String>,
 }
 
 impl PluginManager {
-    pub fn register_plugins(&mut self) {
-        self.register_internal_plugins();
+    pub fn register_plugins(&mut self, config: &ManagerConfig) {
+        self.downloader_preference = config.downloader_preference.clone();
+        self.register_internal_plugins(config);
 
         let plugins_dir = plugins_dir();
         if plugins_dir.exists() {
@@ -29,7 +38,7 @@ impl PluginManager {
                 match entry {
                     Ok(entry) => {
                         unsafe {
-                            self.load_plugin(entry.file_name()).expect("Failed to load plugin}");
+                            self.load_plugin(entry.path()).expect("Failed to load plugin");
                         }
                     }
                     Err(err) => {
@@ -40,15 +49,21 @@ impl PluginManager {
         }
     }
 
-    fn register_internal_plugins(&mut self) {
-        // let http_downloader = Box::new(http_downloader::HttpDownloader {});
-        // self.registry.downloaders.register(http_downloader.as_ref().name(), http_downloader).expect("Failed to add http downloader");
+    fn register_internal_plugins(&mut self, config: &ManagerConfig) {
+        let http_downloader = Arc::new(HttpDownloader {});
+        self.registry.downloaders.register(http_downloader.name(), http_downloader).expect("Failed to add http downloader");
+
+        let local_file_downloader = Arc::new(LocalFileDownloader {});
+        self.registry.downloaders.register(local_file_downloader.name(), local_file_downloader).expect("Failed to add local file downloader");
+
+        let nexus_downloader = Arc::new(NexusDownloader::new(&config.nexus_api_url, config.nexus_api_key.clone()));
+        self.registry.downloaders.register(nexus_downloader.name(), nexus_downloader).expect("Failed to add nexus downloader");
     }
 
     unsafe fn load_plugin<P: AsRef<OsStr>>(&mut self, filename: P) -> plugin_errors::Result<()> {
         type PluginCreate = unsafe fn() -> *mut dyn Plugin;
 
-        let lib = Library::new(&filename.as_ref()).chain_err(|| "Unable to load plugin")?;
+        let lib = Library::new(filename.as_ref()).chain_err(|| "Unable to load plugin")?;
 
         let constructor: Symbol<PluginCreate> = lib.get(b"_plugin_create")
             .chain_err(|| "The `_plugin_create` symbol wasn't found")?;
@@ -57,20 +72,19 @@ impl PluginManager {
         let plugin = Box::from_raw(boxed_raw);
 
         let plugin_id = plugin.name().to_string();
-        match self.plugins.entry(plugin_id) {
+        match self.plugins.entry(plugin_id.clone()) {
             Entry::Occupied(_) => {
                 println!("Library {} tried to register a plugin with the id {plugin_id}, but a plugin \
                 with that Id already exists.", filename.as_ref().to_str().unwrap());
                 bail!(plugin_errors::ErrorKind::PluginIdExists)
             }
             Entry::Vacant(v) => {
-                v.insert(Lib::new(lib, plugin));
+                let lib = v.insert(Lib::new(lib, plugin));
+                dbg!("Loaded plugin: {}", lib.plugin.name());
+                lib.plugin.register_components(&mut self.registry);
             }
         }
 
-        dbg!("Loaded plugin: {}", plugin.name());
-        plugin.register_components(&mut self.registry);
-
         Ok(())
     }
 
@@ -85,6 +99,16 @@ impl PluginManager {
         }
     }
 
+    /// Get the downloaders that support a URL in the configured order to try them, URLs without a scheme are
+    /// treated as http
+    pub fn downloaders_for_url(&self, url: &Url) -> Vec<Arc<dyn Downloader>> {
+        let protocol = match url.scheme() {
+            "" => "http",
+            scheme => scheme
+        };
+        self.registry.downloaders.get_preferred_downloaders_for_protocol(protocol, &self.downloader_preference)
+    }
+
     pub fn libraries(&self) -> &HashMap<String, Lib> {
         &self.plugins
     }
@@ -114,12 +138,16 @@ impl Lib {
 
 #[derive(Default)]
 pub struct PluginRegistry {
-    downloaders: PluginRegister<Box<dyn Downloader>>
+    downloaders: PluginRegister<Arc<dyn Downloader>>
 
 }
 
 impl PluginRegistry {
-    pub fn downloaders(&mut self) -> &PluginRegister<Box<dyn Downloader>> {
+    pub fn downloaders(&self) -> &PluginRegister<Arc<dyn Downloader>> {
         &self.downloaders
     }
+
+    pub fn downloaders_mut(&mut self) -> &mut PluginRegister<Arc<dyn Downloader>> {
+        &mut self.downloaders
+    }
 }
\ No newline at end of file
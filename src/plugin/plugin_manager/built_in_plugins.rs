@@ -1 +1,3 @@
-pub mod http_downloader;
\ No newline at end of file
+pub mod http_downloader;
+pub mod local_file_downloader;
+pub mod nexus_downloader;
@@ -1,14 +1,8 @@
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
 use std::path::PathBuf;
-use std::thread;
-use std::thread::Thread;
-use std::time::Duration;
-use reqwest::blocking::Client;
 use url::Url;
-use crate::plugin::downloader::{Downloader, DownloadFailed};
-use crate::plugin::plugin_errors;
-
+use crate::download_manager::metadata::available_file_name;
+use crate::plugin::downloader::{DownloadControl, DownloadedFile, Downloader, DownloadFailed, url_file_name};
+use crate::plugin::plugin_util::download_http_file;
 
 pub struct HttpDownloader{}
 
@@ -21,9 +15,13 @@ impl Downloader for HttpDownloader {
         vec!["http", "https"]
     }
 
-    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64)) -> plugin_errors::Result<()> {
-        src.path_segments().unwrap().last().unwrap()
+    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed> {
+        let file_name = available_file_name(&dest, &url_file_name(src), src);
+        let path = dest.join(&file_name);
+
+        std::fs::create_dir_all(&dest).map_err(|err| DownloadFailed::new(format!("Failed to create the downloads directory: {err}")))?;
+        download_http_file(src.clone(), &path, &mut |total, progress| callback(total, progress, file_name.clone()))?;
 
-        Ok(())
+        Ok(path.into())
     }
-}
\ No newline at end of file
+}
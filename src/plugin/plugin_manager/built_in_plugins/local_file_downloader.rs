@@ -0,0 +1,148 @@
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use url::Url;
+use crate::download_manager::metadata::available_file_name;
+use crate::plugin::downloader::{DownloadControl, DownloadedFile, Downloader, DownloadFailed, partial_path, remove_partial};
+
+/// Imports archives that are already on disk, such as ones downloaded by hand, into the downloads directory
+///
+/// The archive is hard linked where possible so importing doesn't take any more space, and copied otherwise.
+pub struct LocalFileDownloader {}
+
+impl Downloader for LocalFileDownloader {
+    fn name(&self) -> &'static str {
+        "Local File Downloader"
+    }
+
+    fn supported_protocols(&self) -> Vec<&'static str> {
+        vec!["file"]
+    }
+
+    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed> {
+        let source = src.to_file_path().map_err(|_| DownloadFailed::new(format!("{src} isn't a local file path")))?;
+        let size = fs::metadata(&source)
+            .map_err(|err| DownloadFailed::new(format!("Failed to read {}: {err}", source.display())))?
+            .len();
+        let file_name = source.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| DownloadFailed::new(format!("{} isn't a file", source.display())))?;
+
+        // Already in the downloads directory, so there's nothing to import. Otherwise don't replace a different
+        // file with the same name
+        let in_place = dest.join(&file_name);
+        let imported = !is_same_file(&source, &in_place);
+        let file_name = if imported { available_file_name(&dest, &file_name, src) } else { file_name };
+        let path = dest.join(&file_name);
+
+        fs::create_dir_all(&dest).map_err(|err| DownloadFailed::new(format!("Failed to create the downloads directory: {err}")))?;
+        if callback(size, 0, file_name.clone()) == DownloadControl::Stop {
+            return Err(DownloadFailed::new("Stopped"))
+        }
+
+        if imported && fs::hard_link(&source, &path).is_err() {
+            copy_file(&source, &path, size, &mut |copied| callback(size, copied, file_name.clone()))?;
+        }
+        callback(size, size, file_name);
+
+        Ok(DownloadedFile {
+            expected_size: Some(size),
+            ..path.into()
+        })
+    }
+}
+
+/// Whether two paths are the same file, either the same path or hard links to each other
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) if a == b => true,
+        #[cfg(unix)]
+        _ => {
+            use std::os::unix::fs::MetadataExt;
+            match (fs::metadata(a), fs::metadata(b)) {
+                (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+                _ => false
+            }
+        }
+        #[cfg(not(unix))]
+        _ => false
+    }
+}
+
+/// Copy a file through its [partial_path], reporting the bytes copied after every chunk
+fn copy_file(source: &Path, dest: &Path, size: u64, progress_callback: &mut dyn FnMut(u64) -> DownloadControl) -> Result<(), DownloadFailed> {
+    let part_path = partial_path(dest);
+    let failed = |err: std::io::Error| {
+        remove_partial(dest);
+        DownloadFailed::new(format!("Failed to copy {}: {err}", source.display()))
+    };
+
+    let mut reader = File::open(source).map_err(failed)?;
+    let mut writer = File::create(&part_path).map_err(failed)?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut copied = 0;
+
+    loop {
+        let read = reader.read(&mut buffer).map_err(failed)?;
+        if read == 0 {
+            break
+        }
+        writer.write_all(&buffer[..read]).map_err(failed)?;
+
+        copied += read as u64;
+        if progress_callback(copied.min(size)) == DownloadControl::Stop {
+            return Err(DownloadFailed::new("Stopped"))
+        }
+    }
+
+    drop(writer);
+    fs::rename(&part_path, dest).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use url::Url;
+
+    use crate::plugin::downloader::{DownloadControl, Downloader, parse_source};
+    use crate::plugin::plugin_manager::built_in_plugins::local_file_downloader::LocalFileDownloader;
+
+    fn import(url: &Url, dest: &Path) -> PathBuf {
+        LocalFileDownloader {}.download(url, dest.to_path_buf(), &mut |_, _, _| DownloadControl::Continue).unwrap().path
+    }
+
+    #[test]
+    fn import_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloads = dir.path().join("downloads");
+        let source = dir.path().join("My Mod.7z");
+        fs::write(&source, "mod").unwrap();
+
+        // A plain path
+        let url = parse_source(source.to_str().unwrap()).unwrap();
+        assert_eq!(url.scheme(), "file");
+        let path = import(&url, &downloads);
+        assert_eq!(path, downloads.join("My Mod.7z"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mod");
+
+        // Already imported, so the same file is used
+        assert_eq!(import(&url, &downloads), path);
+
+        // A file URL to a file already in the downloads directory
+        let url = Url::from_file_path(&path).unwrap();
+        assert_eq!(import(&url, &downloads), path);
+        assert_eq!(fs::read_dir(&downloads).unwrap().count(), 1);
+
+        // A different file with the same name doesn't replace it
+        let other = dir.path().join("other/My Mod.7z");
+        fs::create_dir_all(other.parent().unwrap()).unwrap();
+        fs::write(&other, "other mod").unwrap();
+        let other_path = import(&Url::from_file_path(&other).unwrap(), &downloads);
+        assert_eq!(other_path, downloads.join("My Mod (1).7z"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mod");
+        assert_eq!(fs::read_to_string(&other_path).unwrap(), "other mod");
+    }
+}
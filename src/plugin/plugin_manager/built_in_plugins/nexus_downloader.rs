@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use error_chain::bail;
+use reqwest::blocking::{Client, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use url::Url;
+use crate::download_manager::metadata::available_file_name;
+use crate::plugin::downloader::{DownloadControl, DownloadedFile, Downloader, DownloadFailed};
+use crate::plugin::plugin_errors;
+use crate::plugin::plugin_util::download_http_file;
+
+const APPLICATION_NAME: &str = env!("CARGO_PKG_NAME");
+const APPLICATION_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The file an `nxm://<game>/mods/<mod id>/files/<file id>?key=..&expires=..` link points to
+///
+/// The key and expiry come from the website's "Download with manager" button, they let users without a premium
+/// account generate a download link.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NxmLink {
+    /// The game's domain name on Nexus Mods, such as `skyrimspecialedition`
+    pub game: String,
+    pub mod_id: u64,
+    pub file_id: u64,
+    pub key: Option<String>,
+    pub expires: Option<u64>,
+}
+
+impl NxmLink {
+    pub fn parse(url: &Url) -> plugin_errors::Result<Self> {
+        let invalid = |reason: &str| plugin_errors::ErrorKind::NexusInvalidLink(url.to_string(), reason.to_string());
+
+        if url.scheme() != "nxm" {
+            bail!(invalid("it isn't an nxm link"))
+        }
+        let game = url.host_str().filter(|game| !game.is_empty()).ok_or_else(|| invalid("it has no game"))?;
+
+        let segments: Vec<&str> = url.path_segments().map(|segments| segments.collect()).unwrap_or_default();
+        let (mod_id, file_id) = match segments.as_slice() {
+            ["mods", mod_id, "files", file_id] => (mod_id.parse().ok(), file_id.parse().ok()),
+            _ => bail!(invalid("it isn't a link to a mod file"))
+        };
+
+        let query = |name: &str| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.to_string());
+        Ok(Self {
+            game: game.to_lowercase(),
+            mod_id: mod_id.ok_or_else(|| invalid("the mod ID isn't a number"))?,
+            file_id: file_id.ok_or_else(|| invalid("the file ID isn't a number"))?,
+            key: query("key"),
+            expires: query("expires").and_then(|expires| expires.parse().ok()),
+        })
+    }
+}
+
+/// A mod file's details from the Nexus Mods API
+#[derive(Deserialize)]
+struct FileInfo {
+    file_name: String,
+    version: Option<String>,
+    size_in_bytes: Option<u64>,
+}
+
+/// A mirror a mod file can be downloaded from
+#[derive(Deserialize)]
+struct DownloadLink {
+    #[serde(rename = "URI")]
+    uri: String,
+}
+
+/// The body of an error response from the Nexus Mods API
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+/// Downloads `nxm://` links by resolving them into download links through the Nexus Mods API
+pub struct NexusDownloader {
+    api_url: String,
+    api_key: Option<String>,
+    client: Client,
+}
+
+impl NexusDownloader {
+    /// Create a Nexus downloader
+    ///
+    /// # Arguments
+    ///
+    /// * `api_url`: The base URL of the Nexus Mods API, such as [crate::manager_config::DEFAULT_NEXUS_API_URL]
+    /// * `api_key`: The user's personal API key, downloads fail until there is one
+    ///
+    /// returns: NexusDownloader
+    pub fn new(api_url: &str, api_key: Option<String>) -> Self {
+        // Without a trailing slash, joining endpoints would replace the last part of the path
+        let api_url = if api_url.ends_with('/') { api_url.to_string() } else { format!("{api_url}/") };
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+
+        Self { api_url, api_key: api_key.filter(|key| !key.trim().is_empty()), client }
+    }
+
+    /// Call an API endpoint, relative to the API's base URL
+    fn get<T: DeserializeOwned>(&self, endpoint: &str, query: &[(&str, String)]) -> plugin_errors::Result<T> {
+        let Some(api_key) = &self.api_key else {
+            bail!(plugin_errors::ErrorKind::NexusNoApiKey)
+        };
+        let url = Url::parse(&self.api_url).and_then(|api_url| api_url.join(endpoint))
+            .map_err(|err| plugin_errors::ErrorKind::NexusInvalidApiUrl(self.api_url.clone(), err.to_string()))?;
+
+        let res = self.client.get(url)
+            .query(query)
+            .header("apikey", api_key)
+            .header("Application-Name", APPLICATION_NAME)
+            .header("Application-Version", APPLICATION_VERSION)
+            .header("Accept", "application/json")
+            .send()
+            .or(Err(plugin_errors::ErrorKind::DownloadFailedToConnect))?;
+
+        if !res.status().is_success() {
+            bail!(api_error(res))
+        }
+        res.json().map_err(|err| plugin_errors::ErrorKind::NexusBadResponse(err.to_string()).into())
+    }
+
+    fn file_info(&self, link: &NxmLink) -> plugin_errors::Result<FileInfo> {
+        self.get(&format!("games/{}/mods/{}/files/{}.json", link.game, link.mod_id, link.file_id), &[])
+    }
+
+    /// Get the URL to download a file from, using the first mirror the API suggests
+    fn download_link(&self, link: &NxmLink) -> plugin_errors::Result<Url> {
+        let mut query = Vec::new();
+        if let Some(key) = &link.key {
+            query.push(("key", key.clone()));
+        }
+        if let Some(expires) = link.expires {
+            query.push(("expires", expires.to_string()));
+        }
+
+        let links: Vec<DownloadLink> = self.get(&format!("games/{}/mods/{}/files/{}/download_link.json", link.game, link.mod_id, link.file_id), &query)?;
+        let link = links.into_iter().next().ok_or(plugin_errors::ErrorKind::NexusNoDownloadLinks)?;
+        Url::parse(&link.uri).map_err(|err| plugin_errors::ErrorKind::NexusBadResponse(err.to_string()).into())
+    }
+}
+
+/// Describe an error response, using the API's message when it sends one
+fn api_error(res: Response) -> plugin_errors::ErrorKind {
+    let status = res.status().as_u16();
+    let message = res.json::<ApiError>().map(|err| err.message).unwrap_or_else(|_| match status {
+        401 => "The API key is invalid".to_string(),
+        403 => "The download key has expired, or downloading without one needs a premium account".to_string(),
+        404 => "The mod file doesn't exist".to_string(),
+        429 => "Too many requests, try again later".to_string(),
+        _ => "Unknown error".to_string()
+    });
+    plugin_errors::ErrorKind::NexusApiError(status, message)
+}
+
+impl Downloader for NexusDownloader {
+    fn name(&self) -> &'static str {
+        "Nexus Mods Downloader"
+    }
+
+    fn supported_protocols(&self) -> Vec<&'static str> {
+        vec!["nxm"]
+    }
+
+    fn download(&self, src: &Url, dest: PathBuf, callback: &mut dyn FnMut(u64, u64, String) -> DownloadControl) -> Result<DownloadedFile, DownloadFailed> {
+        let link = NxmLink::parse(src)?;
+        let file_info = self.file_info(&link)?;
+        let download_url = self.download_link(&link)?;
+
+        // The file name comes from the server, so only keep the name in case it's a path
+        let file_name = Path::new(&file_info.file_name).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| DownloadFailed::new(format!("Nexus Mods gave the invalid file name {}", file_info.file_name)))?;
+        let file_name = available_file_name(&dest, &file_name, src);
+        let path = dest.join(&file_name);
+
+        std::fs::create_dir_all(&dest).map_err(|err| DownloadFailed::new(format!("Failed to create the downloads directory: {err}")))?;
+        download_http_file(download_url, &path, &mut |total, progress| callback(total, progress, file_name.clone()))?;
+
+        Ok(DownloadedFile {
+            expected_size: file_info.size_in_bytes,
+            game: Some(link.game),
+            mod_id: Some(link.mod_id),
+            file_id: Some(link.file_id),
+            version: file_info.version,
+            ..path.into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::plugin::plugin_errors::ErrorKind;
+    use crate::plugin::plugin_manager::built_in_plugins::nexus_downloader::{NexusDownloader, NxmLink};
+    use crate::plugin::test_util::{response, serve};
+
+    fn link() -> NxmLink {
+        NxmLink::parse(&Url::parse("nxm://skyrimspecialedition/mods/266/files/1000?key=abc&expires=1700000000").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parse_nxm_link() {
+        let url = Url::parse("nxm://SkyrimSpecialEdition/mods/266/files/1000?key=abc&expires=1700000000&user_id=1").unwrap();
+        assert_eq!(NxmLink::parse(&url).unwrap(), NxmLink {
+            game: "skyrimspecialedition".to_string(),
+            mod_id: 266,
+            file_id: 1000,
+            key: Some("abc".to_string()),
+            expires: Some(1700000000),
+        });
+
+        let premium = NxmLink::parse(&Url::parse("nxm://fallout4/mods/1/files/2").unwrap()).unwrap();
+        assert_eq!(premium.key, None);
+
+        assert!(NxmLink::parse(&Url::parse("nxm://fallout4/mods/1").unwrap()).is_err());
+        assert!(NxmLink::parse(&Url::parse("nxm://fallout4/mods/a/files/2").unwrap()).is_err());
+    }
+
+    #[test]
+    fn api_requests() {
+        let (url, server) = serve(vec![
+            response("200 OK", &["Content-Type: application/json"], r#"{"file_name":"SkyUI.7z","version":"5.2","size_in_bytes":10}"#),
+            response("200 OK", &["Content-Type: application/json"], r#"[{"URI":"https://cdn.example.com/SkyUI.7z?token=1"},{"URI":"https://other.example.com/SkyUI.7z"}]"#),
+        ]);
+        let downloader = NexusDownloader::new(url.join("v1").unwrap().as_str(), Some("secret".to_string()));
+
+        let file_info = downloader.file_info(&link()).unwrap();
+        assert_eq!(file_info.file_name, "SkyUI.7z");
+        assert_eq!(file_info.version.as_deref(), Some("5.2"));
+        assert_eq!(file_info.size_in_bytes, Some(10));
+        assert_eq!(downloader.download_link(&link()).unwrap().as_str(), "https://cdn.example.com/SkyUI.7z?token=1");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("get /v1/games/skyrimspecialedition/mods/266/files/1000.json http/1.1\r\n"));
+        assert!(requests[0].contains("apikey: secret\r\n"));
+        assert!(requests[1].starts_with("get /v1/games/skyrimspecialedition/mods/266/files/1000/download_link.json?key=abc&expires=1700000000 "));
+    }
+
+    #[test]
+    fn api_errors() {
+        let (url, server) = serve(vec![
+            response("401 Unauthorized", &["Content-Type: application/json"], r#"{"message":"Please provide a valid API Key"}"#),
+            response("404 Not Found", &[], "Not found"),
+            response("429 Too Many Requests", &[], ""),
+        ]);
+        let downloader = NexusDownloader::new(url.as_str(), Some("wrong".to_string()));
+
+        let errors: Vec<ErrorKind> = (0..3).map(|_| downloader.file_info(&link()).err().unwrap().0).collect();
+        server.join().unwrap();
+        assert!(matches!(&errors[0], ErrorKind::NexusApiError(401, message) if message == "Please provide a valid API Key"));
+        assert!(matches!(&errors[1], ErrorKind::NexusApiError(404, message) if message == "The mod file doesn't exist"));
+        assert!(matches!(&errors[2], ErrorKind::NexusApiError(429, _)));
+        assert!(!errors[0].is_transient() && !errors[1].is_transient() && errors[2].is_transient());
+
+        let no_key = NexusDownloader::new(url.as_str(), Some(" ".to_string()));
+        assert!(matches!(no_key.file_info(&link()).err().unwrap().0, ErrorKind::NexusNoApiKey));
+    }
+}
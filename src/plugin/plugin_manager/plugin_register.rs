@@ -1,5 +1,6 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::sync::Arc;
 use error_chain::bail;
 use crate::plugin::downloader::Downloader;
 use crate::plugin::plugin_errors;
@@ -36,11 +37,38 @@ impl<T> PluginRegister<T> {
     }
 }
 
-impl PluginRegister<Box<dyn Downloader>> {
-    pub fn get_downloaders_for_protocol(&self, protocol: &str) -> Vec<&dyn Downloader> {
+impl PluginRegister<Arc<dyn Downloader>> {
+    pub fn get_downloaders_for_protocol(&self, protocol: &str) -> Vec<Arc<dyn Downloader>> {
         self.registry.values()
-            .filter(|downloader| downloader.as_ref().supported_protocols().contains(&protocol))
-            .map(|downloader| downloader.as_ref())
+            .filter(|downloader| downloader.supported_protocols().contains(&protocol))
+            .cloned()
             .collect()
     }
+
+    /// Get the downloaders that support a protocol in the order to try them
+    ///
+    /// # Arguments
+    ///
+    /// * `protocol`: The protocol the downloaders must support
+    /// * `preference`: The IDs of the downloaders to try first, most preferred first. The rest come after them in
+    ///   order of ID
+    ///
+    /// returns: Vec<Arc<dyn Downloader>>
+    pub fn get_preferred_downloaders_for_protocol(&self, protocol: &str, preference: &[String]) -> Vec<Arc<dyn Downloader>> {
+        let mut downloaders: Vec<(&String, &Arc<dyn Downloader>)> = self.registry.iter()
+            .filter(|(_, downloader)| downloader.supported_protocols().contains(&protocol))
+            .collect();
+        downloaders.sort_by_key(|(id, _)| (preference.iter().position(|preferred| preferred == *id).unwrap_or(usize::MAX), *id));
+        downloaders.into_iter().map(|(_, downloader)| downloader.clone()).collect()
+    }
+
+    /// Get every protocol a registered downloader supports, sorted and without duplicates
+    pub fn supported_protocols(&self) -> Vec<&'static str> {
+        let mut protocols: Vec<&'static str> = self.registry.values()
+            .flat_map(|downloader| downloader.supported_protocols())
+            .collect();
+        protocols.sort();
+        protocols.dedup();
+        protocols
+    }
 }
\ No newline at end of file
@@ -1,11 +1,17 @@
+use std::fs;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::path::Path;
 use std::time::Duration;
 use error_chain::bail;
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use url::Url;
+use crate::plugin::downloader::{DownloadControl, partial_path, remove_partial, resume_info_path};
 use crate::plugin::plugin_errors;
+use crate::plugin::plugin_errors::ResultExt;
 
 pub fn get_http_file_size(url: Url) -> plugin_errors::Result<u64> {
     let client = Client::builder()
@@ -13,40 +19,134 @@ pub fn get_http_file_size(url: Url) -> plugin_errors::Result<u64> {
         .build()
         .unwrap();
 
-    let mut res = client.head(url.clone())
+    let res = client.head(url.clone())
         .send()
         .or(Err(plugin_errors::ErrorKind::DownloadFailedToConnect))?;
 
-    res.content_length().ok_or(Err(plugin_errors::ErrorKind::DownloadNoFileSize)?)
+    res.content_length().ok_or_else(|| plugin_errors::ErrorKind::DownloadNoFileSize.into())
 }
 
-pub fn download_http_file(url: Url, dest: &Path, progress_callback: &mut dyn FnMut(u64)) -> plugin_errors::Result<()> {
+/// What's needed to resume a partial HTTP download, stored next to the partial file
+///
+/// The URL is only kept for reference: download links such as Nexus Mods' CDN links change with every request,
+/// so whether the partial file can be resumed is decided by the validators and the size of the whole file.
+#[derive(Serialize, Deserialize)]
+struct ResumeInfo {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// The size of the whole file, if the server sent it
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+impl ResumeInfo {
+    fn from_response(url: &Url, res: &Response, size: Option<u64>) -> Self {
+        let header = |name| res.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+        Self { url: url.to_string(), etag: header(ETAG), last_modified: header(LAST_MODIFIED), size }
+    }
+
+    /// The validator to send in `If-Range`, weak ETags can't be used for ranges
+    fn validator(&self) -> Option<&str> {
+        self.etag.as_deref().filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        toml::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    fn save(&self, path: &Path) {
+        if let Ok(content) = toml::to_string(self) {
+            let _ = fs::write(path, content);
+        }
+    }
+}
+
+/// Download a file over HTTP, replacing the destination file
+///
+/// The file is written to its [partial_path] and renamed once complete. If a partial file from an earlier
+/// attempt exists, the download carries on from its end with a range request, as long as the server still has
+/// the same version of the file by its ETag or Last-Modified date. Otherwise, or if the server sends a range that
+/// doesn't carry on from the partial file, the download starts over.
+///
+/// # Arguments
+///
+/// * `url`: The URL to download
+/// * `dest`: The path to write the file to
+/// * `progress_callback`: Called with the total size (0 if unknown) and the bytes downloaded after every chunk,
+///   the download stops with `DownloadStopped` if it returns [DownloadControl::Stop]
+///
+/// returns: Result<(), Error>
+pub fn download_http_file(url: Url, dest: &Path, progress_callback: &mut dyn FnMut(u64, u64) -> DownloadControl) -> plugin_errors::Result<()> {
+    // The timeout covers the whole body, so only limit connecting
     let client = Client::builder()
-        .timeout(Duration::from_secs(5))
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(None)
         .build()
         .unwrap();
 
-    let mut res = client.get(url.clone())
-        .send()
+    let part_path = partial_path(dest);
+    let info_path = resume_info_path(dest);
+    let existing_size = fs::metadata(&part_path).map(|metadata| metadata.len()).unwrap_or(0);
+    let resume_info = ResumeInfo::load(&info_path);
+
+    let mut request = client.get(url.clone());
+    if let Some(validator) = resume_info.as_ref().and_then(|info| info.validator()).filter(|_| existing_size > 0) {
+        request = request
+            .header(RANGE, format!("bytes={existing_size}-"))
+            .header(IF_RANGE, validator);
+    }
+
+    let mut res = request.send()
         .or(Err(plugin_errors::ErrorKind::DownloadFailedToConnect))?;
 
+    // The server only sends part of the file if the range was accepted, otherwise it's the whole file again
+    let expected_size = resume_info.as_ref().and_then(|info| info.size);
+    let resume_from = match res.status() {
+        StatusCode::PARTIAL_CONTENT => match content_range(&res) {
+            Some((start, size)) if start == existing_size && (expected_size.is_none() || size == expected_size) =>
+                existing_size,
+            // The range doesn't carry on from the partial file, so start over without it
+            _ if existing_size > 0 => {
+                remove_partial(dest);
+                return download_http_file(url, dest, progress_callback)
+            }
+            _ => bail!(plugin_errors::ErrorKind::DownloadBadStatus(res.status().as_u16()))
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if existing_size > 0 => {
+            remove_partial(dest);
+            return download_http_file(url, dest, progress_callback)
+        }
+        status if status.is_success() => 0,
+        status => bail!(plugin_errors::ErrorKind::DownloadBadStatus(status.as_u16()))
+    };
+    let total_size = res.content_length().map_or(0, |length| length + resume_from);
+
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
-        .open(dest)
+        .truncate(resume_from == 0)
+        .append(resume_from > 0)
+        .open(&part_path)
         .or(Err(plugin_errors::ErrorKind::DownloadFailedToCreateDestFile))?;
+    ResumeInfo::from_response(&url, &res, Some(total_size).filter(|size| *size > 0)).save(&info_path);
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut current_size: u64 = resume_from;
+    if progress_callback(total_size, current_size) == DownloadControl::Stop {
+        bail!(plugin_errors::ErrorKind::DownloadStopped)
+    }
 
-    let mut buffer: [u8; 1024] = [0; 1024];
-    let mut current_size: u64 = 0;
     loop {
         match res.read(&mut buffer) {
+            Ok(0) => break,
             Ok(size) => {
-                if size > 0 {
-                    file.write_all(&buffer[0..size]).unwrap();
-                    current_size += size as u64;
-                    progress_callback(current_size);
-                } else {
-                    break;
+                file.write_all(&buffer[0..size])
+                    .chain_err(|| plugin_errors::ErrorKind::DownloadErrorDuringDownload)?;
+                current_size += size as u64;
+                if progress_callback(total_size, current_size) == DownloadControl::Stop {
+                    bail!(plugin_errors::ErrorKind::DownloadStopped)
                 }
             }
             Err(err) => {
@@ -55,5 +155,109 @@ pub fn download_http_file(url: Url, dest: &Path, progress_callback: &mut dyn FnM
         };
     }
 
+    // A connection closed early can look like the end of the body
+    if total_size > 0 && current_size != total_size {
+        bail!(plugin_errors::ErrorKind::DownloadErrorDuringDownload)
+    }
+
+    drop(file);
+    fs::rename(&part_path, dest).chain_err(|| plugin_errors::ErrorKind::DownloadFailedToCreateDestFile)?;
+    let _ = fs::remove_file(&info_path);
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Get the first byte of the range and the size of the whole file, if known, in a
+/// `Content-Range: bytes start-end/total` header
+fn content_range(res: &Response) -> Option<(u64, Option<u64>)> {
+    let range = res.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, rest) = range.strip_prefix("bytes ")?.split_once('-')?;
+    let size = rest.split_once('/').and_then(|(_, size)| size.trim().parse().ok());
+    Some((start.trim().parse().ok()?, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread::JoinHandle;
+
+    use url::Url;
+
+    use crate::plugin::downloader::{DownloadControl, partial_path, resume_info_path};
+    use crate::plugin::plugin_util::download_http_file;
+    use crate::plugin::test_util::{response, serve};
+
+    /// Write a partial download of the first 4 bytes with resume information from a different link
+    fn partial(dest: &std::path::Path) {
+        fs::write(partial_path(dest), "0123").unwrap();
+        fs::write(resume_info_path(dest), "url = \"http://cdn.example.com/mod.zip?token=old\"\netag = \"\\\"v1\\\"\"\nsize = 10\n").unwrap();
+    }
+
+    fn serve_file(responses: Vec<String>) -> (Url, JoinHandle<Vec<String>>) {
+        let (url, server) = serve(responses);
+        (url.join("mod.zip").unwrap(), server)
+    }
+
+    fn download(url: Url, dest: &std::path::Path) {
+        download_http_file(url, dest, &mut |_, _| DownloadControl::Continue).unwrap();
+        assert!(!partial_path(dest).exists());
+        assert!(!resume_info_path(dest).exists());
+    }
+
+    #[test]
+    fn resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("mod.zip");
+        partial(&dest);
+
+        let (url, server) = serve_file(vec![response("206 Partial Content", &["Content-Range: bytes 4-9/10", "ETag: \"v1\""], "456789")]);
+        download(url, &dest);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("range: bytes=4-\r\n"));
+        assert!(requests[0].contains("if-range: \"v1\"\r\n"));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn restart_when_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("mod.zip");
+
+        // The validator no longer matches, so the server sends the whole new file
+        partial(&dest);
+        let (url, server) = serve_file(vec![response("200 OK", &["ETag: \"v2\""], "abcdefghij")]);
+        download(url, &dest);
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "abcdefghij");
+
+        // A range that doesn't carry on from the partial file is thrown away and the download starts over
+        partial(&dest);
+        let (url, server) = serve_file(vec![
+            response("206 Partial Content", &["Content-Range: bytes 0-9/10", "ETag: \"v1\""], "abcdefghij"),
+            response("200 OK", &["ETag: \"v1\""], "klmnopqrst"),
+        ]);
+        download(url, &dest);
+        let requests = server.join().unwrap();
+        assert!(!requests[1].contains("range:"));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "klmnopqrst");
+    }
+
+    #[test]
+    fn range_not_satisfiable() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("mod.zip");
+        partial(&dest);
+
+        let (url, server) = serve_file(vec![
+            response("416 Range Not Satisfiable", &["Content-Range: bytes */3"], ""),
+            response("200 OK", &[], "abc"),
+        ]);
+        download(url, &dest);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("range: bytes=4-"));
+        assert!(!requests[1].contains("range:"));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "abc");
+    }
+}
@@ -0,0 +1,367 @@
+use std::{env, fs, io};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use directories::BaseDirs;
+use crate::errors;
+use crate::errors::ErrorKind;
+
+/// The name of the desktop entry the manager registers as a URL handler
+pub const DESKTOP_FILE_NAME: &str = "dat-mod-manager.desktop";
+/// The file desktop environments look up default applications in
+const MIME_APPS_FILE: &str = "mimeapps.list";
+const DEFAULT_APPLICATIONS: &str = "Default Applications";
+const ADDED_ASSOCIATIONS: &str = "Added Associations";
+
+/// Schemes the manager can download but shouldn't take over from the browser or file manager
+const EXCLUDED_SCHEMES: [&str; 3] = ["http", "https", "file"];
+
+/// Get the schemes worth registering the manager as the handler for, out of the ones downloaders support
+pub fn handler_schemes(protocols: &[&str]) -> Vec<String> {
+    protocols.iter()
+        .filter(|protocol| !EXCLUDED_SCHEMES.contains(protocol))
+        .map(|protocol| protocol.to_string())
+        .collect()
+}
+
+/// Get the MIME type browsers look up the handler of a URL scheme by
+pub fn scheme_mime_type(scheme: &str) -> String {
+    format!("x-scheme-handler/{scheme}")
+}
+
+/// Get the directory user desktop entries are installed to, `$XDG_DATA_HOME/applications`
+pub fn applications_dir() -> errors::Result<PathBuf> {
+    let base_dirs = BaseDirs::new().ok_or(ErrorKind::NoHomeDirectory)?;
+    Ok(base_dirs.data_dir().join("applications"))
+}
+
+/// Get the `mimeapps.list` file user associations are written to, `$XDG_CONFIG_HOME/mimeapps.list`
+fn user_mime_apps_path() -> errors::Result<PathBuf> {
+    let base_dirs = BaseDirs::new().ok_or(ErrorKind::NoHomeDirectory)?;
+    Ok(base_dirs.config_dir().join(MIME_APPS_FILE))
+}
+
+/// Get the `mimeapps.list` files in the order the default application is looked up in, most important first
+fn mime_apps_paths() -> errors::Result<Vec<PathBuf>> {
+    let base_dirs = BaseDirs::new().ok_or(ErrorKind::NoHomeDirectory)?;
+    let dirs_var = |name: &str, default: &str| -> Vec<PathBuf> {
+        env::var(name).ok().filter(|dirs| !dirs.is_empty()).unwrap_or_else(|| default.to_string())
+            .split(':')
+            .map(PathBuf::from)
+            .collect()
+    };
+
+    let mut paths = vec![base_dirs.config_dir().join(MIME_APPS_FILE)];
+    paths.extend(dirs_var("XDG_CONFIG_DIRS", "/etc/xdg").into_iter().map(|dir| dir.join(MIME_APPS_FILE)));
+    paths.push(base_dirs.data_dir().join("applications").join(MIME_APPS_FILE));
+    paths.extend(dirs_var("XDG_DATA_DIRS", "/usr/local/share:/usr/share").into_iter()
+        .map(|dir| dir.join("applications").join(MIME_APPS_FILE)));
+    Ok(paths)
+}
+
+/// Build the desktop entry that runs the download command for the schemes' URLs
+fn desktop_entry(executable: &Path, schemes: &[String]) -> String {
+    // Quote the executable in case its path has spaces, escaping the characters that are special inside quotes.
+    // String escapes are undone before quoting, so the backslashes are escaped again, then % is escaped from field
+    // codes
+    let executable = executable.to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('`', "\\`")
+        .replace('$', "\\$")
+        .replace('\\', "\\\\")
+        .replace('%', "%%");
+    let mime_types: String = schemes.iter().map(|scheme| format!("{};", scheme_mime_type(scheme))).collect();
+
+    format!("[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Dat Mod Manager\n\
+             Comment=Download mods with Dat Mod Manager\n\
+             Exec=\"{executable}\" download --no-prompt %u\n\
+             Icon=dat-mod-manager\n\
+             Terminal=false\n\
+             NoDisplay=true\n\
+             MimeType={mime_types}\n")
+}
+
+/// Get the URL schemes a desktop entry says it handles
+fn desktop_entry_schemes(contents: &str) -> Vec<String> {
+    get_entries(contents, "Desktop Entry", "MimeType").iter()
+        .filter_map(|mime_type| mime_type.strip_prefix(&scheme_mime_type("")))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Get the desktop entries a key in a section of a `mimeapps.list` file lists
+fn get_entries(contents: &str, section: &str, key: &str) -> Vec<String> {
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            in_section = name == section;
+        } else if in_section {
+            if let Some((line_key, value)) = line.split_once('=') {
+                if line_key.trim() == key {
+                    return value.split(';').map(str::trim).filter(|entry| !entry.is_empty()).map(str::to_string).collect()
+                }
+            }
+        }
+    }
+    Vec::new()
+}
+
+/// Change the desktop entries a key in a section of a `mimeapps.list` file lists, keeping the rest of the file
+///
+/// The key is removed if there are no entries left, and the section is added if it doesn't exist yet.
+fn update_entries(contents: &str, section: &str, key: &str, update: impl FnOnce(&mut Vec<String>)) -> String {
+    let mut entries = get_entries(contents, section, key);
+    update(&mut entries);
+    let new_line = (!entries.is_empty()).then(|| format!("{key}={};", entries.join(";")));
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_section = false;
+    let mut section_found = false;
+    let mut written = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            // Add the key at the end of the section if it wasn't already in it
+            if in_section && !written {
+                insert_before_blank_lines(&mut lines, new_line.clone());
+                written = true;
+            }
+            in_section = name == section;
+            section_found |= in_section;
+        } else if in_section && trimmed.split_once('=').is_some_and(|(line_key, _)| line_key.trim() == key) {
+            if let Some(new_line) = new_line.clone().filter(|_| !written) {
+                lines.push(new_line);
+            }
+            written = true;
+            continue
+        }
+        lines.push(line.to_string());
+    }
+
+    if in_section && !written {
+        insert_before_blank_lines(&mut lines, new_line);
+    } else if !section_found {
+        if let Some(new_line) = new_line {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{section}]"));
+            lines.push(new_line);
+        }
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Add a line after the last non-blank line, so it stays in the section before the gap to the next one
+fn insert_before_blank_lines(lines: &mut Vec<String>, line: Option<String>) {
+    if let Some(line) = line {
+        let index = lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |index| index + 1);
+        lines.insert(index, line);
+    }
+}
+
+fn read_or_empty(path: &Path) -> errors::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into())
+    }
+}
+
+/// Tell the desktop about the changed desktop entry, the entry still works without it but may be slower to show up
+fn update_desktop_database(applications_dir: &Path) {
+    let _ = Command::new("update-desktop-database").arg(applications_dir).output();
+}
+
+/// Install the desktop entry and make the manager the default handler for URL schemes
+///
+/// The schemes are added to any the desktop entry already handles.
+///
+/// # Arguments
+///
+/// * `executable`: The manager's executable the desktop entry runs
+/// * `schemes`: The URL schemes to handle, such as `nxm`
+///
+/// returns: Result<(), Error>
+pub fn register(executable: &Path, schemes: &[String]) -> errors::Result<()> {
+    let applications_dir = applications_dir()?;
+    register_in(&applications_dir, &user_mime_apps_path()?, executable, schemes)?;
+    update_desktop_database(&applications_dir);
+    Ok(())
+}
+
+fn register_in(applications_dir: &Path, mime_apps_path: &Path, executable: &Path, schemes: &[String]) -> errors::Result<()> {
+    let desktop_file_path = applications_dir.join(DESKTOP_FILE_NAME);
+    let mut handled = desktop_entry_schemes(&read_or_empty(&desktop_file_path)?);
+    for scheme in schemes {
+        if !handled.contains(scheme) {
+            handled.push(scheme.clone());
+        }
+    }
+    fs::create_dir_all(applications_dir)?;
+    fs::write(&desktop_file_path, desktop_entry(executable, &handled))?;
+
+    let mut contents = read_or_empty(mime_apps_path)?;
+    for scheme in schemes {
+        let mime_type = scheme_mime_type(scheme);
+        // Put the manager first, keeping the handlers it replaces as fallbacks
+        contents = update_entries(&contents, DEFAULT_APPLICATIONS, &mime_type, |entries| {
+            entries.retain(|entry| entry != DESKTOP_FILE_NAME);
+            entries.insert(0, DESKTOP_FILE_NAME.to_string());
+        });
+        contents = update_entries(&contents, ADDED_ASSOCIATIONS, &mime_type, |entries| {
+            if !entries.iter().any(|entry| entry == DESKTOP_FILE_NAME) {
+                entries.push(DESKTOP_FILE_NAME.to_string());
+            }
+        });
+    }
+    if let Some(parent) = mime_apps_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(mime_apps_path, contents)?;
+    Ok(())
+}
+
+/// Remove the manager's MIME associations for URL schemes, leaving other handlers in place
+///
+/// The desktop entry stops listing the schemes, and is removed once it doesn't handle any. Associations in the
+/// `mimeapps.list` older versions wrote to `$XDG_DATA_HOME/applications` are removed too.
+///
+/// # Arguments
+///
+/// * `schemes`: The URL schemes to stop handling
+///
+/// returns: Result<bool, Error> Whether anything was registered to remove
+pub fn unregister(schemes: &[String]) -> errors::Result<bool> {
+    let applications_dir = applications_dir()?;
+    let mime_apps_paths = [user_mime_apps_path()?, applications_dir.join(MIME_APPS_FILE)];
+    let removed = unregister_in(&applications_dir, &mime_apps_paths, schemes)?;
+    update_desktop_database(&applications_dir);
+    Ok(removed)
+}
+
+fn unregister_in(applications_dir: &Path, mime_apps_paths: &[PathBuf], schemes: &[String]) -> errors::Result<bool> {
+    let mut removed = false;
+    let mut still_referenced = false;
+    for mime_apps_path in mime_apps_paths {
+        let original = read_or_empty(mime_apps_path)?;
+        let mut contents = original.clone();
+        for scheme in schemes {
+            for section in [DEFAULT_APPLICATIONS, ADDED_ASSOCIATIONS] {
+                contents = update_entries(&contents, section, &scheme_mime_type(scheme), |entries| {
+                    entries.retain(|entry| entry != DESKTOP_FILE_NAME);
+                });
+            }
+        }
+        if contents.trim() != original.trim() {
+            fs::write(mime_apps_path, &contents)?;
+            removed = true;
+        }
+        still_referenced |= contents.contains(DESKTOP_FILE_NAME);
+    }
+
+    let desktop_file_path = applications_dir.join(DESKTOP_FILE_NAME);
+    let desktop_file = match fs::read_to_string(&desktop_file_path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(removed),
+        Err(err) => return Err(err.into())
+    };
+    let handled = desktop_entry_schemes(&desktop_file);
+    let remaining: Vec<String> = handled.iter().filter(|scheme| !schemes.contains(scheme)).cloned().collect();
+    if remaining.is_empty() && !still_referenced {
+        fs::remove_file(&desktop_file_path)?;
+        removed = true;
+    } else if remaining.len() != handled.len() {
+        let mime_types: Vec<String> = remaining.iter().map(|scheme| scheme_mime_type(scheme)).collect();
+        let contents = update_entries(&desktop_file, "Desktop Entry", "MimeType", |entries| *entries = mime_types);
+        fs::write(&desktop_file_path, contents)?;
+        removed = true;
+    }
+
+    Ok(removed)
+}
+
+/// Get the desktop entry that's the default handler for a URL scheme, as the first `mimeapps.list` to set one says
+pub fn current_handler(scheme: &str) -> errors::Result<Option<String>> {
+    let mime_type = scheme_mime_type(scheme);
+    for path in mime_apps_paths()? {
+        let contents = read_or_empty(&path)?;
+        if let Some(entry) = get_entries(&contents, DEFAULT_APPLICATIONS, &mime_type).into_iter().next() {
+            return Ok(Some(entry))
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::url_handler::{DESKTOP_FILE_NAME, desktop_entry, desktop_entry_schemes, get_entries, register_in, unregister_in, update_entries};
+
+    #[test]
+    fn update_mime_apps() {
+        let contents = "[Default Applications]\ntext/plain=editor.desktop;\n\n[Added Associations]\ntext/plain=editor.desktop;\n";
+
+        let added = update_entries(contents, "Default Applications", "x-scheme-handler/nxm", |entries| {
+            entries.insert(0, "dat-mod-manager.desktop".to_string())
+        });
+        assert_eq!(added, "[Default Applications]\ntext/plain=editor.desktop;\nx-scheme-handler/nxm=dat-mod-manager.desktop;\n\n\
+                           [Added Associations]\ntext/plain=editor.desktop;\n");
+        assert_eq!(get_entries(&added, "Default Applications", "x-scheme-handler/nxm"), vec!["dat-mod-manager.desktop"]);
+        assert!(get_entries(&added, "Added Associations", "x-scheme-handler/nxm").is_empty());
+
+        let removed = update_entries(&added, "Default Applications", "x-scheme-handler/nxm", |entries| entries.clear());
+        assert_eq!(removed, contents);
+
+        let new_section = update_entries("", "Default Applications", "x-scheme-handler/nxm", |entries| {
+            entries.push("dat-mod-manager.desktop".to_string())
+        });
+        assert_eq!(new_section, "[Default Applications]\nx-scheme-handler/nxm=dat-mod-manager.desktop;\n");
+    }
+
+    #[test]
+    fn desktop_entry_exec() {
+        let entry = desktop_entry(Path::new(r#"/opt/a b\c$"d`e%/dat-mod-manager"#), &["nxm".to_string()]);
+        assert!(entry.contains(r#"Exec="/opt/a b\\\\c\\$\\"d\\`e%%/dat-mod-manager" download --no-prompt %u"#));
+        assert_eq!(desktop_entry_schemes(&entry), ["nxm"]);
+    }
+
+    #[test]
+    fn register_and_unregister() {
+        let dir = tempfile::tempdir().unwrap();
+        let applications_dir = dir.path().join("data/applications");
+        let mime_apps_path = dir.path().join("config/mimeapps.list");
+        let legacy_path = applications_dir.join("mimeapps.list");
+        let desktop_file_path = applications_dir.join(DESKTOP_FILE_NAME);
+        let executable = Path::new("/usr/bin/dat-mod-manager");
+
+        register_in(&applications_dir, &mime_apps_path, executable, &["nxm".to_string()]).unwrap();
+        register_in(&applications_dir, &mime_apps_path, executable, &["modl".to_string()]).unwrap();
+        assert_eq!(desktop_entry_schemes(&fs::read_to_string(&desktop_file_path).unwrap()), ["nxm", "modl"]);
+        let contents = fs::read_to_string(&mime_apps_path).unwrap();
+        assert_eq!(get_entries(&contents, "Default Applications", "x-scheme-handler/modl"), [DESKTOP_FILE_NAME]);
+        assert_eq!(get_entries(&contents, "Added Associations", "x-scheme-handler/nxm"), [DESKTOP_FILE_NAME]);
+
+        // Associations written by older versions are cleaned up too
+        fs::write(&legacy_path, "[Default Applications]\nx-scheme-handler/nxm=dat-mod-manager.desktop;other.desktop;\n").unwrap();
+        let paths = [mime_apps_path.clone(), legacy_path.clone()];
+
+        // The desktop entry stays while it still handles a scheme
+        assert!(unregister_in(&applications_dir, &paths, &["nxm".to_string()]).unwrap());
+        assert_eq!(desktop_entry_schemes(&fs::read_to_string(&desktop_file_path).unwrap()), ["modl"]);
+        assert!(!fs::read_to_string(&mime_apps_path).unwrap().contains("x-scheme-handler/nxm"));
+        assert_eq!(fs::read_to_string(&legacy_path).unwrap(), "[Default Applications]\nx-scheme-handler/nxm=other.desktop;\n");
+
+        assert!(unregister_in(&applications_dir, &paths, &["modl".to_string()]).unwrap());
+        assert!(!desktop_file_path.exists());
+        assert!(!unregister_in(&applications_dir, &paths, &["modl".to_string()]).unwrap());
+    }
+}
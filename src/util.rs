@@ -1,5 +1,8 @@
 use std::fs;
-use std::path::{PathBuf};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
 
 use crate::constants;
 
@@ -30,4 +33,61 @@ where F: FnMut(u32, u32, &str) {
             fs::remove_file(path).expect("Failed to remove file");
         }
     }
-}
\ No newline at end of file
+}
+
+/// Resolve a relative path against a directory the way a case-insensitive filesystem would
+///
+/// Each component that already exists under a different case is replaced with the existing name,
+/// components that don't exist are kept as given.
+///
+/// # Arguments
+///
+/// * `base`: The directory the path is relative to
+/// * `relative`: The relative path to resolve
+///
+/// returns: PathBuf The resolved path, including the base
+pub fn resolve_case_insensitive(base: &Path, relative: &Path) -> PathBuf {
+    let mut resolved = base.to_path_buf();
+
+    for component in relative.components() {
+        let component = component.as_os_str();
+        let candidate = resolved.join(component);
+        if candidate.exists() {
+            resolved = candidate;
+            continue;
+        }
+
+        let folded = component.to_string_lossy().to_lowercase();
+        let existing = fs::read_dir(&resolved).ok()
+            .and_then(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == folded));
+
+        resolved = match existing {
+            Some(entry) => entry.path(),
+            None => candidate
+        };
+    }
+
+    resolved
+}
+
+/// Move a file, copying it when the destination is on a different filesystem
+pub fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(src, dest) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(src, dest)?;
+            fs::remove_file(src)
+        }
+        result => result
+    }
+}
+
+/// Get the SHA-256 hash of a file as a lowercase hex string
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}